@@ -1,4 +1,10 @@
-use std::{collections::HashMap, default::Default, env, env::current_dir, fs, path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    default::Default,
+    env,
+    env::current_dir,
+    fs, path,
+};
 
 use convert_case::{Case, Casing};
 use prettyplease::unparse;
@@ -14,8 +20,9 @@ use syn::{
     parse_quote,
     punctuated::Punctuated,
     token::{Comma, PathSep, Pub},
-    Arm, Block, Expr, ExprMatch, Field, FieldMutability, FieldValue, File, Ident, ImplItem, ImplItemFn, Item, ItemImpl, ItemMod, ItemTrait,
-    Lit, Path, PathArguments, PathSegment, Stmt, TraitItem, TraitItemFn, Type, TypePath, Variant, Visibility,
+    Arm, Attribute, Block, Expr, ExprMatch, Field, FieldMutability, FieldValue, File, FnArg, GenericArgument, Ident, ImplItem, ImplItemFn,
+    Item, ItemImpl, ItemMod, ItemStruct, ItemTrait, Lit, PatType, Path, PathArguments, PathSegment, ReturnType, Stmt, TraitItem,
+    TraitItemFn, Type, TypeArray, TypeParamBound, TypePath, Variant, Visibility,
 };
 
 fn main() -> Result<(), PyErr> {
@@ -59,6 +66,36 @@ struct TfDevice<'a> {
     packets: Vec<&'a PyAny>,
 }
 
+/// Constants of all groups visible to a device, by group name as written in the config.
+type ConstantGroupLookup<'a> = HashMap<String, Vec<(&'a str, &'a PyAny)>>;
+
+/// A function or callback as seen from the device, to generate the server side of the protocol.
+#[derive(Clone)]
+struct ServerPacket {
+    function_id: u8,
+    /// Name of the handler function or callback emitter.
+    name: Ident,
+    doc: String,
+    callback: bool,
+    /// Type and size of the request, if any.
+    request: Option<(Type, usize)>,
+    /// Type and size of the response or callback payload, if any.
+    response: Option<(Type, usize)>,
+}
+
+/// Everything collected from the common configs for one feature.
+#[derive(Default)]
+struct FeatureData<'a> {
+    items: Vec<Item>,
+    packets: Vec<(u8, PacketEntry<'a>, Option<SinceFirmware<'a>>)>,
+    constant_groups: ConstantGroupLookup<'a>,
+    constant_group_metadata: Vec<Expr>,
+    /// Initial response expected flags by function id.
+    response_expected: Vec<(u8, Expr)>,
+    /// Functions without packet, implemented on the client side.
+    virtual_functions: Vec<ImplItemFn>,
+}
+
 #[derive(Debug, FromPyObject)]
 struct ConstantGroupEntry<'a> {
     #[pyo3(item)]
@@ -79,8 +116,31 @@ struct PacketEntry<'a> {
     elements: Vec<&'a PyAny>,
     #[pyo3(item)]
     doc: &'a PyAny,
-    //#[pyo3(item)]
-    //since_firmware: [u8; 3],
+}
+
+/// Firmware version a packet was introduced with, common packets list it per device name.
+#[derive(Debug, Clone, FromPyObject)]
+enum SinceFirmware<'a> {
+    Version([u8; 3]),
+    PerDevice(HashMap<&'a str, Option<[u8; 3]>>),
+}
+
+impl<'a> SinceFirmware<'a> {
+    fn from_packet(packet: &'a PyAny) -> PyResult<Option<Self>> {
+        match packet.get_item("since_firmware") {
+            Ok(since_firmware) if !since_firmware.is_none() => Ok(Some(since_firmware.extract()?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the version to check on the given device, functions available since the initial firmware need no check.
+    fn required_version(&self, device_name: &str) -> Option<[u8; 3]> {
+        let version = match self {
+            SinceFirmware::Version(version) => Some(*version),
+            SinceFirmware::PerDevice(versions) => versions.get(device_name).or_else(|| versions.get("*")).copied().flatten(),
+        };
+        version.filter(|version| *version > [2, 0, 0])
+    }
 }
 
 #[derive(Debug, FromPyObject)]
@@ -98,6 +158,61 @@ struct ElementDescription<'a> {
     unit: Option<&'a str>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+struct SiConversion {
+    symbol: String,
+    factor: f64,
+}
+
+impl SiConversion {
+    fn from_element_details(details: &PyDict) -> PyResult<Option<Self>> {
+        let unit_name = if let Some(unit_name) = string_from_dict(details, "unit")? {
+            unit_name
+        } else {
+            return Ok(None);
+        };
+        if unit_name == "dynamic" || unit_name == "unknown" {
+            return Ok(None);
+        }
+        let (scale_numerator, scale_denominator) = if let Some(scale) = details.get_item("scale")? {
+            if let Ok(scale) = scale.extract::<(u64, u64)>() {
+                scale
+            } else if scale.extract::<&str>()? == "dynamic" {
+                return Ok(None);
+            } else {
+                (1, 1)
+            }
+        } else {
+            (1, 1)
+        };
+        let resolved = details.py().import("initializer")?.getattr("resolve_unit")?.call1((unit_name,))?;
+        if resolved.is_none() {
+            println!("cargo:warning=Unknown unit: {unit_name}");
+            return Ok(None);
+        }
+        let (symbol, unit_numerator, unit_denominator) = resolved.extract::<(String, u64, u64)>()?;
+        let factor = (scale_numerator as f64 * unit_numerator as f64) / (scale_denominator as f64 * unit_denominator as f64);
+        Ok(Some(SiConversion { symbol, factor }))
+    }
+
+    fn convert(&self, value: Expr) -> Expr {
+        let factor = proc_macro2::Literal::f64_suffixed(self.factor);
+        parse_quote!((#value) as f64 * #factor)
+    }
+}
+
+#[derive(Debug)]
+struct PacketField {
+    field: Field,
+    size: usize,
+    si_conversion: Option<SiConversion>,
+    /// Initial value from the config, only set on request fields.
+    default: Option<Expr>,
+    /// Set on request fields of a constant group without a zero-valued constant and without a configured default, the
+    /// request then has no `Default` implementation.
+    no_default: bool,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 enum TfValueType {
     U8,
@@ -134,7 +249,7 @@ impl ToTokens for TfValueType {
                     quote!(char)
                 }
                 TfValueType::String => {
-                    quote!(Box<str>)
+                    quote!(String)
                 }
                 TfValueType::I8 => {
                     quote!(i8)
@@ -202,7 +317,7 @@ impl TfValueType {
                 .map_err(|e| PyErr::new::<PyTypeError, _>(format!("Cannot parse {string} as char: {e}")))?
                 .into_token_stream(),
             TfValueType::String => {
-                parse_quote!(#string.to_boxed_str())
+                parse_quote!(String::from(#string))
             }
             TfValueType::I8 => string.parse::<i8>()?.into_token_stream(),
             TfValueType::I16 => string.parse::<i16>()?.into_token_stream(),
@@ -212,6 +327,10 @@ impl TfValueType {
             TfValueType::I64 => string.parse::<i64>()?.into_token_stream(),
         })
     }
+    fn metadata_type(&self) -> Expr {
+        let variant = create_ident(&format!("{self:?}"));
+        parse_quote!(crate::metadata::ElementType::#variant)
+    }
     fn bytecount(&self, array_length: usize) -> usize {
         match self {
             TfValueType::U8 => array_length,
@@ -283,7 +402,30 @@ def create_generators_module():
 if 'generators' not in sys.modules:
     create_generators_module()
 
-from generators import common"
+from generators import common
+import functools
+
+@functools.lru_cache(maxsize=None)
+def resolve_unit(unit_name):
+    for unit in common.units:
+        for prefix in [None] + common.unit_prefixes:
+            for inverse_prefix in [None] + common.unit_prefixes:
+                if unit.get_name(prefix=prefix, inverse_prefix=inverse_prefix) != unit_name:
+                    continue
+                numerator = 1
+                denominator = 1
+                if prefix != None:
+                    if prefix.index == 0:
+                        numerator *= prefix.divisor ** unit.get_numerator_exponent()
+                    else:
+                        denominator *= prefix.divisor ** unit.get_numerator_exponent()
+                if inverse_prefix != None:
+                    if inverse_prefix.index == 0:
+                        denominator *= inverse_prefix.divisor ** unit.get_denominator_exponent()
+                    else:
+                        numerator *= inverse_prefix.divisor ** unit.get_denominator_exponent()
+                return unit.get_base_symbol(), numerator, denominator
+    return None"
     );
     PyModule::from_code(py, &initializer_code, "generators.rs", "initializer")?;
     let mut bindings_content = Vec::new();
@@ -291,6 +433,9 @@ from generators import common"
     let mut device_encode_arms = Vec::new();
     let mut device_parse_arms = Vec::new();
     let mut device_name_arms = Vec::new();
+    let mut device_details = Vec::new();
+    let mut device_metadata_arms = Vec::new();
+    let mut device_metadata = Vec::new();
 
     let found_modules = dir
         .into_iter()
@@ -309,9 +454,9 @@ from generators import common"
                 None
             }
         })
-        .collect::<HashMap<_, _>>();
+        .collect::<BTreeMap<_, _>>();
     let mut common_items = Vec::new();
-    let mut features = HashMap::<_, (Vec<_>, Vec<_>)>::new();
+    let mut features = BTreeMap::<_, FeatureData>::new();
     for (_, module) in found_modules.iter() {
         if let Ok(com_struct) = module.getattr("common_constant_groups") {
             for common_constant_group_entry_dict in
@@ -322,7 +467,9 @@ from generators import common"
                     .into_boxed_str();
                 let common_constant_group_entry = ConstantGroupEntry::extract(common_constant_group_entry_dict)
                     .expect("Cannot parse common_constant_groups of {module_name}");
-                process_constant_group(&mut features.entry(feature).or_default().0, common_constant_group_entry);
+                let feature_data = features.entry(feature).or_default();
+                feature_data.constant_group_metadata.extend(constant_group_metadata_expr(&common_constant_group_entry)?);
+                process_constant_group(&mut feature_data.items, &mut feature_data.constant_groups, common_constant_group_entry);
             }
         }
         if let Ok(com_packets) = module.getattr("common_packets") {
@@ -330,6 +477,7 @@ from generators import common"
                 let feature = String::extract(com_packet.get_item("feature").expect("Missing feature")).expect("Feature is not a string");
                 let feature_data = features.entry(feature.into_boxed_str()).or_default();
                 if com_packet.get_item("is_virtual").ok().map(bool::extract).and_then(<PyResult<bool>>::ok).unwrap_or(false) {
+                    feature_data.virtual_functions.extend(virtual_function(com_packet)?);
                     continue;
                 }
                 let packet_data = PacketEntry::extract(com_packet).expect("Cannot unpack packet entry");
@@ -340,12 +488,20 @@ from generators import common"
                     .expect("Function_ID is not a number")
                     .extract()
                     .expect("Function id is not u8");
-                feature_data.1.push((function_id, packet_data));
+                let since_firmware = SinceFirmware::from_packet(com_packet)?;
+                feature_data.response_expected.extend(response_expected_flag(com_packet, &packet_data)?.map(|flag| (function_id, flag)));
+                feature_data.packets.push((function_id, packet_data, since_firmware));
             }
         }
     }
-    let mut feature_trait_impls = HashMap::<_, (Path, Vec<ImplItemFn>)>::new();
-    for (feature_name, (mut constants, packets)) in features {
+    let mut feature_trait_impls = BTreeMap::<_, (Path, Vec<(ImplItemFn, Option<SinceFirmware>)>)>::new();
+    let mut feature_metadata = BTreeMap::<_, (Vec<(u8, Expr)>, Vec<Expr>, Vec<(u8, Expr)>)>::new();
+    let mut feature_server_packets = BTreeMap::<_, Vec<ServerPacket>>::new();
+    for (
+        feature_name,
+        FeatureData { items: mut constants, packets, constant_groups, constant_group_metadata, response_expected, virtual_functions },
+    ) in features
+    {
         let trait_name = create_ident(&feature_name.as_ref().to_case(Case::UpperCamel));
         let feature_package_ident = create_ident(&feature_name.as_ref().to_case(Case::Snake));
         let base_package_path = parse_quote!(crate::bindings::common::#feature_package_ident);
@@ -355,15 +511,39 @@ from generators import common"
         let mut trait_items = Vec::<TraitItem>::new();
 
         let mut item_impls = Vec::with_capacity(packets.len());
-        for (function_id, packet_entry) in packets {
-            let mut function = generate_packet_element_item(&mut trait_helper_structs, function_id, &packet_entry, &base_package_path)?;
-
-            let mut attrs = function.attrs.clone();
-            attrs.push(parse_quote!(#[allow(async_fn_in_trait)]));
-            trait_items.push(TraitItem::Fn(TraitItemFn { attrs, sig: function.sig.clone(), default: None, semi_token: None }));
+        let mut packet_metadata = Vec::with_capacity(packets.len());
+        let mut server_packets = Vec::with_capacity(packets.len());
+        for (function_id, packet_entry, since_firmware) in packets {
+            packet_metadata.extend(packet_metadata_expr(function_id, &packet_entry)?.map(|metadata| (function_id, metadata)));
+            // the required firmware depends on the device, the check is added when implementing the trait
+            let since_firmware = since_firmware.filter(|_| packet_entry.r#type == "function");
+            for mut function in generate_packet_element_item(
+                &mut trait_helper_structs,
+                function_id,
+                &packet_entry,
+                &base_package_path,
+                &constant_groups,
+                &mut server_packets,
+            )? {
+                let mut attrs = function.attrs.clone();
+                attrs.push(parse_quote!(#[allow(async_fn_in_trait)]));
+                trait_items.push(TraitItem::Fn(TraitItemFn { attrs, sig: function.sig.clone(), default: None, semi_token: None }));
+                function.vis = Visibility::Inherited;
+                item_impls.push((function, since_firmware.clone()));
+            }
+        }
+        for mut function in virtual_functions {
+            trait_items.push(TraitItem::Fn(TraitItemFn {
+                attrs: function.attrs.clone(),
+                sig: function.sig.clone(),
+                default: None,
+                semi_token: None,
+            }));
             function.vis = Visibility::Inherited;
-            item_impls.push(function);
+            item_impls.push((function, None));
         }
+        feature_metadata.insert(feature_name.clone(), (packet_metadata, constant_group_metadata, response_expected));
+        feature_server_packets.insert(feature_name.clone(), server_packets);
         feature_trait_impls.insert(feature_name, (parse_quote!(#base_package_path::#trait_name), item_impls));
         trait_helper_structs.push(Item::Trait(ItemTrait {
             attrs: vec![],
@@ -428,6 +608,7 @@ from generators import common"
                 device_encode_arms.push(parse_quote!(DeviceIdentifier::#device_struct_name =>#value));
                 device_parse_arms.push(parse_quote!(#value => Ok(DeviceIdentifier::#device_struct_name)));
                 device_name_arms.push(parse_quote!(DeviceIdentifier::#device_struct_name =>#raw_package_name));
+                device_details.push((device_struct_name.clone(), raw_package_name, tf_device.category, tf_device.features.clone()));
             }
 
             let mut items = Vec::new();
@@ -460,19 +641,31 @@ from generators import common"
             let av3 = api_version[2];*/
             let mut device_impl: ItemImpl = parse_quote!(
                 impl #device_struct_name {
-                    pub fn new(uid: crate::base58::Uid, connection: crate::ip_connection::async_io::AsyncIpConnection) -> #device_struct_name {
+                    /// Creates the device from its enumeration, functions newer than the reported firmware are rejected locally.
+                    pub fn from_enumerate_response(response: &crate::ip_connection::EnumerateResponse, connection: crate::ip_connection::async_io::AsyncIpConnection) -> #device_struct_name {
+                        let result = Self::new(response.uid, connection);
                         Self{
-                            device: crate::device::Device::new(uid,connection,#raw_package_name)
+                            device: result.device.with_firmware_version(response.firmware_version)
                         }
                     }
                     pub fn uid(&self)->crate::base58::Uid{
                         self.device.uid()
                     }
+                    /// Firmware version of the device, if it was created from an enumeration.
+                    pub fn firmware_version(&self) -> Option<crate::ip_connection::Version> {
+                        self.device.firmware_version()
+                    }
                 }
             );
+            let mut constant_groups = ConstantGroupLookup::new();
+            let mut constant_group_metadata = Vec::new();
             for group in tf_device.constant_groups {
-                process_constant_group(&mut items, group);
+                constant_group_metadata.extend(constant_group_metadata_expr(&group)?);
+                process_constant_group(&mut items, &mut constant_groups, group);
             }
+            let mut packet_metadata = Vec::new();
+            let mut response_expected = Vec::new();
+            let mut server_packets = Vec::new();
             let mut function_id: u8 = 0;
             for packet_entry_any in tf_device.packets.iter() {
                 if packet_entry_any.get_item("openhab_doc").ok().map(bool::extract).and_then(<PyResult<bool>>::ok).unwrap_or(false) {
@@ -487,24 +680,158 @@ from generators import common"
                     function_id + 1
                 };
 
-                let function = generate_packet_element_item(&mut items, function_id, &packet_entry, &package_path)?;
-                device_impl.items.push(ImplItem::Fn(function));
+                packet_metadata.extend(packet_metadata_expr(function_id, &packet_entry)?.map(|metadata| (function_id, metadata)));
+                response_expected.extend(response_expected_flag(packet_entry_any, &packet_entry)?.map(|flag| (function_id, flag)));
+                let since_firmware = SinceFirmware::from_packet(packet_entry_any)?.filter(|_| packet_entry.r#type == "function");
+                for mut function in generate_packet_element_item(
+                    &mut items,
+                    function_id,
+                    &packet_entry,
+                    &package_path,
+                    &constant_groups,
+                    &mut server_packets,
+                )? {
+                    if let Some(since_firmware) = &since_firmware {
+                        insert_firmware_check(&mut function, since_firmware, raw_package_name);
+                    }
+                    device_impl.items.push(ImplItem::Fn(function));
+                }
             }
+            for feature_name in &tf_device.features {
+                if let Some((_, _, flags)) = feature_metadata.get(*feature_name) {
+                    response_expected.extend(flags.iter().cloned());
+                }
+            }
+            let api_functions = device_impl
+                .items
+                .iter()
+                .filter_map(|item| if let ImplItem::Fn(function) = item { Some(function) } else { None })
+                .filter(|function| function.sig.ident != "from_enumerate_response")
+                .cloned()
+                .collect::<Vec<_>>();
+            let mock_flags =
+                response_expected.iter().map(|(function_id, flag)| -> Expr { parse_quote!((#function_id, #flag)) }).collect::<Vec<_>>();
+            let [major, minor, revision] = tf_device.api_version;
+            let response_expected = response_expected.into_iter().map(|(function_id, flag)| -> Stmt {
+                let function_id = function_id as usize;
+                parse_quote!(result.device.response_expected[#function_id] = #flag;)
+            });
+            device_impl.items.insert(
+                0,
+                parse_quote!(
+                    pub fn new(uid: crate::base58::Uid, connection: crate::ip_connection::async_io::AsyncIpConnection) -> #device_struct_name {
+                        let mut result = Self{
+                            device: crate::device::Device::new([#major, #minor, #revision], uid, connection, #raw_package_name)
+                        };
+                        #(#response_expected)*
+                        result
+                    }
+                ),
+            );
             items.push(Item::Impl(device_impl));
+            let api_trait_name = create_ident(&format!("{}Api", raw_package_name.to_case(Case::UpperCamel)));
+            let mock_struct_name = create_ident(&format!("{}Mock", raw_package_name.to_case(Case::UpperCamel)));
+            let mut api_supertraits = Vec::new();
+            let mut mock_feature_impls = Vec::new();
             for feature_name in tf_device.features {
+                server_packets.extend(feature_server_packets.get(feature_name).into_iter().flatten().cloned());
+                if let Some((packets, groups, _)) = feature_metadata.get(feature_name) {
+                    packet_metadata.extend(packets.iter().cloned());
+                    constant_group_metadata.extend(groups.iter().cloned());
+                }
                 if let Some((path, impls)) = feature_trait_impls.get(feature_name) {
                     let mut feature_impl: ItemImpl = parse_quote!(
                         impl #path for #device_struct_name{
                         }
                     );
-                    for item_fn in impls {
-                        feature_impl.items.push(ImplItem::Fn(item_fn.clone()));
+                    for (item_fn, since_firmware) in impls {
+                        let mut item_fn = item_fn.clone();
+                        if let Some(since_firmware) = since_firmware {
+                            insert_firmware_check(&mut item_fn, since_firmware, raw_package_name);
+                        }
+                        feature_impl.items.push(ImplItem::Fn(item_fn));
                     }
                     items.push(Item::Impl(feature_impl));
+                    let mock_functions = impls.iter().map(|(item_fn, _)| mock_function(item_fn));
+                    mock_feature_impls.push(parse_quote!(
+                        #[cfg(feature = "mock")]
+                        impl #path for #mock_struct_name {
+                            #(#mock_functions)*
+                        }
+                    ));
+                    api_supertraits.push(path.clone());
                 } else {
                     panic!("Feature {feature_name} not defined");
                 }
             }
+            let api_trait_doc =
+                format!(" All functions of [`{device_struct_name}`], to allow replacing the device by [`{mock_struct_name}`] in tests.");
+            let mut api_trait: ItemTrait = parse_quote!(
+                #[doc = #api_trait_doc]
+                pub trait #api_trait_name: #(#api_supertraits)+* {}
+            );
+            if api_supertraits.is_empty() {
+                api_trait.colon_token = None;
+            }
+            api_trait.items.extend(api_functions.iter().map(api_trait_item));
+            items.push(Item::Trait(api_trait));
+            let delegating_functions = api_functions.iter().map(delegate_function);
+            items.push(parse_quote!(
+                impl #api_trait_name for #device_struct_name {
+                    #(#delegating_functions)*
+                }
+            ));
+            let mock_doc = format!(" Stand-in for [`{device_struct_name}`] whose behaviour is scripted by [`crate::mock::MockDevice`].");
+            items.push(parse_quote!(
+                #[cfg(feature = "mock")]
+                #[doc = #mock_doc]
+                #[derive(Clone, Debug)]
+                pub struct #mock_struct_name {
+                    device: crate::mock::MockDevice,
+                }
+            ));
+            items.push(parse_quote!(
+                #[cfg(feature = "mock")]
+                impl #mock_struct_name {
+                    pub fn new(uid: crate::base58::Uid) -> #mock_struct_name {
+                        Self {
+                            device: crate::mock::MockDevice::new([#major, #minor, #revision], uid, &[#(#mock_flags),*]),
+                        }
+                    }
+                    /// Scripts return values, inspects recorded calls and injects callbacks, clones share this state.
+                    pub fn mock(&self) -> &crate::mock::MockDevice {
+                        &self.device
+                    }
+                }
+            ));
+            let mock_functions = api_functions.iter().map(mock_function);
+            items.push(parse_quote!(
+                #[cfg(feature = "mock")]
+                impl #api_trait_name for #mock_struct_name {
+                    #(#mock_functions)*
+                }
+            ));
+            items.append(&mut mock_feature_impls);
+            items.append(&mut server_items(&device_struct_name, raw_package_name, server_packets));
+            if value > 0 {
+                packet_metadata.sort_by_key(|(function_id, _)| *function_id);
+                let packet_metadata = packet_metadata.into_iter().map(|(_, metadata)| metadata);
+                let display_name = tf_device.display_name;
+                let category = tf_device.category;
+                items.push(parse_quote!(
+                    /// Functions, callbacks and constants of this device.
+                    pub static METADATA: crate::metadata::DeviceMetadata = crate::metadata::DeviceMetadata {
+                        identifier: crate::bindings::DeviceIdentifier::#device_struct_name,
+                        name: #raw_package_name,
+                        display_name: #display_name,
+                        category: #category,
+                        packets: &[#(#packet_metadata),*],
+                        constant_groups: &[#(#constant_group_metadata),*],
+                    };
+                ));
+                device_metadata_arms.push(parse_quote!(DeviceIdentifier::#device_struct_name => &#package_path::METADATA));
+                device_metadata.push((tf_device.device_identifier, package_path.clone()));
+            }
             bindings_content.push(Item::Mod(ItemMod {
                 attrs: vec![],
                 vis: Visibility::Public(Default::default()),
@@ -521,18 +848,30 @@ from generators import common"
 
     bindings_content.push(Item::Enum(parse_quote!(
         #[derive(Copy,Clone,Eq,PartialEq,Debug,Ord, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum DeviceIdentifier{
             #device_variants
         }
     )));
     let name_match = match_self(device_name_arms);
+    let metadata_match = match_self(device_metadata_arms);
     bindings_content.push(Item::Impl(parse_quote!(
         impl DeviceIdentifier {
             pub fn name(self) -> &'static str {
                 #name_match
             }
+            /// Functions, callbacks and constants of this device type.
+            pub fn metadata(self) -> &'static crate::metadata::DeviceMetadata {
+                #metadata_match
+            }
         }
     )));
+    bindings_content.push(Item::Impl(device_identifier_details(&device_details)));
+    device_metadata.sort_by_key(|(device_identifier, _)| *device_identifier);
+    let device_metadata = device_metadata.into_iter().map(|(_, package_path)| -> Expr { parse_quote!(&#package_path::METADATA) });
+    bindings_content.push(parse_quote!(
+        pub(crate) static DEVICE_METADATA: &[&crate::metadata::DeviceMetadata] = &[#(#device_metadata),*];
+    ));
     let encode_match = match_self(device_encode_arms);
     bindings_content.push(Item::Impl(parse_quote!(
         impl Into<u16> for DeviceIdentifier {
@@ -554,10 +893,107 @@ from generators import common"
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let dest_path = path::Path::new(&out_dir).join("bindings.rs");
     fs::write(dest_path, unparse(&file))?;
+    check_api(&file, path::Path::new(&out_dir))?;
+
+    Ok(())
+}
 
+/// Writes the public API of the bindings to `api.txt` in the output directory. If `TINKERFORGE_API_SNAPSHOT` points
+/// to a previously written file, the build fails on removed or changed items. A missing snapshot is created instead.
+fn check_api(file: &File, out_dir: &path::Path) -> Result<(), PyErr> {
+    println!("cargo:rerun-if-env-changed=TINKERFORGE_API_SNAPSHOT");
+    let mut api = BTreeMap::new();
+    collect_api(&mut api, "crate", &file.items);
+    let api_text = api.iter().map(|(key, signature)| format!("{key}\t{signature}\n")).collect::<String>();
+    fs::write(out_dir.join("api.txt"), &api_text)?;
+    let snapshot_path = if let Some(snapshot_path) = env::var_os("TINKERFORGE_API_SNAPSHOT") {
+        path::PathBuf::from(snapshot_path)
+    } else {
+        return Ok(());
+    };
+    println!("cargo:rerun-if-changed={}", snapshot_path.display());
+    if !snapshot_path.exists() {
+        fs::write(&snapshot_path, &api_text)?;
+        println!("cargo:warning=Created API snapshot {}", snapshot_path.display());
+        return Ok(());
+    }
+    let snapshot = fs::read_to_string(&snapshot_path)?;
+    let snapshot = snapshot.lines().filter_map(|line| line.split_once('\t')).collect::<BTreeMap<_, _>>();
+    let mut breaking_changes = Vec::new();
+    for (key, old_signature) in &snapshot {
+        match api.get(*key) {
+            None => breaking_changes.push(format!("removed {key}")),
+            Some(signature) if signature != old_signature => {
+                breaking_changes.push(format!("changed {key}: {old_signature} -> {signature}"))
+            }
+            Some(_) => {}
+        }
+    }
+    let added = api.keys().filter(|key| !snapshot.contains_key(key.as_str())).count();
+    if added > 0 {
+        println!("cargo:warning={added} API items added compared to {}", snapshot_path.display());
+    }
+    for change in &breaking_changes {
+        println!("cargo:warning={change}");
+    }
+    if !breaking_changes.is_empty() {
+        panic!("{} API breaking changes compared to {}", breaking_changes.len(), snapshot_path.display());
+    }
     Ok(())
 }
 
+/// Collects all public items by their path, with the signature or type as value.
+fn collect_api(api: &mut BTreeMap<String, String>, path: &str, items: &[Item]) {
+    let is_public = |vis: &Visibility| matches!(vis, Visibility::Public(_));
+    for item in items {
+        match item {
+            Item::Mod(ItemMod { vis, ident, content: Some((_, items)), .. }) if is_public(vis) => {
+                collect_api(api, &format!("{path}::{ident}"), items)
+            }
+            Item::Struct(item) if is_public(&item.vis) => {
+                api.insert(format!("{path}::{}", item.ident), "struct".to_string());
+                for field in item.fields.iter().filter(|field| is_public(&field.vis)) {
+                    if let Some(field_name) = &field.ident {
+                        api.insert(format!("{path}::{}.{field_name}", item.ident), field.ty.to_token_stream().to_string());
+                    }
+                }
+            }
+            Item::Enum(item) if is_public(&item.vis) => {
+                api.insert(format!("{path}::{}", item.ident), "enum".to_string());
+                for variant in &item.variants {
+                    api.insert(format!("{path}::{}::{}", item.ident, variant.ident), variant.fields.to_token_stream().to_string());
+                }
+            }
+            Item::Trait(item) if is_public(&item.vis) => {
+                for trait_item in &item.items {
+                    if let TraitItem::Fn(function) = trait_item {
+                        api.insert(format!("{path}::{}::{}", item.ident, function.sig.ident), function.sig.to_token_stream().to_string());
+                    }
+                }
+            }
+            Item::Impl(item) => {
+                let self_type = item.self_ty.to_token_stream().to_string();
+                let prefix = if let Some((_, trait_path, _)) = &item.trait_ {
+                    format!("{path}::<{self_type} as {}>", trait_path.to_token_stream())
+                } else {
+                    format!("{path}::{self_type}")
+                };
+                for impl_item in &item.items {
+                    if let ImplItem::Fn(function) = impl_item {
+                        if item.trait_.is_some() || is_public(&function.vis) {
+                            api.insert(format!("{prefix}::{}", function.sig.ident), function.sig.to_token_stream().to_string());
+                        }
+                    }
+                }
+            }
+            Item::Static(item) if is_public(&item.vis) => {
+                api.insert(format!("{path}::{}", item.ident), item.ty.to_token_stream().to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
 fn find_generators(path: Option<&path::Path>) -> Option<&path::Path> {
     if let Some(p) = path {
         if p.ends_with("generators") {
@@ -575,7 +1011,9 @@ fn generate_packet_element_item(
     function_id: u8,
     packet_entry: &PacketEntry,
     base_path: &Path,
-) -> Result<ImplItemFn, PyErr> {
+    constant_groups: &ConstantGroupLookup,
+    server_packets: &mut Vec<ServerPacket>,
+) -> Result<Vec<ImplItemFn>, PyErr> {
     let packet_name = packet_entry.name.to_case(Case::UpperCamel);
     let packet_type = TfPacketType::try_parse_type(packet_entry.r#type).expect("Unknown Packet type");
     let doc_list = packet_entry.doc.downcast::<PyList>()?;
@@ -584,87 +1022,133 @@ fn generate_packet_element_item(
     println!("Packet: {packet_name}");
     let doc_de = doc.get_item("de")?.map(|v| v.to_string()).unwrap_or_default();
 
-    let (mut in_fields, mut out_fields) = parse_packet_elements(&packet_entry, base_path)?;
+    let (mut in_fields, mut out_fields) = parse_packet_elements(&packet_entry, base_path, constant_groups)?;
     Ok(if packet_type == TfPacketType::Function {
-        let (request_type, request_size): (Option<Type>, usize) = if in_fields.is_empty() {
-            (None, 0)
+        let (request_type, request_size, server_request): (Option<Type>, usize, Option<(Type, usize)>) = if in_fields.is_empty() {
+            (None, 0, None)
         } else if in_fields.len() == 1 {
-            let (first_field, length) = in_fields.remove(0);
-            (Some(first_field.ty), length)
+            let first_field = in_fields.remove(0);
+            let server_request = Some((first_field.field.ty.clone(), first_field.size));
+            if is_string(&first_field.field.ty) {
+                (Some(parse_quote!(&str)), first_field.size, server_request)
+            } else {
+                (Some(first_field.field.ty), first_field.size, server_request)
+            }
         } else {
             let name = format!("{packet_name}Request");
             let struct_name: Ident = create_ident(&name);
             let size = append_data_object(items, &in_fields, &struct_name);
-            (Some(parse_quote!(#base_path::#struct_name)), size)
+            append_default_impl(items, &in_fields, &struct_name);
+            let ty: Type = parse_quote!(#base_path::#struct_name);
+            (Some(ty.clone()), size, Some((ty, size)))
         };
-        let (response_type, response_line): (Type, Option<Stmt>) = if out_fields.is_empty() {
-            (parse_quote!(()), None)
+        let mut response_size = 0;
+        let (response_type, response_expr, si_conversion): (Type, Option<Expr>, Option<SiConversion>) = if out_fields.is_empty() {
+            (parse_quote!(()), None, None)
         } else if out_fields.len() == 1 {
-            let (first_field, length) = out_fields.remove(0);
+            let first_field = out_fields.remove(0);
+            let length = first_field.size;
+            response_size = length;
             let length_literal: Lit = parse_quote!(#length);
             let method_ident = parse_quote!(from_le_byte_slice);
             let args = parse_quote!((&result.body()[0..#length_literal]));
-            let read_method_call = static_method_call(&first_field.ty, method_ident, args);
-            (first_field.ty, Some(Stmt::Expr(parse_quote!(Ok(#read_method_call)), None)))
+            let read_method_call = static_method_call(&first_field.field.ty, method_ident, args);
+            (first_field.field.ty, Some(read_method_call), first_field.si_conversion)
         } else {
             let name = format!("{packet_name}Response");
             let struct_name: Ident = create_ident(&name);
-            append_data_object(items, &out_fields, &struct_name);
-            (
-                parse_quote!(#base_path::#struct_name),
-                Some(Stmt::Expr(parse_quote!(Ok(#base_path::#struct_name::from_le_byte_slice(result.body()))), None)),
-            )
+            response_size = append_data_object(items, &out_fields, &struct_name);
+            (parse_quote!(#base_path::#struct_name), Some(parse_quote!(#base_path::#struct_name::from_le_byte_slice(result.body()))), None)
         };
         let function_name = create_ident(&packet_entry.name.to_case(Case::Snake));
+        server_packets.push(ServerPacket {
+            function_id,
+            name: function_name.clone(),
+            doc: doc_de.clone(),
+            callback: false,
+            request: server_request,
+            response: Some((response_type.clone(), response_size)).filter(|_| response_expr.is_some()),
+        });
         let mut function_statements = Vec::new();
         if request_type.is_some() {
             function_statements.push(parse_quote!(let mut payload = [0; #request_size];));
-            function_statements.push(parse_quote!(crate::byte_converter::ToBytes::write_to_slice(request,&mut payload);))
+            function_statements
+                .push(parse_quote!(crate::byte_converter::ToBytes::try_write_to_slice(request, #request_size, &mut payload)?;))
         } else {
             function_statements.push(parse_quote!(let payload = [0; #request_size];));
         }
 
-        if let Some(response_line) = response_line {
+        let mut si_function_statements = None;
+        if let Some(response_expr) = response_expr {
             function_statements.push(parse_quote!(let result = self.device.get(#function_id, &payload).await?;));
-            function_statements.push(response_line);
+            if let Some(si_conversion) = &si_conversion {
+                let mut statements = function_statements.clone();
+                let converted = si_conversion.convert(response_expr.clone());
+                statements.push(Stmt::Expr(parse_quote!(Ok(#converted)), None));
+                si_function_statements = Some(statements);
+            }
+            function_statements.push(Stmt::Expr(parse_quote!(Ok(#response_expr)), None));
         } else {
             function_statements
                 .push(parse_quote!(self.device.set(#function_id, &payload,Some(std::time::Duration::from_secs(20))).await?;));
             function_statements.push(Stmt::Expr(parse_quote!(Ok(())), None));
         }
-        let function_block = Block { brace_token: Default::default(), stmts: function_statements };
-        if let Some(request_type) = request_type {
-            parse_quote!(
-                #[doc = #doc_de]
-                pub async fn #function_name(&mut self, request: #request_type) -> Result<#response_type, crate::error::TinkerforgeError>
-                    #function_block
-            )
-        } else {
-            parse_quote!(
-                #[doc = #doc_de]
-                pub async fn #function_name(&mut self) -> Result<#response_type, crate::error::TinkerforgeError>
-                    #function_block
-            )
+        let mut functions = vec![(function_name.clone(), doc_de, response_type, function_statements)];
+        if let (Some(si_conversion), Some(statements)) = (si_conversion, si_function_statements) {
+            let doc = format!(" Like [`{function_name}`](Self::{function_name}), but returns the value in {}.", si_conversion.symbol);
+            functions.push((create_ident(&format!("{function_name}_si")), doc, parse_quote!(f64), statements));
         }
+        functions
+            .into_iter()
+            .map(|(function_name, doc, response_type, function_statements)| {
+                let function_block = Block { brace_token: Default::default(), stmts: function_statements };
+                if let Some(request_type) = &request_type {
+                    parse_quote!(
+                        #[doc = #doc]
+                        pub async fn #function_name(&mut self, request: #request_type) -> Result<#response_type, crate::error::TinkerforgeError>
+                            #function_block
+                    )
+                } else {
+                    parse_quote!(
+                        #[doc = #doc]
+                        pub async fn #function_name(&mut self) -> Result<#response_type, crate::error::TinkerforgeError>
+                            #function_block
+                    )
+                }
+            })
+            .collect()
     } else if packet_type == TfPacketType::Callback {
-        let function_name = create_ident(&format!("{}_stream", packet_entry.name.to_case(Case::Snake)));
+        let stream_name = packet_entry.name.to_case(Case::Snake);
+        let function_name = create_ident(&format!("{stream_name}_stream"));
+        let mut server_packet = ServerPacket {
+            function_id,
+            name: create_ident(&stream_name),
+            doc: doc_de.clone(),
+            callback: true,
+            request: None,
+            response: None,
+        };
         if out_fields.is_empty() {
+            server_packets.push(server_packet);
             let function_block: Block = parse_quote!({self.device
                         .get_callback_receiver(#function_id)
                         .await
                         .map(|_| ())});
-            parse_quote!(
+            vec![parse_quote!(
                 #[doc = #doc_de]
                 pub async fn #function_name(&mut self) -> impl futures_core::Stream<Item = ()>
                     #function_block
-            )
+            )]
         } else if out_fields.len() == 1 {
-            let (first_field, length) = out_fields.remove(0);
+            let first_field = out_fields.remove(0);
+            let length = first_field.size;
             let length_literal: Lit = parse_quote!(#length);
             let method_ident = parse_quote!(from_le_byte_slice);
             let args = parse_quote!((&p.body()[0..#length_literal]));
-            let read_method_call = static_method_call(&first_field.ty, method_ident, args);
-            let struct_name = first_field.ty;
+            let read_method_call = static_method_call(&first_field.field.ty, method_ident, args);
+            let struct_name = first_field.field.ty;
+            server_packet.response = Some((struct_name.clone(), length));
+            server_packets.push(server_packet);
             let function_block: Block = parse_quote!(
                 {self.device
                         .get_callback_receiver(#function_id)
@@ -672,32 +1156,53 @@ fn generate_packet_element_item(
                         .map(|p| #read_method_call)
                     }
             );
-            parse_quote!(
+            let mut functions = vec![parse_quote!(
                 #[doc = #doc_de]
                 pub async fn #function_name(&mut self) -> impl futures_core::Stream<Item = #struct_name>
                     #function_block
-            )
+            )];
+            if let Some(si_conversion) = first_field.si_conversion {
+                let si_function_name = create_ident(&format!("{stream_name}_si_stream"));
+                let doc = format!(" Like [`{function_name}`](Self::{function_name}), but yields the values in {}.", si_conversion.symbol);
+                let converted = si_conversion.convert(read_method_call);
+                functions.push(parse_quote!(
+                    #[doc = #doc]
+                    pub async fn #si_function_name(&mut self) -> impl futures_core::Stream<Item = f64>
+                        {self.device
+                            .get_callback_receiver(#function_id)
+                            .await
+                            .map(|p| #converted)
+                        }
+                ));
+            }
+            functions
         } else {
             let struct_name: Ident = create_ident(&format!("{packet_name}Callback"));
-            append_data_object(items, &mut out_fields, &struct_name);
+            let size = append_data_object(items, &mut out_fields, &struct_name);
+            server_packet.response = Some((parse_quote!(#base_path::#struct_name), size));
+            server_packets.push(server_packet);
             let function_block: Block = parse_quote!({
                        self.device
                         .get_callback_receiver(#function_id)
                         .await
                         .map(|p| #struct_name::from_le_byte_slice(p.body()))}
             );
-            parse_quote!(
+            vec![parse_quote!(
                 #[doc = #doc_de]
                 pub async fn #function_name(&mut self) -> impl futures_core::Stream<Item = #base_path::#struct_name>
                     #function_block
-            )
+            )]
         }
     } else {
         panic!("Invalid packet type")
     })
 }
 
-fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result<(Vec<(Field, usize)>, Vec<(Field, usize)>), PyErr> {
+fn parse_packet_elements(
+    packet_entry: &PacketEntry,
+    base_path: &Path,
+    constant_groups: &ConstantGroupLookup,
+) -> Result<(Vec<PacketField>, Vec<PacketField>), PyErr> {
     let mut in_fields = Vec::new();
     let mut out_fields = Vec::new();
     for element_entry in &packet_entry.elements {
@@ -712,9 +1217,10 @@ fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result
         let subelements = if element_tuple.len() > 4 {
             let details = element_tuple.get_item(4)?;
             if let Ok(params) = details.downcast::<PyDict>() {
-                let unit = string_from_dict(params, "unit").expect("Cannot parse unit of element");
+                let si_conversion = SiConversion::from_element_details(params)?;
                 let constant_group = string_from_dict(params, "constant_group")?;
-                vec![(constant_group, create_ident(&element_name_rust.to_case(Case::Snake)), unit)]
+                let default = params.get_item("default")?;
+                vec![(constant_group, create_ident(&element_name_rust.to_case(Case::Snake)), si_conversion, default)]
             } else if let Ok(param_list) = details.downcast::<PyList>() {
                 if param_list.len() == repeat_count {
                     param_list
@@ -724,9 +1230,10 @@ fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result
                             let name =
                                 string_from_dict(e, "name").expect("Error extracting name").expect("No name attribute in list entry");
                             let constant_group = string_from_dict(e, "constant_group").expect("Error extracting constant group");
-                            let unit = string_from_dict(e, "unit").expect("Error extracting unit");
+                            let si_conversion = SiConversion::from_element_details(e).expect("Error extracting unit");
+                            let default = e.get_item("default").expect("Error extracting default");
                             let element_name_rust = format!("{element_name} {}", name).to_case(Case::Snake);
-                            (constant_group, create_ident(&element_name_rust), unit)
+                            (constant_group, create_ident(&element_name_rust), si_conversion, default)
                         })
                         .collect()
                 } else {
@@ -746,41 +1253,77 @@ fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result
             panic!("Unknown direction: {direction_str}");
         };
         let ident = create_ident(&element_name_rust.to_case(Case::Snake));
-        let (create_fields, field_size): (Box<[(Type, Ident)]>, _) = if let Some(ty) = transfer_type {
+        let (create_fields, field_size): (Box<[(Type, Ident, Option<SiConversion>, Option<Expr>, bool)]>, _) = if let Some(ty) = transfer_type {
             if repeat_count > 1 && ty == TfValueType::String {
-                (vec![(parse_quote!([char;#repeat_count]), parse_quote!(#ident))].into(), ty.bytecount(repeat_count))
+                let default = match subelements.first() {
+                    Some((_, _, _, Some(default))) if wrap_enum => string_default_expr(default, repeat_count)?,
+                    _ => None,
+                };
+                (vec![(parse_quote!(String), parse_quote!(#ident), None, default, false)].into(), ty.bytecount(repeat_count))
             } else {
                 let base_type = ty.to_token_stream();
-                let found_types: Box<[(Type, Ident)]> = if subelements.is_empty() {
-                    vec![(parse_quote!(#base_type), parse_quote!(#ident))].into()
+                let found_types: Box<[(Type, Ident, Option<SiConversion>, Option<&PyAny>, Option<&Vec<(&str, &PyAny)>>)]> =
+                    if subelements.is_empty() {
+                        vec![(parse_quote!(#base_type), parse_quote!(#ident), None, None, None)].into()
+                    } else {
+                        subelements
+                            .into_iter()
+                            .map(|(constant_group, ident, si_conversion, default)| {
+                                if let Some(constant_group) = constant_group {
+                                    let constant_type_name = Some(create_ident(&constant_group.to_case(Case::UpperCamel)));
+                                    (
+                                        if wrap_enum {
+                                            parse_quote!(#base_path::#constant_type_name)
+                                        } else {
+                                            parse_quote!(crate::byte_converter::ParsedOrRaw<#base_path::#constant_type_name,#ty>)
+                                        },
+                                        parse_quote!(#ident),
+                                        None,
+                                        default,
+                                        constant_groups.get(constant_group),
+                                    )
+                                } else {
+                                    (parse_quote!(#base_type), parse_quote!( #ident), si_conversion, default, None)
+                                }
+                            })
+                            .collect()
+                    };
+                // defaults are only needed to construct requests
+                let found_types = if wrap_enum {
+                    found_types
                 } else {
-                    subelements
-                        .into_iter()
-                        .map(|(constant_group, ident, unit)| {
-                            if let Some(constant_group) = constant_group {
-                                let constant_type_name = Some(create_ident(&constant_group.to_case(Case::UpperCamel)));
-                                (
-                                    if wrap_enum {
-                                        parse_quote!(#base_path::#constant_type_name)
-                                    } else {
-                                        parse_quote!(crate::byte_converter::ParsedOrRaw<#base_path::#constant_type_name,#ty>)
-                                    },
-                                    parse_quote!(#ident),
-                                )
-                            } else {
-                                (parse_quote!(#base_type), parse_quote!( #ident))
-                            }
-                        })
+                    found_types
+                        .iter()
+                        .map(|(ty, ident, si_conversion, _, _)| (ty.clone(), ident.clone(), si_conversion.clone(), None, None))
                         .collect()
                 };
                 if found_types.len() == 1 && repeat_count > 1 {
-                    if let [(base_type, ident)] = found_types.as_ref() {
-                        (vec![(parse_quote!([#base_type;#repeat_count]), ident.clone())].into(), ty.bytecount(repeat_count))
+                    if let [(base_type, ident, _, default, constants)] = found_types.as_ref() {
+                        let default = if let Some(default) = default {
+                            array_default_expr(&ty, constants.map(|c| (base_type, c.as_slice())), default, repeat_count)?
+                        } else {
+                            None
+                        };
+                        let no_default = default.is_none() && constants.is_some_and(|c| zero_constant(c).is_none());
+                        (
+                            vec![(parse_quote!([#base_type;#repeat_count]), ident.clone(), None, default, no_default)].into(),
+                            ty.bytecount(repeat_count),
+                        )
                     } else {
                         panic!("Invalid");
                     }
                 } else if found_types.len() == repeat_count {
-                    (found_types, ty.bytecount(1))
+                    let mut fields = Vec::with_capacity(found_types.len());
+                    for (base_type, ident, si_conversion, default, constants) in found_types.iter() {
+                        let default = if let Some(default) = default {
+                            scalar_default_expr(&ty, constants.map(|c| (base_type, c.as_slice())), default)?
+                        } else {
+                            None
+                        };
+                        let no_default = default.is_none() && constants.is_some_and(|c| zero_constant(c).is_none());
+                        fields.push((base_type.clone(), ident.clone(), si_conversion.clone(), default, no_default));
+                    }
+                    (fields.into_boxed_slice(), ty.bytecount(1))
                 } else {
                     panic!("Count mismatch");
                 }
@@ -789,9 +1332,9 @@ fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result
             panic!(" ########### Unknown type: {}", transfer_type_str);
         };
 
-        for (ty, ident) in create_fields.into_iter().cloned() {
-            fields.push((
-                Field {
+        for (ty, ident, si_conversion, default, no_default) in create_fields.iter().cloned() {
+            fields.push(PacketField {
+                field: Field {
                     attrs: vec![],
                     vis: Visibility::Public(Pub::default()),
                     mutability: FieldMutability::None,
@@ -799,14 +1342,260 @@ fn parse_packet_elements(packet_entry: &PacketEntry, base_path: &Path) -> Result
                     colon_token: None,
                     ty,
                 },
-                field_size,
-            ));
+                size: field_size,
+                // unit conversions are only offered for values read from the device
+                si_conversion: if direction_str == "out" { si_conversion } else { None },
+                default,
+                no_default,
+            });
         }
     }
     Ok((in_fields, out_fields))
 }
 
-fn process_constant_group(items: &mut Vec<Item>, group: ConstantGroupEntry) {
+fn constant_group_metadata_expr(group: &ConstantGroupEntry) -> PyResult<Option<Expr>> {
+    let ty = if let Some(ty) = TfValueType::try_parse_type(group.r#type) {
+        ty
+    } else {
+        return Ok(None);
+    };
+    let name = group.name.to_case(Case::UpperCamel);
+    let element_type = ty.metadata_type();
+    let mut constants = Vec::<Expr>::with_capacity(group.constants.len());
+    for (constant_name, value) in &group.constants {
+        let constant_name = constant_name.to_case(Case::UpperCamel);
+        let value = match ty {
+            TfValueType::Char => value.extract::<char>()? as i64,
+            TfValueType::Bool => value.extract::<bool>()? as i64,
+            _ => value.extract::<i64>()?,
+        };
+        constants.push(parse_quote!(crate::metadata::ConstantMetadata { name: #constant_name, value: #value }));
+    }
+    Ok(Some(parse_quote!(crate::metadata::ConstantGroupMetadata {
+        name: #name,
+        element_type: #element_type,
+        constants: &[#(#constants),*],
+    })))
+}
+
+fn packet_metadata_expr(function_id: u8, packet_entry: &PacketEntry) -> PyResult<Option<Expr>> {
+    let kind: Expr = match TfPacketType::try_parse_type(packet_entry.r#type) {
+        Some(TfPacketType::Function) => parse_quote!(crate::metadata::PacketKind::Function),
+        Some(TfPacketType::Callback) => parse_quote!(crate::metadata::PacketKind::Callback),
+        _ => return Ok(None),
+    };
+    let mut elements = Vec::<Expr>::with_capacity(packet_entry.elements.len());
+    for element_entry in &packet_entry.elements {
+        let element_tuple = element_entry.downcast::<PyTuple>()?;
+        let name = element_tuple.get_item(0)?.to_string().to_case(Case::Snake);
+        let element_type = if let Some(ty) = TfValueType::try_parse_type(element_tuple.get_item(1)?.extract()?) {
+            ty.metadata_type()
+        } else {
+            return Ok(None);
+        };
+        let count = usize::extract(element_tuple.get_item(2)?)?;
+        let direction: Expr = if element_tuple.get_item(3)?.extract::<&str>()? == "in" {
+            parse_quote!(crate::metadata::Direction::In)
+        } else {
+            parse_quote!(crate::metadata::Direction::Out)
+        };
+        let details = if element_tuple.len() > 4 { element_tuple.get_item(4)?.downcast::<PyDict>().ok() } else { None };
+        let (unit, si_unit, constant_group) = if let Some(details) = details {
+            let unit = string_from_dict(details, "unit")?.filter(|unit| *unit != "dynamic" && *unit != "unknown");
+            let si_unit = SiConversion::from_element_details(details)?;
+            let constant_group = string_from_dict(details, "constant_group")?.map(|group| group.to_case(Case::UpperCamel));
+            (unit, si_unit, constant_group)
+        } else {
+            (None, None, None)
+        };
+        let unit = option_expr(unit.map(|unit| parse_quote!(#unit)));
+        let si_unit = option_expr(si_unit.map(|SiConversion { symbol, factor }| {
+            let factor = proc_macro2::Literal::f64_suffixed(factor);
+            parse_quote!(crate::metadata::SiUnit { symbol: #symbol, factor: #factor })
+        }));
+        let constant_group = option_expr(constant_group.map(|group| parse_quote!(#group)));
+        elements.push(parse_quote!(crate::metadata::ElementMetadata {
+            name: #name,
+            element_type: #element_type,
+            count: #count,
+            direction: #direction,
+            unit: #unit,
+            si_unit: #si_unit,
+            constant_group: #constant_group,
+        }));
+    }
+    let name = packet_entry.name.to_case(Case::Snake);
+    Ok(Some(parse_quote!(crate::metadata::PacketMetadata {
+        function_id: #function_id,
+        name: #name,
+        kind: #kind,
+        elements: &[#(#elements),*],
+    })))
+}
+
+/// Splits a device name like `Temperature V2` into its family and hardware version.
+fn hardware_version(name: &str) -> (&str, u8) {
+    if let Some((family, version)) = name.rsplit_once(" V") {
+        if let Ok(version) = version.parse() {
+            return (family, version);
+        }
+    }
+    (name, 1)
+}
+
+/// Generates the accessors describing the kind, family, features and documentation of each device.
+fn device_identifier_details(devices: &[(Ident, &str, &str, Vec<&str>)]) -> ItemImpl {
+    let versions =
+        devices.iter().map(|(variant, name, category, _)| ((hardware_version(name), *category), variant)).collect::<HashMap<_, _>>();
+    let mut kind_arms = Vec::new();
+    let mut comcu_arms = Vec::new();
+    let mut family_arms = Vec::new();
+    let mut version_arms = Vec::new();
+    let mut predecessor_arms = Vec::new();
+    let mut successor_arms = Vec::new();
+    let mut feature_arms = Vec::new();
+    let mut docs_arms = Vec::new();
+    for (variant, name, category, features) in devices {
+        let kind = create_ident(&category.to_case(Case::UpperCamel));
+        kind_arms.push(parse_quote!(DeviceIdentifier::#variant => crate::metadata::DeviceKind::#kind));
+        let comcu = features.contains(&"comcu_bricklet");
+        comcu_arms.push(parse_quote!(DeviceIdentifier::#variant => #comcu));
+        let (family, version) = hardware_version(name);
+        family_arms.push(parse_quote!(DeviceIdentifier::#variant => #family));
+        version_arms.push(parse_quote!(DeviceIdentifier::#variant => #version));
+        let predecessor = option_expr(
+            version
+                .checked_sub(1)
+                .and_then(|version| versions.get(&((family, version), *category)))
+                .map(|other| parse_quote!(DeviceIdentifier::#other)),
+        );
+        predecessor_arms.push(parse_quote!(DeviceIdentifier::#variant => #predecessor));
+        let successor = option_expr(versions.get(&((family, version + 1), *category)).map(|other| parse_quote!(DeviceIdentifier::#other)));
+        successor_arms.push(parse_quote!(DeviceIdentifier::#variant => #successor));
+        feature_arms.push(parse_quote!(DeviceIdentifier::#variant => &[#(#features),*]));
+        let docs_url = option_expr(match *category {
+            "Brick" | "Bricklet" => {
+                let url = format!("https://www.tinkerforge.com/en/doc/Hardware/{category}s/{}.html", name.replace(' ', "_"));
+                let url = if *category == "Brick" { url.replace(".html", "_Brick.html") } else { url };
+                Some(parse_quote!(#url))
+            }
+            _ => None,
+        });
+        docs_arms.push(parse_quote!(DeviceIdentifier::#variant => #docs_url));
+    }
+    let kind_match = match_self(kind_arms);
+    let comcu_match = match_self(comcu_arms);
+    let family_match = match_self(family_arms);
+    let version_match = match_self(version_arms);
+    let predecessor_match = match_self(predecessor_arms);
+    let successor_match = match_self(successor_arms);
+    let feature_match = match_self(feature_arms);
+    let docs_match = match_self(docs_arms);
+    parse_quote!(
+        impl DeviceIdentifier {
+            pub fn kind(self) -> crate::metadata::DeviceKind {
+                #kind_match
+            }
+            /// Whether the device runs its firmware on its own co-processor and can be flashed through the Brick.
+            pub fn has_comcu(self) -> bool {
+                #comcu_match
+            }
+            /// Name without hardware version, shared by all versions of a device.
+            pub fn family(self) -> &'static str {
+                #family_match
+            }
+            /// Hardware version within the family, starting at 1.
+            pub fn hardware_version(self) -> u8 {
+                #version_match
+            }
+            /// The previous hardware version of this device, if any.
+            pub fn predecessor(self) -> Option<DeviceIdentifier> {
+                #predecessor_match
+            }
+            /// The next hardware version of this device, if any.
+            pub fn successor(self) -> Option<DeviceIdentifier> {
+                #successor_match
+            }
+            /// Common features implemented by this device, as named in the configs.
+            pub fn features(self) -> &'static [&'static str] {
+                #feature_match
+            }
+            /// Link to the hardware documentation.
+            pub fn docs_url(self) -> Option<&'static str> {
+                #docs_match
+            }
+        }
+    )
+}
+
+/// Initial response expected flag of a function, following `Packet.get_response_expected` of the common generator.
+fn response_expected_flag(packet: &PyAny, packet_entry: &PacketEntry) -> PyResult<Option<Expr>> {
+    if TfPacketType::try_parse_type(packet_entry.r#type) != Some(TfPacketType::Function) {
+        return Ok(None);
+    }
+    let mut has_response = false;
+    for element in &packet_entry.elements {
+        has_response |= element.get_item(3)?.extract::<&str>()? == "out";
+    }
+    let streams_in = match packet.get_item("high_level") {
+        Ok(high_level) => high_level.downcast::<PyDict>().is_ok_and(|high_level| high_level.contains("stream_in").unwrap_or(false)),
+        Err(_) => false,
+    };
+    let configures_callback = packet_entry.doc.get_item(0)?.extract::<&str>()? == "ccf";
+    let configured = packet.get_item("response_expected").ok().and_then(|flag| flag.extract::<&str>().ok()) == Some("true");
+    Ok(Some(if has_response {
+        parse_quote!(crate::device::ResponseExpectedFlag::AlwaysTrue)
+    } else if streams_in || configures_callback || configured {
+        parse_quote!(crate::device::ResponseExpectedFlag::True)
+    } else {
+        parse_quote!(crate::device::ResponseExpectedFlag::False)
+    }))
+}
+
+/// Functions of the `device` feature, which have no packet but are answered from the state of the device.
+fn virtual_function(packet: &PyAny) -> PyResult<Option<ImplItemFn>> {
+    let doc = packet.get_item("doc")?.get_item(1)?.get_item("de").map(|doc| doc.to_string()).unwrap_or_default();
+    Ok(Some(match packet.get_item("name")?.extract::<&str>()? {
+        "Get API Version" => parse_quote!(
+            #[doc = #doc]
+            pub fn get_api_version(&self) -> [u8; 3] {
+                self.device.api_version
+            }
+        ),
+        "Get Response Expected" => parse_quote!(
+            #[doc = #doc]
+            pub fn get_response_expected(&self, function_id: u8) -> Result<bool, crate::device::GetResponseExpectedError> {
+                self.device.get_response_expected(function_id)
+            }
+        ),
+        "Set Response Expected" => parse_quote!(
+            #[doc = #doc]
+            pub fn set_response_expected(&mut self, function_id: u8, response_expected: bool) -> Result<(), crate::device::SetResponseExpectedError> {
+                self.device.set_response_expected(function_id, response_expected)
+            }
+        ),
+        "Set Response Expected All" => parse_quote!(
+            #[doc = #doc]
+            pub fn set_response_expected_all(&mut self, response_expected: bool) {
+                self.device.set_response_expected_all(response_expected)
+            }
+        ),
+        name => {
+            println!("cargo:warning=Unknown virtual function: {name}");
+            return Ok(None);
+        }
+    }))
+}
+
+fn option_expr(value: Option<Expr>) -> Expr {
+    if let Some(value) = value {
+        parse_quote!(Some(#value))
+    } else {
+        parse_quote!(None)
+    }
+}
+
+fn process_constant_group<'a>(items: &mut Vec<Item>, constant_groups: &mut ConstantGroupLookup<'a>, group: ConstantGroupEntry<'a>) {
     let camel_name = group.name.to_case(Case::UpperCamel);
     println!("Constant group: {}", group.name);
     let ty = if let Some(ty) = TfValueType::try_parse_type(group.r#type) {
@@ -815,10 +1604,11 @@ fn process_constant_group(items: &mut Vec<Item>, group: ConstantGroupEntry) {
         return;
     };
     let enum_name_ident = create_ident(&camel_name);
+    constant_groups.insert(group.name.to_string(), group.constants.clone());
     let mut variants: Punctuated<Variant, Comma> = Default::default();
     let mut encode_arms = vec![];
     let mut parse_arms = vec![];
-    for (name, value) in group.constants {
+    for &(name, value) in group.constants.iter() {
         let variant_ident = create_ident(&name.to_case(Case::UpperCamel));
         variants.push(parse_quote!(#variant_ident));
         let value = ty.parse_token_value(value).expect("Cannot parse python value {value:?}");
@@ -827,6 +1617,7 @@ fn process_constant_group(items: &mut Vec<Item>, group: ConstantGroupEntry) {
     }
     items.push(parse_quote!(
         #[derive(Copy,Clone,Eq,PartialEq,Debug)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub enum #enum_name_ident{
             #variants
         }
@@ -871,9 +1662,43 @@ fn process_constant_group(items: &mut Vec<Item>, group: ConstantGroupEntry) {
             fn bytes_expected() -> usize{
                 #type_size
             }
+            fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, crate::converting_receiver::BrickletError> {
+                #ty::try_from_le_byte_slice(bytes)?.try_into().map_err(|()| crate::converting_receiver::BrickletError::InvalidParameter)
+            }
         }
     ));
 
+    // request structs containing enum arrays are serialized element-wise, the baseline did not compile without these
+    items.push(parse_quote!(
+        impl<const N: usize> crate::byte_converter::ToBytes for [#enum_name_ident; N] {
+            fn write_to_slice(self, target: &mut [u8]) {
+                for (i, value) in self.into_iter().enumerate() {
+                    value.write_to_slice(&mut target[i * #type_size..(i + 1) * #type_size]);
+                }
+            }
+        }
+    ));
+    items.push(parse_quote!(
+        impl<const N: usize> crate::byte_converter::ToBytes for [crate::byte_converter::ParsedOrRaw<#enum_name_ident, #ty>; N] {
+            fn write_to_slice(self, target: &mut [u8]) {
+                for (i, value) in self.into_iter().enumerate() {
+                    value.write_to_slice(&mut target[i * #type_size..(i + 1) * #type_size]);
+                }
+            }
+        }
+    ));
+    // only a zero-valued constant matches the zeroed request a device would otherwise receive
+    if let Some(zero_variant) = zero_constant(&group.constants) {
+        let zero_variant = create_ident(&zero_variant.to_case(Case::UpperCamel));
+        items.push(parse_quote!(
+            impl Default for #enum_name_ident {
+                fn default() -> Self {
+                    #enum_name_ident::#zero_variant
+                }
+            }
+        ));
+    }
+
     items.push(Item::Impl(parse_quote!(
         impl std::convert::TryInto<#enum_name_ident> for #ty {
             type Error = ();
@@ -884,6 +1709,218 @@ fn process_constant_group(items: &mut Vec<Item>, group: ConstantGroupEntry) {
     )));
 }
 
+/// Returns the name of the zero-valued constant of a constant group, if there is one.
+fn zero_constant<'a>(constants: &[(&'a str, &PyAny)]) -> Option<&'a str> {
+    constants.iter().find(|(_, value)| matches!(value.extract::<i64>(), Ok(0))).map(|(name, _)| *name)
+}
+
+fn insert_firmware_check(function: &mut ImplItemFn, since_firmware: &SinceFirmware, device_name: &str) {
+    if let Some([major, minor, patch]) = since_firmware.required_version(device_name) {
+        let function_name = function.sig.ident.to_string();
+        function.block.stmts.insert(
+            0,
+            parse_quote!(self.device.check_firmware_version(#function_name, crate::ip_connection::Version::new(#major, #minor, #patch))?;),
+        );
+    }
+}
+
+/// Generates the handler trait to be implemented by emulated devices, the dispatch of requests to it and the emitter
+/// for callbacks.
+fn server_items(device_struct_name: &Ident, raw_package_name: &str, mut packets: Vec<ServerPacket>) -> Vec<Item> {
+    packets.sort_by_key(|packet| packet.function_id);
+    let camel_name = raw_package_name.to_case(Case::UpperCamel);
+    let handler_name = create_ident(&format!("{camel_name}Handler"));
+    let emitter_name = create_ident(&format!("{camel_name}CallbackEmitter"));
+    let mut handler_functions = Vec::<TraitItem>::new();
+    let mut dispatch_arms = Vec::<Arm>::new();
+    let mut emitter_functions = Vec::<ImplItemFn>::new();
+    for ServerPacket { function_id, name, doc, callback, request, response } in packets {
+        if callback {
+            emitter_functions.push(if let Some((ty, size)) = response {
+                parse_quote!(
+                    #[doc = #doc]
+                    pub fn #name(&self, value: #ty) -> usize {
+                        let mut payload = vec![0; #size];
+                        crate::byte_converter::ToBytes::write_to_slice(value, &mut payload);
+                        self.emitter.emit(#function_id, payload)
+                    }
+                )
+            } else {
+                parse_quote!(
+                    #[doc = #doc]
+                    pub fn #name(&self) -> usize {
+                        self.emitter.emit(#function_id, Vec::new())
+                    }
+                )
+            });
+            continue;
+        }
+        let response_type = response.as_ref().map(|(ty, _)| ty.clone()).unwrap_or_else(|| parse_quote!(()));
+        let mut statements = Vec::<Stmt>::new();
+        if let Some((ty, size)) = request {
+            handler_functions.push(parse_quote!(
+                #[doc = #doc]
+                #[allow(async_fn_in_trait)]
+                async fn #name(&mut self, _request: #ty) -> Result<#response_type, crate::converting_receiver::BrickletError> {
+                    Err(crate::converting_receiver::BrickletError::FunctionNotSupported)
+                }
+            ));
+            statements.push(parse_quote!(
+                if payload.len() < #size {
+                    return Err(crate::converting_receiver::BrickletError::InvalidParameter);
+                }
+            ));
+            statements.push(parse_quote!(
+                let request = <#ty as crate::byte_converter::FromByteSlice>::try_from_le_byte_slice(&payload[0..#size])?;
+            ));
+            statements.push(parse_quote!(let response = handler.#name(request).await?;));
+        } else {
+            handler_functions.push(parse_quote!(
+                #[doc = #doc]
+                #[allow(async_fn_in_trait)]
+                async fn #name(&mut self) -> Result<#response_type, crate::converting_receiver::BrickletError> {
+                    Err(crate::converting_receiver::BrickletError::FunctionNotSupported)
+                }
+            ));
+            statements.push(parse_quote!(let response = handler.#name().await?;));
+        }
+        if let Some((_, size)) = response {
+            statements.push(parse_quote!(let mut result = vec![0; #size];));
+            statements.push(parse_quote!(crate::byte_converter::ToBytes::write_to_slice(response, &mut result);));
+            statements.push(Stmt::Expr(parse_quote!(Ok(result)), None));
+        } else {
+            statements.push(parse_quote!(let () = response;));
+            statements.push(Stmt::Expr(parse_quote!(Ok(Vec::new())), None));
+        }
+        dispatch_arms.push(parse_quote!(#function_id => { #(#statements)* }));
+    }
+    dispatch_arms.push(parse_quote!(_ => Err(crate::converting_receiver::BrickletError::FunctionNotSupported)));
+    let uses_payload = dispatch_arms.iter().any(|arm| arm.body.to_token_stream().to_string().contains("payload"));
+    let payload_name = create_ident(if uses_payload { "payload" } else { "_payload" });
+    let handler_doc = format!(
+        " Server side of [`{device_struct_name}`], implemented by emulated devices. Functions which are not implemented answer with [`FunctionNotSupported`](crate::converting_receiver::BrickletError::FunctionNotSupported)."
+    );
+    let emitter_doc = format!(" Sends the callbacks of an emulated [`{device_struct_name}`].");
+    vec![
+        parse_quote!(
+            #[cfg(feature = "server")]
+            #[doc = #handler_doc]
+            pub trait #handler_name {
+                #(#handler_functions)*
+            }
+        ),
+        parse_quote!(
+            /// Decodes a request to this device, passes it to the handler and encodes the response.
+            #[cfg(feature = "server")]
+            pub async fn dispatch<H: #handler_name>(
+                handler: &mut H,
+                function_id: u8,
+                #payload_name: &[u8],
+            ) -> Result<Vec<u8>, crate::converting_receiver::BrickletError> {
+                match function_id {
+                    #(#dispatch_arms)*
+                }
+            }
+        ),
+        parse_quote!(
+            #[cfg(feature = "server")]
+            #[doc = #emitter_doc]
+            #[derive(Clone, Debug)]
+            pub struct #emitter_name {
+                emitter: crate::server::CallbackEmitter,
+            }
+        ),
+        parse_quote!(
+            #[cfg(feature = "server")]
+            impl #emitter_name {
+                pub fn new(emitter: crate::server::CallbackEmitter) -> #emitter_name {
+                    Self { emitter }
+                }
+                pub fn uid(&self) -> crate::base58::Uid {
+                    self.emitter.uid()
+                }
+                #(#emitter_functions)*
+            }
+        ),
+    ]
+}
+
+/// Declaration of a device function in the device trait.
+fn api_trait_item(function: &ImplItemFn) -> TraitItem {
+    let mut attrs: Vec<Attribute> = function.attrs.iter().filter(|attr| attr.path().is_ident("doc")).cloned().collect();
+    if function.sig.asyncness.is_some() {
+        attrs.push(parse_quote!(#[allow(async_fn_in_trait)]));
+    }
+    TraitItem::Fn(TraitItemFn { attrs, sig: function.sig.clone(), default: None, semi_token: None })
+}
+
+/// Implements a function of the device trait by calling the inherent function of the device.
+fn delegate_function(function: &ImplItemFn) -> ImplItemFn {
+    let sig = &function.sig;
+    let name = &sig.ident;
+    let args = sig.inputs.iter().filter_map(|arg| if let FnArg::Typed(arg) = arg { Some(&arg.pat) } else { None });
+    let call: Expr = parse_quote!(Self::#name(self #(, #args)*));
+    let body: Expr = if sig.asyncness.is_some() { parse_quote!(#call.await) } else { call };
+    parse_quote!(
+        #sig {
+            #body
+        }
+    )
+}
+
+/// Implements a function on a mock: requests are recorded and answered by the scripted values, callback streams are
+/// fed by injected values. Synchronous functions only access the device state, which the mock provides as well.
+fn mock_function(function: &ImplItemFn) -> ImplItemFn {
+    let sig = &function.sig;
+    if sig.asyncness.is_none() {
+        return strip_docs(function);
+    }
+    let name = sig.ident.to_string();
+    let request: Expr = match sig.inputs.iter().nth(1) {
+        Some(FnArg::Typed(PatType { pat, ty, .. })) if matches!(**ty, Type::Reference(_)) => parse_quote!(#pat.to_owned()),
+        Some(FnArg::Typed(PatType { pat, .. })) => parse_quote!(#pat),
+        _ => parse_quote!(()),
+    };
+    let body: Expr = match &sig.output {
+        ReturnType::Type(_, ty) => match stream_item_type(ty) {
+            Some(item) => parse_quote!(self.device.callback_stream::<#item>(#name)),
+            None => parse_quote!(self.device.call(#name, #request)),
+        },
+        ReturnType::Default => parse_quote!(self.device.call(#name, #request)),
+    };
+    parse_quote!(
+        #sig {
+            #body
+        }
+    )
+}
+
+fn strip_docs(function: &ImplItemFn) -> ImplItemFn {
+    let mut function = function.clone();
+    function.attrs.retain(|attr| !attr.path().is_ident("doc"));
+    function.vis = Visibility::Inherited;
+    function
+}
+
+/// Returns `T` of a `impl Stream<Item = T>`.
+fn stream_item_type(ty: &Type) -> Option<&Type> {
+    let Type::ImplTrait(impl_trait) = ty else {
+        return None;
+    };
+    impl_trait.bounds.iter().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let PathArguments::AngleBracketed(arguments) = &bound.path.segments.last()?.arguments else {
+            return None;
+        };
+        arguments.args.iter().find_map(|argument| match argument {
+            GenericArgument::AssocType(assoc) if assoc.ident == "Item" => Some(&assoc.ty),
+            _ => None,
+        })
+    })
+}
+
 fn create_ident(string: &str) -> Ident {
     if if string == "type" {
         true
@@ -902,13 +1939,16 @@ fn match_self(arms: Vec<Arm>) -> ExprMatch {
     ExprMatch { attrs: vec![], match_token: Default::default(), expr: Box::new(parse_quote!(self)), brace_token: Default::default(), arms }
 }
 
-fn append_data_object(items: &mut Vec<Item>, fields: &[(Field, usize)], struct_name: &Ident) -> usize {
+fn append_data_object(items: &mut Vec<Item>, fields: &[PacketField], struct_name: &Ident) -> usize {
     let mut reader_statements = Vec::<Stmt>::new();
+    let mut try_reader_statements = Vec::<Stmt>::new();
     let mut writer_statements = Vec::<Stmt>::new();
+    let mut try_writer_statements = Vec::<Stmt>::new();
     let mut initialization_fields = Punctuated::<FieldValue, Comma>::new();
     let mut offset = 0;
     let mut struct_fields = Punctuated::<Field, Comma>::new();
-    for (field, size) in fields.iter() {
+    let mut si_accessors = Vec::<ImplItem>::new();
+    for PacketField { field, size, si_conversion, .. } in fields.iter() {
         if let Some(field_name) = &field.ident {
             let offset_before: Lit = parse_quote!(#offset);
             offset += *size;
@@ -916,22 +1956,62 @@ fn append_data_object(items: &mut Vec<Item>, fields: &[(Field, usize)], struct_n
             let read_method_call =
                 static_method_call(&field.ty, parse_quote!(from_le_byte_slice), parse_quote!((&bytes[#offset_before..#offset_after])));
             reader_statements.push(parse_quote!(let #field_name = #read_method_call;));
+            let try_read_method_call =
+                static_method_call(&field.ty, parse_quote!(try_from_le_byte_slice), parse_quote!((&bytes[#offset_before..#offset_after])));
+            try_reader_statements.push(parse_quote!(let #field_name = #try_read_method_call?;));
             initialization_fields.push(parse_quote!(#field_name));
             writer_statements.push(parse_quote!(self.#field_name.write_to_slice(&mut target[#offset_before..#offset_after]);));
-            struct_fields.push(field.clone());
+            try_writer_statements
+                .push(parse_quote!(self.#field_name.try_write_to_slice(#size, &mut target[#offset_before..#offset_after])?;));
+            let mut field = field.clone();
+            if let Type::Array(_) = field.ty {
+                // serde only implements arrays up to 32 elements
+                field.attrs.push(parse_quote!(#[cfg_attr(feature = "serde", serde(with = "crate::byte_converter::serde_array"))]));
+            }
+            struct_fields.push(field);
+            if let Some(si_conversion) = si_conversion {
+                let accessor_name = create_ident(&format!("{field_name}_si"));
+                let doc = format!(" Returns [`{field_name}`](Self::{field_name}) in {}.", si_conversion.symbol);
+                let converted = si_conversion.convert(parse_quote!(self.#field_name));
+                si_accessors.push(parse_quote!(
+                    #[doc = #doc]
+                    pub fn #accessor_name(&self) -> f64 {
+                        #converted
+                    }
+                ));
+            }
         }
     }
     let total_size: Lit = parse_quote!(#offset);
-    items.push(parse_quote!(
-        #[derive(Copy, Clone, PartialEq, Debug)]
+    let mut data_object: ItemStruct = parse_quote!(
+        #[derive(Clone, PartialEq, Debug)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
         pub struct #struct_name {
             #struct_fields
         }
-
-    ));
+    );
+    if !fields.iter().any(|PacketField { field, .. }| is_string(&field.ty)) {
+        data_object.attrs.insert(0, parse_quote!(#[derive(Copy)]));
+    }
+    items.push(Item::Struct(data_object));
+    if !si_accessors.is_empty() {
+        items.push(parse_quote!(
+            impl #struct_name {
+                #(#si_accessors)*
+            }
+        ));
+    }
 
     reader_statements.push(Stmt::Expr(parse_quote!(Self{#initialization_fields}), None));
     let read_fields = Block { brace_token: Default::default(), stmts: reader_statements };
+    try_reader_statements.insert(
+        0,
+        parse_quote!(if bytes.len() < #total_size {
+            return Err(crate::converting_receiver::BrickletError::InvalidParameter);
+        }),
+    );
+    try_reader_statements.push(Stmt::Expr(parse_quote!(Ok(Self{#initialization_fields})), None));
+    let try_read_fields = Block { brace_token: Default::default(), stmts: try_reader_statements };
     items.push(parse_quote!(
        impl crate::byte_converter::FromByteSlice for #struct_name {
        fn from_le_byte_slice(bytes: &[u8]) -> Self
@@ -939,17 +2019,27 @@ fn append_data_object(items: &mut Vec<Item>, fields: &[(Field, usize)], struct_n
        fn bytes_expected() -> usize {
          #total_size
        }
+       fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, crate::converting_receiver::BrickletError>
+               #try_read_fields
     }));
     let write_fields = Block { brace_token: Default::default(), stmts: writer_statements };
+    try_writer_statements.push(Stmt::Expr(parse_quote!(Ok(())), None));
+    let try_write_fields = Block { brace_token: Default::default(), stmts: try_writer_statements };
     items.push(parse_quote!(
          impl crate::byte_converter::ToBytes for #struct_name {
             fn write_to_slice(self, target: &mut [u8])
                 #write_fields
+            fn try_write_to_slice(self, _max_len: usize, target: &mut [u8]) -> Result<(), crate::converting_receiver::BrickletError>
+                #try_write_fields
         }
     ));
     offset
 }
 
+fn is_string(ty: &Type) -> bool {
+    matches!(ty, Type::Path(TypePath { qself: None, path }) if path.is_ident("String"))
+}
+
 fn static_method_call(ty: &Type, method: Ident, args: Punctuated<Expr, Comma>) -> Expr {
     if let Type::Path(TypePath { qself: None, path: Path { leading_colon: _, segments } }) = &ty {
         let seg = segments.last();
@@ -973,6 +2063,117 @@ fn static_method_call(ty: &Type, method: Ident, args: Punctuated<Expr, Comma>) -
     }
 }
 
+fn scalar_default_expr(ty: &TfValueType, constant_group: Option<(&Type, &[(&str, &PyAny)])>, value: &PyAny) -> PyResult<Option<Expr>> {
+    if let Some((enum_type, constants)) = constant_group {
+        for (name, constant_value) in constants {
+            if constant_value.eq(value)? {
+                let variant_ident = create_ident(&name.to_case(Case::UpperCamel));
+                return Ok(Some(parse_quote!(#enum_type::#variant_ident)));
+            }
+        }
+        println!("No constant matches default value {value}");
+        return Ok(None);
+    }
+    let value = match ty {
+        TfValueType::U8 => value.extract::<u8>()?.into_token_stream(),
+        TfValueType::I8 => value.extract::<i8>()?.into_token_stream(),
+        TfValueType::U16 => value.extract::<u16>()?.into_token_stream(),
+        TfValueType::I16 => value.extract::<i16>()?.into_token_stream(),
+        TfValueType::U32 => value.extract::<u32>()?.into_token_stream(),
+        TfValueType::I32 => value.extract::<i32>()?.into_token_stream(),
+        TfValueType::U64 => value.extract::<u64>()?.into_token_stream(),
+        TfValueType::I64 => value.extract::<i64>()?.into_token_stream(),
+        TfValueType::Bool => value.is_true()?.into_token_stream(),
+        TfValueType::Char => value.extract::<char>()?.into_token_stream(),
+        TfValueType::Float => value.extract::<f32>()?.into_token_stream(),
+        TfValueType::String => return Ok(None),
+    };
+    Ok(Some(parse_quote!(#value)))
+}
+
+fn string_default_expr(value: &PyAny, max_length: usize) -> PyResult<Option<Expr>> {
+    if let Ok(text) = value.downcast::<PyString>() {
+        let text = text.to_str()?.trim_end_matches('\0');
+        if !text.is_empty() && text.chars().count() <= max_length {
+            return Ok(Some(parse_quote!(String::from(#text))));
+        }
+    }
+    Ok(None)
+}
+
+fn array_default_expr(
+    ty: &TfValueType,
+    constant_group: Option<(&Type, &[(&str, &PyAny)])>,
+    value: &PyAny,
+    repeat_count: usize,
+) -> PyResult<Option<Expr>> {
+    let values = if let Ok(list) = value.downcast::<PyList>() {
+        if list.len() != repeat_count {
+            return Ok(None);
+        }
+        let mut values = Vec::with_capacity(repeat_count);
+        for entry in list {
+            if let Some(value) = scalar_default_expr(ty, constant_group, entry)? {
+                values.push(value);
+            } else {
+                return Ok(None);
+            }
+        }
+        values
+    } else if let Some(value) = scalar_default_expr(ty, constant_group, value)? {
+        return Ok(Some(parse_quote!([#value; #repeat_count])));
+    } else {
+        return Ok(None);
+    };
+    if let Some(first) = values.first() {
+        if values.iter().all(|v| v == first) {
+            return Ok(Some(parse_quote!([#first; #repeat_count])));
+        }
+    }
+    Ok(Some(parse_quote!([#(#values),*])))
+}
+
+fn append_default_impl(items: &mut Vec<Item>, fields: &[PacketField], struct_name: &Ident) {
+    let mut default_fields = Punctuated::<FieldValue, Comma>::new();
+    let mut builder_methods = Vec::<ImplItem>::new();
+    for PacketField { field, default, .. } in fields.iter() {
+        if let Some(field_name) = &field.ident {
+            let ty = &field.ty;
+            let value: Expr = if let Some(default) = default {
+                default.clone()
+            } else if let Type::Array(TypeArray { len, .. }) = ty {
+                parse_quote!([Default::default(); #len])
+            } else {
+                parse_quote!(Default::default())
+            };
+            default_fields.push(parse_quote!(#field_name: #value));
+            let method_name = create_ident(&format!("with_{}", field_name.to_string().trim_start_matches('_')));
+            let doc = format!(" Sets [`{field_name}`](Self::{field_name}).");
+            builder_methods.push(parse_quote!(
+                #[doc = #doc]
+                pub fn #method_name(mut self, #field_name: #ty) -> Self {
+                    self.#field_name = #field_name;
+                    self
+                }
+            ));
+        }
+    }
+    if !fields.iter().any(|field| field.no_default) {
+        items.push(parse_quote!(
+            impl Default for #struct_name {
+                fn default() -> Self {
+                    Self{#default_fields}
+                }
+            }
+        ));
+    }
+    items.push(parse_quote!(
+        impl #struct_name {
+            #(#builder_methods)*
+        }
+    ));
+}
+
 fn string_from_dict<'a>(dict: &'a PyDict, key: &str) -> PyResult<Option<&'a str>> {
     if let Some(entry) = dict.get_item(key)? {
         if let Ok(string_value) = entry.downcast::<PyString>() {
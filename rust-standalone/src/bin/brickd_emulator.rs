@@ -0,0 +1,24 @@
+//! Serves the devices of a scenario file like a Brick Daemon.
+//!
+//! Usage: `brickd-emulator <scenario.toml|scenario.json> [address]`, the address defaults to `0.0.0.0:4223`.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::emulator::{Emulator, Scenario};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:4223";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let Some(scenario) = args.next() else {
+        eprintln!("Usage: brickd-emulator <scenario> [address]");
+        return Ok(ExitCode::FAILURE);
+    };
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let scenario = Scenario::load(&scenario)?;
+    let device_count = scenario.devices.len();
+    let emulator = Emulator::bind(address, scenario).await?;
+    println!("Emulating {device_count} devices on {}", emulator.local_addr());
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,24 @@
+//! Shares one Brick Daemon or Master Extension between many clients.
+//!
+//! Usage: `brickd-proxy <config.toml|config.json> [address]`, the address defaults to `0.0.0.0:4223`.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::proxy::{Proxy, ProxyConfig};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:4223";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let Some(config) = args.next() else {
+        eprintln!("Usage: brickd-proxy <config> [address]");
+        return Ok(ExitCode::FAILURE);
+    };
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let config = ProxyConfig::load(&config)?;
+    let upstream = config.upstream.clone();
+    let proxy = Proxy::bind(address, config).await?;
+    println!("Forwarding clients on {} to {upstream}", proxy.local_addr());
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,35 @@
+//! Prints the packets of a recording or pcap capture, decoded with the metadata of the bindings.
+//!
+//! Usage: `tfp-dump <capture> [uid=Device ...]`. Devices are learnt from the enumerate callbacks and `get_identity`
+//! responses in the capture, others can be given by their uid and name, e.g. `ZQH=TemperatureV2Bricklet`.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::{base58::Uid, dissector::Dissector, metadata, recording::Recording};
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let Some(capture) = args.next() else {
+        eprintln!("Usage: tfp-dump <capture> [uid=Device ...]");
+        return Ok(ExitCode::FAILURE);
+    };
+    let mut dissector = Dissector::default();
+    for device in args {
+        let Some((uid, name)) = device.split_once('=') else {
+            eprintln!("Expected uid=Device instead of {device}");
+            return Ok(ExitCode::FAILURE);
+        };
+        let Some(device) = metadata::devices().iter().find(|device| format!("{:?}", device.identifier) == name) else {
+            eprintln!("Unknown device {name}");
+            return Ok(ExitCode::FAILURE);
+        };
+        dissector.register(uid.parse::<Uid>()?, device.identifier);
+    }
+    for packet in Recording::load(&capture)?.packets {
+        let elapsed = packet.elapsed.as_secs_f64();
+        match dissector.dissect(packet.direction, &packet.bytes) {
+            Ok(dissected) => println!("{elapsed:12.6} {dissected}"),
+            Err(error) => println!("{elapsed:12.6} {error}"),
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
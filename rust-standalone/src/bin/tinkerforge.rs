@@ -0,0 +1,459 @@
+//! Calls functions of devices and watches their callbacks from the command line, like the `tinkerforge` tool of the
+//! shell bindings but as a single binary built on the [`metadata`](tinkerforge_async::metadata) of the bindings.
+//!
+//! Usage: `tinkerforge [options] enumerate|call|dispatch|completions ...`. Devices, functions, callbacks and constants
+//! are named in kebab case, e.g. `tinkerforge call temperature-v2-bricklet ZQH get-temperature`.
+use std::{
+    env,
+    error::Error,
+    io::{self, Write},
+    process::{Command, ExitCode},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde_json::{Map, Value};
+use tinkerforge_async::{
+    base58::Uid,
+    byte_converter::ParsedOrRaw,
+    dynamic::{DeviceDefinition, DynamicDevice, ElementDefinition},
+    ip_connection::{async_io::AsyncIpConnection, EnumerationType, Version},
+    metadata::{self, DeviceMetadata, Direction, ElementType, PacketKind},
+};
+use tokio_stream::{Stream, StreamExt};
+
+const USAGE: &str = "Usage: tinkerforge [options] <command> [arguments]
+
+Options:
+  --host <host>               host of the Brick Daemon, default: localhost
+  --port <port>               port of the Brick Daemon, default: 4223
+  --secret <secret>           secret for authentication
+  --timeout <ms>              maximum time to wait for a response, default: 2500
+  --json                      print each response as a JSON object on a line of its own
+  --item-separator <text>     separator of array items, default: ,
+  --group-separator <text>    separator of responses, default: a newline
+  --no-symbolic-response      print constants by their value instead of their name
+
+Commands:
+  enumerate [--duration <ms>] [--types <types>] [--execute <command>]
+  call <device> <uid> <function> [arguments] [--execute <command>]
+  call <device> --list-functions
+  dispatch <device> <uid> <callback> [--duration <ms>] [--execute <command>]
+  dispatch <device> --list-callbacks
+  call|dispatch --list-devices
+  completions bash|zsh
+
+Durations are given in milliseconds or as exit-after-first or forever. --execute runs a shell command for each
+response, {name} placeholders are replaced by the values of the response.";
+
+struct Options {
+    host: String,
+    port: u16,
+    secret: Option<String>,
+    timeout: Duration,
+    json: bool,
+    item_separator: String,
+    group_separator: String,
+    symbolic: bool,
+}
+
+/// Named values of a response, printed as lines of `name=value` or as JSON object.
+type Record = Vec<(String, Value)>;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        // e.g. piped into head
+        Err(error) if error.downcast_ref::<io::Error>().is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("tinkerforge: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(mut args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut options = Options {
+        host: "localhost".to_string(),
+        port: 4223,
+        secret: None,
+        timeout: Duration::from_millis(2500),
+        json: false,
+        item_separator: ",".to_string(),
+        group_separator: "\n".to_string(),
+        symbolic: true,
+    };
+    while args.first().is_some_and(|arg| arg.starts_with("--")) {
+        let option = args.remove(0);
+        match option.as_str() {
+            "--json" => options.json = true,
+            "--no-symbolic-response" => options.symbolic = false,
+            _ => {
+                let value = (!args.is_empty()).then(|| args.remove(0)).ok_or_else(|| format!("{option} needs a value"))?;
+                match option.as_str() {
+                    "--host" => options.host = value,
+                    "--port" => options.port = value.parse()?,
+                    "--secret" => options.secret = Some(value),
+                    "--timeout" => options.timeout = Duration::from_millis(value.parse()?),
+                    "--item-separator" => options.item_separator = value,
+                    "--group-separator" => options.group_separator = value,
+                    _ => return Err(format!("unknown option {option}").into()),
+                }
+            }
+        }
+    }
+    if args.is_empty() {
+        return Err("missing command".into());
+    }
+    let command = args.remove(0);
+    let execute = take_option(&mut args, "--execute")?;
+    match command.as_str() {
+        "enumerate" => {
+            let duration = parse_duration(take_option(&mut args, "--duration")?, Some(Duration::from_millis(250)))?;
+            let types = take_option(&mut args, "--types")?.unwrap_or_else(|| "available".to_string());
+            let types = types.split(&options.item_separator).map(str::to_string).collect::<Vec<_>>();
+            expect_arguments(&args, 0)?;
+            let mut connection = connect(&options).await?;
+            let responses = connection.enumerate().await?.filter(move |response| {
+                let enumeration_type = kebab_case(&format!("{:?}", response.enumeration_type));
+                types.iter().any(|name| name == "all" || *name == enumeration_type)
+            });
+            let options = &options;
+            output(
+                options,
+                execute.as_deref(),
+                duration,
+                responses.map(|response| {
+                    let device_identifier = match response.device_identifier {
+                        ParsedOrRaw::Parsed(identifier) if options.symbolic => Value::from(device_name(metadata::device(identifier))),
+                        ParsedOrRaw::Parsed(identifier) => Value::from(Into::<u16>::into(identifier)),
+                        ParsedOrRaw::Raw(value) => Value::from(value),
+                    };
+                    let enumeration_type = match response.enumeration_type {
+                        enumeration_type if options.symbolic => Value::from(kebab_case(&format!("{enumeration_type:?}"))),
+                        EnumerationType::Available => Value::from(0),
+                        EnumerationType::Connected => Value::from(1),
+                        EnumerationType::Disconnected => Value::from(2),
+                        EnumerationType::Unknown => Value::Null,
+                    };
+                    let version = |version: Version| Value::from(vec![version.major(), version.minor(), version.patch()]);
+                    vec![
+                        ("uid".to_string(), Value::from(uid_text(response.uid))),
+                        ("connected-uid".to_string(), Value::from(uid_text(response.connected_uid))),
+                        ("position".to_string(), Value::from(if response.position == '\0' { 'x' } else { response.position }.to_string())),
+                        ("hardware-version".to_string(), version(response.hardware_version)),
+                        ("firmware-version".to_string(), version(response.firmware_version)),
+                        ("device-identifier".to_string(), device_identifier),
+                        ("enumeration-type".to_string(), enumeration_type),
+                    ]
+                }),
+            )
+            .await
+        }
+        "call" | "dispatch" => {
+            let kind = if command == "call" { PacketKind::Function } else { PacketKind::Callback };
+            let duration = parse_duration(take_option(&mut args, "--duration")?, None)?;
+            if take_flag(&mut args, "--list-devices") {
+                expect_arguments(&args, 0)?;
+                for device in metadata::devices() {
+                    writeln!(io::stdout(), "{}", device_name(device))?;
+                }
+                return Ok(());
+            }
+            let list = take_flag(&mut args, "--list-functions") | take_flag(&mut args, "--list-callbacks");
+            let device_name = args.first().ok_or("missing device")?;
+            let device = metadata::devices()
+                .iter()
+                .find(|device| self::device_name(device) == *device_name)
+                .ok_or_else(|| format!("unknown device {device_name}, see --list-devices"))?;
+            let definition = Arc::new(DeviceDefinition::from(*device));
+            if list {
+                expect_arguments(&args, 1)?;
+                for packet in definition.packets.iter().filter(|packet| packet.kind == kind) {
+                    let direction = if kind == PacketKind::Function { Direction::In } else { Direction::Out };
+                    let elements = packet.elements.iter().filter(|element| element.direction == direction);
+                    let elements = elements.map(|element| format!(" <{}>", kebab_case(&element.name))).collect::<String>();
+                    writeln!(io::stdout(), "{}{elements}", kebab_case(&packet.name))?;
+                }
+                return Ok(());
+            }
+            let uid = args.get(1).ok_or("missing uid")?.parse::<Uid>()?;
+            let packet_name =
+                args.get(2).ok_or_else(|| format!("missing {}", if kind == PacketKind::Function { "function" } else { "callback" }))?;
+            let packet = definition
+                .packet(kind, &packet_name.replace('-', "_"))
+                .ok_or_else(|| format!("{device_name} has no {packet_name}, see --list-{}s", kebab_case(&format!("{kind:?}"))))?;
+            let elements = packet.elements.iter().filter(|element| element.direction == Direction::Out).collect::<Vec<_>>();
+            let mut device = DynamicDevice::new(uid, connect(&options).await?, definition.clone());
+            device.set_timeout(options.timeout);
+            let options = &options;
+            let to_record = move |response: Value| -> Record {
+                elements
+                    .iter()
+                    .map(|element| (kebab_case(&element.name), output_value(options, element, &response[element.name.as_str()])))
+                    .collect()
+            };
+            if kind == PacketKind::Function {
+                let inputs = packet.elements.iter().filter(|element| element.direction == Direction::In).collect::<Vec<_>>();
+                expect_arguments(&args, 3 + inputs.len())?;
+                let mut request = Map::new();
+                for (element, argument) in inputs.into_iter().zip(&args[3..]) {
+                    request.insert(element.name.clone(), input_value(options, element, argument)?);
+                }
+                let response = device.call(&packet.name, Value::Object(request)).await?;
+                output(options, execute.as_deref(), None, tokio_stream::once(to_record(response))).await
+            } else {
+                expect_arguments(&args, 3)?;
+                let stream = device.callback_stream(&packet.name).await?;
+                output(options, execute.as_deref(), duration, stream.map(to_record)).await
+            }
+        }
+        "completions" => {
+            expect_arguments(&args, 1)?;
+            match args[0].as_str() {
+                "bash" => writeln!(io::stdout(), "{}", bash_completion())?,
+                "zsh" => writeln!(io::stdout(), "autoload -U +X bashcompinit && bashcompinit\n{}", bash_completion())?,
+                shell => return Err(format!("no completions for {shell}, use bash or zsh").into()),
+            }
+            Ok(())
+        }
+        _ => Err(format!("unknown command {command}, see --help").into()),
+    }
+}
+
+async fn connect(options: &Options) -> Result<AsyncIpConnection, Box<dyn Error>> {
+    let mut connection = AsyncIpConnection::new((options.host.clone(), options.port)).await?;
+    if let Some(secret) = &options.secret {
+        connection.authenticate(secret).await?;
+    }
+    Ok(connection)
+}
+
+/// Removes `name <value>` from the arguments.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(index) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if index + 1 == args.len() {
+        return Err(format!("{name} needs a value").into());
+    }
+    args.remove(index);
+    Ok(Some(args.remove(index)))
+}
+
+/// Removes `name` from the arguments and returns whether it was given.
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let length = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != length
+}
+
+fn expect_arguments(args: &[String], count: usize) -> Result<(), Box<dyn Error>> {
+    match args.len() {
+        length if length < count => Err(format!("expected {} more arguments", count - length).into()),
+        length if length > count => Err(format!("unexpected argument {}", args[count]).into()),
+        _ => Ok(()),
+    }
+}
+
+/// `None` waits forever, a zero duration exits after the first response.
+fn parse_duration(duration: Option<String>, default: Option<Duration>) -> Result<Option<Duration>, Box<dyn Error>> {
+    Ok(match duration.as_deref() {
+        None => default,
+        Some("exit-after-first") => Some(Duration::ZERO),
+        Some("forever") => None,
+        Some(milliseconds) => Some(Duration::from_millis(milliseconds.parse()?)),
+    })
+}
+
+/// Prints or executes the records of a stream until the duration is over.
+async fn output(
+    options: &Options,
+    execute: Option<&str>,
+    duration: Option<Duration>,
+    records: impl Stream<Item = Record>,
+) -> Result<(), Box<dyn Error>> {
+    let deadline = tokio::time::Instant::now() + duration.unwrap_or_default();
+    tokio::pin!(records);
+    let mut first = true;
+    loop {
+        let record = match duration {
+            Some(Duration::ZERO) if !first => break,
+            Some(Duration::ZERO) | None => records.next().await,
+            Some(_) => match tokio::time::timeout_at(deadline, records.next()).await {
+                Ok(record) => record,
+                Err(_) => break,
+            },
+        };
+        let Some(record) = record else {
+            break;
+        };
+        let separate = !first;
+        first = false;
+        if let Some(command) = execute {
+            let command = format_command(options, command, &record)?;
+            Command::new("sh").arg("-c").arg(command).status()?;
+            continue;
+        }
+        let mut stdout = io::stdout().lock();
+        if options.json {
+            let object = record.into_iter().collect::<Map<_, _>>();
+            writeln!(stdout, "{}", Value::Object(object))?;
+        } else {
+            if separate {
+                write!(stdout, "{}", options.group_separator)?;
+            }
+            for (name, value) in &record {
+                writeln!(stdout, "{name}={}", format_value(options, value))?;
+            }
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// Replaces the `{name}` placeholders of a command by the values of a record, `{{` and `}}` escape braces.
+fn format_command(options: &Options, command: &str, record: &Record) -> Result<String, Box<dyn Error>> {
+    let mut formatted = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(index) = rest.find(['{', '}']) {
+        formatted.push_str(&rest[..index]);
+        let escaped = &rest[index..index + 1];
+        if rest[index + 1..].starts_with(escaped) {
+            formatted.push_str(escaped);
+            rest = &rest[index + 2..];
+            continue;
+        }
+        let end = rest[index..].find('}').filter(|_| escaped == "{").ok_or_else(|| format!("unbalanced braces in {command}"))?;
+        let name = &rest[index + 1..index + end];
+        let (_, value) =
+            record.iter().find(|(field, _)| field == name).ok_or_else(|| format!("invalid placeholder {{{name}}} in {command}"))?;
+        formatted.push_str(&format_value(options, value));
+        rest = &rest[index + end + 1..];
+    }
+    formatted.push_str(rest);
+    Ok(formatted)
+}
+
+fn format_value(options: &Options, value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(values) => values.iter().map(|value| format_value(options, value)).collect::<Vec<_>>().join(&options.item_separator),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Converts an argument into the value of an element for [`DynamicDevice::call`].
+fn input_value(options: &Options, element: &ElementDefinition, argument: &str) -> Result<Value, Box<dyn Error>> {
+    let invalid = || format!("invalid value {argument} for {}", kebab_case(&element.name));
+    if element.element_type == ElementType::String {
+        return Ok(Value::from(argument));
+    }
+    let scalar = |item: &str| -> Result<Value, String> {
+        let constant = element.extra.iter().filter_map(|extra| extra.constant_group.as_ref()).flat_map(|group| &group.constants);
+        if let Some(constant) = constant.clone().find(|constant| kebab_case(&constant.name) == item) {
+            return Ok(constant.value.clone());
+        }
+        let (negative, digits) = item.strip_prefix('-').map_or((false, item), |digits| (true, digits));
+        let integer = match digits.get(..2) {
+            Some("0x") => i64::from_str_radix(&digits[2..], 16),
+            Some("0o") => i64::from_str_radix(&digits[2..], 8),
+            Some("0b") => i64::from_str_radix(&digits[2..], 2),
+            _ => digits.parse(),
+        };
+        match element.element_type {
+            ElementType::Bool => item.parse::<bool>().map(Value::from).map_err(|_| invalid()),
+            ElementType::Float => item.parse::<f64>().map(Value::from).map_err(|_| invalid()),
+            ElementType::Char => Ok(Value::from(item)),
+            _ => integer.map(|value| Value::from(if negative { -value } else { value })).map_err(|_| invalid()),
+        }
+    };
+    if element.cardinality == 1 {
+        return Ok(scalar(argument)?);
+    }
+    Ok(Value::Array(argument.split(&options.item_separator).map(scalar).collect::<Result<_, _>>()?))
+}
+
+/// Converts a value returned by [`DynamicDevice`] into the form printed, constants are named in kebab case.
+fn output_value(options: &Options, element: &ElementDefinition, value: &Value) -> Value {
+    match value {
+        Value::Array(values) => Value::Array(values.iter().map(|value| output_value(options, element, value)).collect()),
+        Value::String(name) => {
+            let constant = element.extra.iter().filter_map(|extra| extra.constant_group.as_ref()).flat_map(|group| &group.constants);
+            match constant.clone().find(|constant| constant.name.to_lowercase().replace(' ', "_") == *name) {
+                Some(constant) if options.symbolic => Value::from(kebab_case(&constant.name)),
+                Some(constant) => constant.value.clone(),
+                None => value.clone(),
+            }
+        }
+        value => value.clone(),
+    }
+}
+
+/// Turns names like `Get Temperature`, `get_temperature` or `TemperatureV2` into `get-temperature`.
+fn kebab_case(name: &str) -> String {
+    let mut kebab = String::with_capacity(name.len() + 4);
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if c.is_uppercase() && previous_lowercase {
+            kebab.push('-');
+        }
+        previous_lowercase = c.is_lowercase() || c.is_ascii_digit();
+        match c {
+            ' ' | '_' => kebab.push('-'),
+            c => kebab.extend(c.to_lowercase()),
+        }
+    }
+    kebab
+}
+
+/// Name of a device on the command line, e.g. `temperature-v2-bricklet`.
+fn device_name(device: &DeviceMetadata) -> String {
+    kebab_case(&format!("{} {}", device.name, device.category))
+}
+
+fn uid_text(uid: Uid) -> String {
+    match u32::from(uid) {
+        0 => "0".to_string(),
+        _ => uid.to_string(),
+    }
+}
+
+fn bash_completion() -> String {
+    let devices = metadata::devices().iter().map(|device| device_name(device)).collect::<Vec<_>>().join(" ");
+    format!(
+        r#"_tinkerforge() {{
+    local current="${{COMP_WORDS[COMP_CWORD]}}" index=1 command=""
+    while [ "$index" -lt "$COMP_CWORD" ]; do
+        case "${{COMP_WORDS[index]}}" in
+            --host|--port|--secret|--timeout|--item-separator|--group-separator) index=$((index + 2)) ;;
+            --*) index=$((index + 1)) ;;
+            *) command="${{COMP_WORDS[index]}}"; break ;;
+        esac
+    done
+    local options="--host --port --secret --timeout --json --item-separator --group-separator --no-symbolic-response"
+    case "$command" in
+        "") COMPREPLY=($(compgen -W "$options enumerate call dispatch completions" -- "$current")) ;;
+        enumerate) COMPREPLY=($(compgen -W "--duration --types --execute" -- "$current")) ;;
+        completions) COMPREPLY=($(compgen -W "bash zsh" -- "$current")) ;;
+        call|dispatch)
+            local list="--list-functions"
+            [ "$command" = dispatch ] && list="--list-callbacks"
+            case $((COMP_CWORD - index)) in
+                1) COMPREPLY=($(compgen -W "--list-devices {devices}" -- "$current")) ;;
+                2) COMPREPLY=($(compgen -W "$list" -- "$current")) ;;
+                3) COMPREPLY=($(compgen -W "$("${{COMP_WORDS[0]}}" "$command" "${{COMP_WORDS[index + 1]}}" "$list" 2>/dev/null | cut -d' ' -f1)" -- "$current")) ;;
+                *) COMPREPLY=($(compgen -W "--execute --duration" -- "$current")) ;;
+            esac ;;
+    esac
+}}
+complete -F _tinkerforge tinkerforge"#
+    )
+}
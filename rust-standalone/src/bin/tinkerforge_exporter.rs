@@ -0,0 +1,39 @@
+//! Serves the measured values of all devices of a Brick Daemon to Prometheus.
+//!
+//! Usage: `tinkerforge-exporter [--host <host>] [--port <port>] [--secret <secret>] [--listen <address>]
+//! [--timeout <ms>]`, the metrics are served at `http://<address>/metrics`, by default on `0.0.0.0:9223`.
+use std::{env, error::Error, process::ExitCode, time::Duration};
+
+use tinkerforge_async::exporter::{Exporter, ExporterConfig};
+
+const USAGE: &str = "Usage: tinkerforge-exporter [--host <host>] [--port <port>] [--secret <secret>] [--listen <address>] [--timeout <ms>]";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let (mut host, mut port, mut listen) = ("localhost".to_string(), 4223u16, "0.0.0.0:9223".to_string());
+    let mut config = ExporterConfig::default();
+    let mut args = env::args().skip(1);
+    while let Some(option) = args.next() {
+        let Some(value) = args.next().filter(|_| option != "--help") else {
+            eprintln!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        };
+        match option.as_str() {
+            "--host" => host = value,
+            "--port" => port = value.parse()?,
+            "--secret" => config.secret = Some(value),
+            "--listen" => listen = value,
+            "--timeout" => config.timeout = Duration::from_millis(value.parse()?),
+            _ => {
+                eprintln!("Unknown option {option}\n{USAGE}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    config.brick_daemon = format!("{host}:{port}");
+    let brick_daemon = config.brick_daemon.clone();
+    let exporter = Exporter::bind(listen, config).await?;
+    println!("Serving metrics of {brick_daemon} on http://{}/metrics", exporter.local_addr());
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
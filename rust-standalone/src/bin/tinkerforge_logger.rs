@@ -0,0 +1,20 @@
+//! Logs values of the devices of a Brick Daemon to files and databases.
+//!
+//! Usage: `tinkerforge-logger <config.toml|config.json>`, see the `logger` module for the configuration.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::logger::{DataLogger, LoggerConfig};
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let Some(config) = env::args().nth(1) else {
+        eprintln!("Usage: tinkerforge-logger <config>");
+        return Ok(ExitCode::FAILURE);
+    };
+    let config = LoggerConfig::load(&config)?;
+    let brick_daemon = config.brick_daemon.clone();
+    let _logger = DataLogger::start(config).await?;
+    println!("Logging devices of {brick_daemon}");
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,25 @@
+//! Exposes devices of a Brick Daemon as Modbus TCP registers, coils and discrete inputs.
+//!
+//! Usage: `tinkerforge-modbus <config.toml|config.json> [address]`, the address defaults to `0.0.0.0:502`. See the
+//! `modbus` module for the configuration.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::modbus::{ModbusConfig, ModbusGateway};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:502";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let Some(config) = args.next() else {
+        eprintln!("Usage: tinkerforge-modbus <config> [address]");
+        return Ok(ExitCode::FAILURE);
+    };
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let config = ModbusConfig::load(&config)?;
+    let brick_daemon = config.brick_daemon.clone();
+    let gateway = ModbusGateway::bind(address, config).await?;
+    println!("Serving devices of {brick_daemon} on {}", gateway.local_addr());
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,19 @@
+//! Bridges Brick Daemons to an MQTT broker with the topic scheme of the MQTT bindings.
+//!
+//! Usage: `tinkerforge-mqtt <config.toml|config.json>`, see the `mqtt` module for the configuration.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::mqtt::{MqttBridge, MqttConfig};
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let Some(config) = env::args().nth(1) else {
+        eprintln!("Usage: tinkerforge-mqtt <config>");
+        return Ok(ExitCode::FAILURE);
+    };
+    let config = MqttConfig::load(&config)?;
+    println!("Bridging {} Brick Daemons to {}:{}", config.brick_daemons.len(), config.broker.host, config.broker.port);
+    let _bridge = MqttBridge::start(config)?;
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
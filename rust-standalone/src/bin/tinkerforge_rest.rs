@@ -0,0 +1,41 @@
+//! Serves the devices of a Brick Daemon over HTTP with JSON.
+//!
+//! Usage: `tinkerforge-rest [--host <host>] [--port <port>] [--secret <secret>] [--listen <address>] [--token <token>]
+//! [--allow-origin <origin>]`, by default on `0.0.0.0:8080`. The token can also be set in `TINKERFORGE_REST_TOKEN`, to
+//! keep it out of the process list.
+use std::{env, error::Error, process::ExitCode};
+
+use tinkerforge_async::rest::{RestConfig, RestGateway};
+
+const USAGE: &str = "Usage: tinkerforge-rest [--host <host>] [--port <port>] [--secret <secret>] [--listen <address>] [--token <token>] [--allow-origin <origin>]";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let (mut host, mut port, mut listen) = ("localhost".to_string(), 4223u16, "0.0.0.0:8080".to_string());
+    let mut config = RestConfig { token: env::var("TINKERFORGE_REST_TOKEN").ok(), ..RestConfig::default() };
+    let mut args = env::args().skip(1);
+    while let Some(option) = args.next() {
+        let Some(value) = args.next().filter(|_| option != "--help") else {
+            eprintln!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        };
+        match option.as_str() {
+            "--host" => host = value,
+            "--port" => port = value.parse()?,
+            "--secret" => config.secret = Some(value),
+            "--listen" => listen = value,
+            "--token" => config.token = Some(value),
+            "--allow-origin" => config.allow_origin = Some(value),
+            _ => {
+                eprintln!("Unknown option {option}\n{USAGE}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    config.brick_daemon = format!("{host}:{port}");
+    let brick_daemon = config.brick_daemon.clone();
+    let gateway = RestGateway::bind(listen, config).await?;
+    println!("Serving devices of {brick_daemon} on http://{}/devices", gateway.local_addr());
+    std::future::pending::<()>().await;
+    Ok(ExitCode::SUCCESS)
+}
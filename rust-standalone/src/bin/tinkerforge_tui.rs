@@ -0,0 +1,29 @@
+//! Browses the devices of a Brick Daemon in the terminal, calls their functions and tails their callbacks.
+//!
+//! Usage: `tinkerforge-tui [--host <host>] [--port <port>] [--secret <secret>]`, see the `tui` module for the keys.
+use std::{env, error::Error, process::ExitCode};
+
+const USAGE: &str = "Usage: tinkerforge-tui [--host <host>] [--port <port>] [--secret <secret>]";
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let (mut host, mut port, mut secret) = ("localhost".to_string(), 4223u16, None);
+    let mut args = env::args().skip(1);
+    while let Some(option) = args.next() {
+        let Some(value) = args.next().filter(|_| option != "--help") else {
+            eprintln!("{USAGE}");
+            return Ok(ExitCode::FAILURE);
+        };
+        match option.as_str() {
+            "--host" => host = value,
+            "--port" => port = value.parse()?,
+            "--secret" => secret = Some(value),
+            _ => {
+                eprintln!("Unknown option {option}\n{USAGE}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    tinkerforge_async::tui::run(format!("{host}:{port}"), secret).await?;
+    Ok(ExitCode::SUCCESS)
+}
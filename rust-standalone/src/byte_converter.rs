@@ -25,6 +25,18 @@ pub trait FromByteSlice {
     fn from_le_byte_slice(bytes: &[u8]) -> Self;
     /// Returns how many bytes are expected to deserialize a instance of the implementing type. Currently this method is only used for strings.
     fn bytes_expected() -> usize;
+
+    /// Deserialize the implementing type from a byte slice, failing with [`BrickletError::InvalidParameter`] instead of
+    /// panicking if the slice is too short or holds a value the type cannot represent.
+    fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, BrickletError>
+    where
+        Self: Sized,
+    {
+        if bytes.len() < Self::bytes_expected() {
+            return Err(BrickletError::InvalidParameter);
+        }
+        Ok(Self::from_le_byte_slice(bytes))
+    }
 }
 
 impl<const N: usize> FromByteSlice for Box<[u8; N]> {
@@ -38,22 +50,34 @@ impl<const N: usize> FromByteSlice for Box<[u8; N]> {
     fn bytes_expected() -> usize {
         N
     }
+
+    fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, BrickletError> {
+        if bytes.len() != Self::bytes_expected() {
+            return Err(BrickletError::InvalidParameter);
+        }
+        Ok(Self::from_le_byte_slice(bytes))
+    }
 }
 
-impl<const N: usize, T: Default + Copy + FromByteSlice> FromByteSlice for [T; N] {
+impl<const N: usize, T: FromByteSlice> FromByteSlice for [T; N] {
     fn from_le_byte_slice(bytes: &[u8]) -> Self {
         assert_eq!(bytes.len(), Self::bytes_expected());
-        let mut ret = [T::default(); N];
         let component_size = T::bytes_expected();
-        for i in 0..N {
-            ret[i] = T::from_le_byte_slice(&bytes[i * component_size..(i + 1) * component_size]);
-        }
-        ret
+        std::array::from_fn(|i| T::from_le_byte_slice(&bytes[i * component_size..(i + 1) * component_size]))
     }
 
     fn bytes_expected() -> usize {
         T::bytes_expected() * N
     }
+
+    fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, BrickletError> {
+        if bytes.len() != Self::bytes_expected() {
+            return Err(BrickletError::InvalidParameter);
+        }
+        let component_size = T::bytes_expected();
+        let values = bytes.chunks(component_size).map(T::try_from_le_byte_slice).collect::<Result<Vec<_>, _>>()?;
+        values.try_into().map_err(|_| BrickletError::InvalidParameter)
+    }
 }
 
 impl<const N: usize> ToBytes for [u8; N] {
@@ -284,10 +308,23 @@ impl FromByteSlice for i64 {
 
 impl ToBytes for char {
     fn write_to_slice(self, target: &mut [u8]) {
-        *(target.get_mut(0).expect("slice too small")) = self as u8;
+        *(target.get_mut(0).expect("slice too small")) = latin1_byte(self).unwrap_or(b'?');
+    }
+
+    fn try_write_to_slice(self, _max_len: usize, target: &mut [u8]) -> Result<(), BrickletError>
+    where
+        Self: Sized,
+    {
+        *(target.get_mut(0).ok_or(BrickletError::InvalidParameter)?) = latin1_byte(self).ok_or(BrickletError::InvalidParameter)?;
+        Ok(())
     }
 }
 
+/// Devices use ISO-8859-1, whose code points are exactly the first 256 unicode scalar values.
+pub(crate) fn latin1_byte(value: char) -> Option<u8> {
+    u8::try_from(u32::from(value)).ok()
+}
+
 impl FromByteSlice for char {
     fn from_le_byte_slice(bytes: &[u8]) -> char {
         bytes[0] as char
@@ -298,22 +335,52 @@ impl FromByteSlice for char {
     }
 }
 
-impl ToBytes for String {
+/// Strings are ISO-8859-1 encoded and padded with NUL bytes, unencodable characters are replaced by `?` and
+/// overlong strings are truncated. Use [`try_write_to_slice`](ToBytes::try_write_to_slice) to reject them instead.
+impl ToBytes for &str {
     fn write_to_slice(self, target: &mut [u8]) {
-        target.copy_from_slice(&self.into_bytes());
+        let mut chars = self.chars();
+        for byte in target.iter_mut() {
+            *byte = chars.next().map(|c| latin1_byte(c).unwrap_or(b'?')).unwrap_or(0);
+        }
     }
 
     fn try_write_to_slice(self, max_len: usize, target: &mut [u8]) -> Result<(), BrickletError>
     where
         Self: Sized,
     {
-        let bytes = self.into_bytes();
-        if bytes.len() > max_len {
-            Err(BrickletError::InvalidParameter)
-        } else {
-            target[0..bytes.len()].copy_from_slice(&bytes);
-            Ok(())
+        if self.chars().count() > max_len.min(target.len()) {
+            return Err(BrickletError::InvalidParameter);
+        }
+        let mut bytes = target.iter_mut();
+        for (c, byte) in self.chars().zip(bytes.by_ref()) {
+            *byte = latin1_byte(c).ok_or(BrickletError::InvalidParameter)?;
         }
+        bytes.for_each(|byte| *byte = 0);
+        Ok(())
+    }
+}
+
+impl ToBytes for String {
+    fn write_to_slice(self, target: &mut [u8]) {
+        self.as_str().write_to_slice(target)
+    }
+
+    fn try_write_to_slice(self, max_len: usize, target: &mut [u8]) -> Result<(), BrickletError>
+    where
+        Self: Sized,
+    {
+        self.as_str().try_write_to_slice(max_len, target)
+    }
+}
+
+impl FromByteSlice for String {
+    fn from_le_byte_slice(bytes: &[u8]) -> String {
+        bytes.iter().take_while(|byte| **byte != 0).map(|byte| char::from(*byte)).collect()
+    }
+
+    fn bytes_expected() -> usize {
+        1
     }
 }
 
@@ -440,6 +507,10 @@ where
     fn bytes_expected() -> usize {
         R::bytes_expected()
     }
+
+    fn try_from_le_byte_slice(bytes: &[u8]) -> Result<Self, BrickletError> {
+        R::try_from_le_byte_slice(bytes).map(Self::from)
+    }
 }
 
 impl<P, R> Default for ParsedOrRaw<P, R>
@@ -451,3 +522,109 @@ where
         Self::from(R::default())
     }
 }
+
+#[cfg(feature = "serde")]
+mod serde {
+    use std::fmt::Debug;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::byte_converter::{FromByteSlice, ParsedOrRaw, ToBytes};
+
+    /// Known values are written by name, unknown values as their raw number.
+    impl<P, R> Serialize for ParsedOrRaw<P, R>
+    where
+        P: Into<R> + Debug + Clone + Copy + Serialize,
+        R: TryInto<P> + FromByteSlice + ToBytes + Debug + Clone + Copy + Serialize,
+    {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match self {
+                ParsedOrRaw::Parsed(value) => value.serialize(serializer),
+                ParsedOrRaw::Raw(value) => value.serialize(serializer),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr<P, R> {
+        Parsed(P),
+        Raw(R),
+    }
+
+    impl<'de, P, R> Deserialize<'de> for ParsedOrRaw<P, R>
+    where
+        P: Into<R> + Debug + Clone + Copy + Deserialize<'de>,
+        R: TryInto<P> + FromByteSlice + ToBytes + Debug + Clone + Copy + Deserialize<'de>,
+    {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Ok(match Repr::<P, R>::deserialize(deserializer)? {
+                Repr::Parsed(value) => ParsedOrRaw::Parsed(value),
+                Repr::Raw(value) => ParsedOrRaw::from(value),
+            })
+        }
+    }
+}
+
+/// (De-)serializes arrays of any length as tuples, serde itself only supports up to 32 elements.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_array {
+    use std::{fmt::Formatter, marker::PhantomData};
+
+    use serde::{
+        de::{Error, SeqAccess, Visitor},
+        ser::SerializeTuple,
+        Deserialize, Deserializer, Serialize, Serializer,
+    };
+
+    pub(crate) fn serialize<S, T, const N: usize>(value: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for element in value {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+
+    struct ArrayVisitor<T, const N: usize>(PhantomData<T>);
+
+    impl<'de, T, const N: usize> Visitor<'de> for ArrayVisitor<T, N>
+    where
+        T: Deserialize<'de>,
+    {
+        type Value = [T; N];
+
+        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+            write!(formatter, "an array of {N} elements")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let mut elements = Vec::with_capacity(N);
+            while let Some(element) = seq.next_element()? {
+                elements.push(element);
+            }
+            let length = elements.len();
+            elements.try_into().map_err(|_| A::Error::invalid_length(length, &self))
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        deserializer.deserialize_tuple(N, ArrayVisitor(PhantomData))
+    }
+}
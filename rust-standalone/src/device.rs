@@ -3,34 +3,45 @@
 use std::time::Duration;
 
 use futures_core::Stream;
-#[cfg(feature = "prometheus")]
-use lazy_static::lazy_static;
-#[cfg(feature = "prometheus")]
-use prometheus::{register_histogram_vec, HistogramVec};
 
 use crate::{
     base58::Uid,
+    converting_receiver::BrickletError,
     error::TinkerforgeError,
-    ip_connection::async_io::{AsyncIpConnection, PacketData},
+    ip_connection::{
+        async_io::{AsyncIpConnection, PacketData},
+        Version,
+    },
+    trace::RequestSpan,
 };
 
-#[cfg(feature = "prometheus")]
-lazy_static! {
-    static ref REQUEST_TIMING: HistogramVec = register_histogram_vec!(
-        "tinkerforge_request",
-        "The Tinkerforge response times latencies in seconds.",
-        &["device_type", "function_id", "method"]
-    )
-    .unwrap();
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ResponseExpectedFlag {
+    InvalidFunctionId,
+    False,
+    True,
+    AlwaysTrue,
 }
 
-const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+impl From<bool> for ResponseExpectedFlag {
+    fn from(b: bool) -> Self {
+        if b {
+            ResponseExpectedFlag::True
+        } else {
+            ResponseExpectedFlag::False
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub(crate) struct Device {
+    pub api_version: [u8; 3],
+    pub response_expected: [ResponseExpectedFlag; 256],
     pub internal_uid: Uid,
     pub connection: AsyncIpConnection,
-    #[cfg(feature = "prometheus")]
+    firmware_version: Option<Version>,
     device_display_name: &'static str,
 }
 
@@ -66,31 +77,98 @@ impl std::fmt::Display for SetResponseExpectedError {
     }
 }
 
+pub(crate) fn get_response_expected(flags: &[ResponseExpectedFlag; 256], function_id: u8) -> Result<bool, GetResponseExpectedError> {
+    match flags[function_id as usize] {
+        ResponseExpectedFlag::False => Ok(false),
+        ResponseExpectedFlag::True => Ok(true),
+        ResponseExpectedFlag::AlwaysTrue => Ok(true),
+        ResponseExpectedFlag::InvalidFunctionId => Err(GetResponseExpectedError(function_id)),
+    }
+}
+
+pub(crate) fn set_response_expected(
+    flags: &mut [ResponseExpectedFlag; 256],
+    function_id: u8,
+    response_expected: bool,
+) -> Result<(), SetResponseExpectedError> {
+    if flags[function_id as usize] == ResponseExpectedFlag::AlwaysTrue {
+        Err(SetResponseExpectedError::IsAlwaysTrue(function_id))
+    } else if flags[function_id as usize] == ResponseExpectedFlag::InvalidFunctionId {
+        Err(SetResponseExpectedError::InvalidFunctionId(function_id))
+    } else {
+        flags[function_id as usize] = ResponseExpectedFlag::from(response_expected);
+        Ok(())
+    }
+}
+
+pub(crate) fn set_response_expected_all(flags: &mut [ResponseExpectedFlag; 256], response_expected: bool) {
+    for resp_exp in flags.iter_mut() {
+        if *resp_exp == ResponseExpectedFlag::True || *resp_exp == ResponseExpectedFlag::False {
+            *resp_exp = ResponseExpectedFlag::from(response_expected);
+        }
+    }
+}
+
 impl Device {
-    pub(crate) fn new(internal_uid: Uid, connection: AsyncIpConnection, #[allow(unused)] device_display_name: &'static str) -> Device {
+    pub(crate) fn new(api_version: [u8; 3], internal_uid: Uid, connection: AsyncIpConnection, device_display_name: &'static str) -> Device {
         Device {
+            api_version,
+            response_expected: [ResponseExpectedFlag::InvalidFunctionId; 256],
             internal_uid,
             connection,
-            #[cfg(feature = "prometheus")]
+            firmware_version: None,
             device_display_name,
         }
     }
+    pub(crate) fn with_firmware_version(mut self, firmware_version: Version) -> Device {
+        self.firmware_version = Some(firmware_version);
+        self
+    }
     pub(crate) fn uid(&self) -> Uid {
         self.internal_uid
     }
+    pub(crate) fn firmware_version(&self) -> Option<Version> {
+        self.firmware_version
+    }
+
+    /// Fails if the firmware version of the device is known and older than `required`.
+    pub(crate) fn check_firmware_version(&self, function: &'static str, required: Version) -> Result<(), TinkerforgeError> {
+        match self.firmware_version {
+            Some(actual) if actual < required => Err(TinkerforgeError::UnsupportedFirmware { function, required, actual }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn get_response_expected(&self, function_id: u8) -> Result<bool, GetResponseExpectedError> {
+        get_response_expected(&self.response_expected, function_id)
+    }
+
+    pub(crate) fn set_response_expected(&mut self, function_id: u8, response_expected: bool) -> Result<(), SetResponseExpectedError> {
+        set_response_expected(&mut self.response_expected, function_id, response_expected)
+    }
+
+    pub(crate) fn set_response_expected_all(&mut self, response_expected: bool) {
+        set_response_expected_all(&mut self.response_expected, response_expected)
+    }
 
+    /// Sends a setter, waits for the response only if response expected is enabled for the function.
     pub(crate) async fn set(
         &mut self,
         function_id: u8,
         payload: &[u8],
         timeout: Option<Duration>,
     ) -> Result<Option<PacketData>, TinkerforgeError> {
+        let timeout = timeout.filter(|_| self.response_expected[function_id as usize] != ResponseExpectedFlag::False);
         #[cfg(feature = "prometheus")]
-        let timer = REQUEST_TIMING.with_label_values(&[self.device_display_name, function_id.to_string().as_str(), "set"]).start_timer();
-        let result = self.connection.set(self.internal_uid, function_id, payload, timeout).await;
+        let timer = self.connection.metrics().request_timer(self.device_display_name, function_id, "set");
+        let span = RequestSpan::for_device_name(self.internal_uid, function_id, self.device_display_name);
+        let result = span.run(self.connection.set(self.internal_uid, function_id, payload, timeout)).await;
         #[cfg(feature = "prometheus")]
         drop(timer);
-        result
+        match result? {
+            Some(response) => Ok(Some(check_error_code(response)?)),
+            None => Ok(None),
+        }
     }
 
     pub(crate) async fn get_callback_receiver(&mut self, function_id: u8) -> impl Stream<Item = PacketData> {
@@ -99,10 +177,19 @@ impl Device {
 
     pub(crate) async fn get(&mut self, function_id: u8, payload: &[u8]) -> Result<PacketData, TinkerforgeError> {
         #[cfg(feature = "prometheus")]
-        let timer = REQUEST_TIMING.with_label_values(&[self.device_display_name, function_id.to_string().as_str(), "get"]).start_timer();
-        let result = self.connection.get(self.internal_uid, function_id, payload, DEFAULT_TIMEOUT).await;
+        let timer = self.connection.metrics().request_timer(self.device_display_name, function_id, "get");
+        let span = RequestSpan::for_device_name(self.internal_uid, function_id, self.device_display_name);
+        let result = span.run(self.connection.get(self.internal_uid, function_id, payload, DEFAULT_TIMEOUT)).await;
         #[cfg(feature = "prometheus")]
         drop(timer);
-        result
+        check_error_code(result?)
+    }
+}
+
+/// Turns an error response into an error, its payload is empty.
+pub(crate) fn check_error_code(response: PacketData) -> Result<PacketData, TinkerforgeError> {
+    match response.header().error_code {
+        0 => Ok(response),
+        error_code => Err(BrickletError::from(error_code).into()),
     }
 }
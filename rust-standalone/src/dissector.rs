@@ -0,0 +1,325 @@
+//! Decodes TFP packets into device, function and named fields, using the [`metadata`](crate::metadata) of the bindings.
+//!
+//! Packets do not name the device they belong to, so a [`Dissector`] learns the device of a uid from the enumerate
+//! callbacks and `get_identity` responses it decodes, or from [`Dissector::register`]:
+//! ```
+//! use tinkerforge_async::{base58::Uid, dissector::Dissector, recording::PacketDirection, DeviceIdentifier};
+//!
+//! let mut dissector = Dissector::default();
+//! dissector.register(Uid::from(42), DeviceIdentifier::TemperatureV2Bricklet);
+//! let packet = dissector.dissect(PacketDirection::Received, &[0x2a, 0, 0, 0, 0x0a, 0x01, 0x28, 0, 0x08, 0x09]).unwrap();
+//! assert_eq!(packet.to_string(), "received J TemperatureV2Bricklet.get_temperature #2 temperature=2312");
+//! ```
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    byte_converter::FromByteSlice,
+    converting_receiver::BrickletError,
+    error::TinkerforgeError,
+    ip_connection::PacketHeader,
+    metadata::{self, ConstantGroupMetadata, Direction, ElementMetadata, ElementType, PacketKind, PacketMetadata},
+    recording::PacketDirection,
+};
+
+const BRICK_DAEMON_UID: u32 = 1;
+const FUNCTION_DISCONNECT_PROBE: u8 = 128;
+const CALLBACK_ENUMERATE: u8 = 253;
+const FUNCTION_ENUMERATE: u8 = 254;
+const FUNCTION_GET_IDENTITY: u8 = 255;
+/// Offset of the device identifier in enumerate callbacks and `get_identity` responses.
+const DEVICE_IDENTIFIER_OFFSET: usize = 23;
+
+const fn element(name: &'static str, element_type: ElementType, count: usize, direction: Direction) -> ElementMetadata {
+    ElementMetadata { name, element_type, count, direction, unit: None, si_unit: None, constant_group: None }
+}
+
+const IDENTITY_ELEMENTS: [ElementMetadata; 6] = [
+    element("uid", ElementType::String, 8, Direction::Out),
+    element("connected_uid", ElementType::String, 8, Direction::Out),
+    element("position", ElementType::Char, 1, Direction::Out),
+    element("hardware_version", ElementType::U8, 3, Direction::Out),
+    element("firmware_version", ElementType::U8, 3, Direction::Out),
+    element("device_identifier", ElementType::U16, 1, Direction::Out),
+];
+
+const ENUMERATE_ELEMENTS: [ElementMetadata; 7] = [
+    IDENTITY_ELEMENTS[0],
+    IDENTITY_ELEMENTS[1],
+    IDENTITY_ELEMENTS[2],
+    IDENTITY_ELEMENTS[3],
+    IDENTITY_ELEMENTS[4],
+    IDENTITY_ELEMENTS[5],
+    element("enumeration_type", ElementType::U8, 1, Direction::Out),
+];
+
+/// Packets of the connection itself, `get_identity` is used for devices without metadata.
+static COMMON_PACKETS: [PacketMetadata; 4] = [
+    PacketMetadata { function_id: FUNCTION_DISCONNECT_PROBE, name: "disconnect_probe", kind: PacketKind::Function, elements: &[] },
+    PacketMetadata { function_id: CALLBACK_ENUMERATE, name: "enumerate", kind: PacketKind::Callback, elements: &ENUMERATE_ELEMENTS },
+    PacketMetadata { function_id: FUNCTION_ENUMERATE, name: "enumerate", kind: PacketKind::Function, elements: &[] },
+    PacketMetadata { function_id: FUNCTION_GET_IDENTITY, name: "get_identity", kind: PacketKind::Function, elements: &IDENTITY_ELEMENTS },
+];
+
+/// Packets answered by the Brick Daemon itself.
+static BRICK_DAEMON_PACKETS: [PacketMetadata; 2] = [
+    PacketMetadata {
+        function_id: 1,
+        name: "get_authentication_nonce",
+        kind: PacketKind::Function,
+        elements: &[element("server_nonce", ElementType::U8, 4, Direction::Out)],
+    },
+    PacketMetadata {
+        function_id: 2,
+        name: "authenticate",
+        kind: PacketKind::Function,
+        elements: &[element("client_nonce", ElementType::U8, 4, Direction::In), element("digest", ElementType::U8, 20, Direction::In)],
+    },
+];
+
+/// A decoded value of a field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Integer(i64),
+    Unsigned(u64),
+    Float(f32),
+    Bool(bool),
+    Char(char),
+    String(String),
+    /// A value of a constant group.
+    Constant {
+        name: &'static str,
+        value: i64,
+    },
+    Array(Vec<FieldValue>),
+    /// The packet ended before the field.
+    Missing,
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Integer(value) => write!(f, "{value}"),
+            FieldValue::Unsigned(value) => write!(f, "{value}"),
+            FieldValue::Float(value) => write!(f, "{value}"),
+            FieldValue::Bool(value) => write!(f, "{value}"),
+            FieldValue::Char(value) => write!(f, "{value:?}"),
+            FieldValue::String(value) => write!(f, "{value:?}"),
+            FieldValue::Constant { name, value } => write!(f, "{name}({value})"),
+            FieldValue::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str("]")
+            }
+            FieldValue::Missing => f.write_str("<missing>"),
+        }
+    }
+}
+
+/// A named element of a packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub element: &'static ElementMetadata,
+    pub value: FieldValue,
+}
+
+/// A packet decoded by a [`Dissector`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DissectedPacket {
+    pub direction: PacketDirection,
+    pub uid: Uid,
+    pub function_id: u8,
+    pub sequence_number: u8,
+    pub response_expected: bool,
+    pub error_code: u8,
+    /// Device of the uid, if known.
+    pub device: Option<DeviceIdentifier>,
+    /// Function or callback of the packet, if known.
+    pub packet: Option<&'static PacketMetadata>,
+    /// Elements of the packet, empty if the packet is unknown or an error response.
+    pub fields: Vec<Field>,
+    /// Payload after the header.
+    pub payload: Vec<u8>,
+}
+
+impl DissectedPacket {
+    /// Error reported by a response, `None` if the request succeeded.
+    pub fn error(&self) -> Option<BrickletError> {
+        (self.error_code != 0).then(|| BrickletError::from(self.error_code))
+    }
+    /// Returns the value of the field with the given snake case name.
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields.iter().find(|field| field.element.name == name).map(|field| &field.value)
+    }
+}
+
+impl Display for DissectedPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ", self.direction)?;
+        match u32::from(self.uid) {
+            0 => f.write_str("0")?,
+            _ => write!(f, "{}", self.uid)?,
+        }
+        match self.device {
+            Some(device) => write!(f, " {device:?}.")?,
+            None => f.write_str(" ?.")?,
+        }
+        match self.packet {
+            Some(packet) => f.write_str(packet.name)?,
+            None => write!(f, "function_{}", self.function_id)?,
+        }
+        write!(f, " #{}", self.sequence_number)?;
+        if self.direction == PacketDirection::Sent && !self.response_expected {
+            f.write_str(" no_response")?;
+        }
+        if let Some(error) = self.error() {
+            write!(f, " error={error:?}")?;
+        }
+        for field in &self.fields {
+            write!(f, " {}={}", field.element.name, field.value)?;
+        }
+        if self.packet.is_none() && !self.payload.is_empty() {
+            f.write_str(" payload=")?;
+            for byte in &self.payload {
+                write!(f, "{byte:02x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes packets and remembers the devices announced in them.
+#[derive(Clone, Debug, Default)]
+pub struct Dissector {
+    devices: HashMap<Uid, DeviceIdentifier>,
+}
+
+impl Dissector {
+    /// Decodes packets of `uid` as packets of `device`.
+    pub fn register(&mut self, uid: Uid, device: DeviceIdentifier) {
+        self.devices.insert(uid, device);
+    }
+    /// Returns the device of a uid, if it was registered or announced.
+    pub fn device(&self, uid: Uid) -> Option<DeviceIdentifier> {
+        self.devices.get(&uid).copied()
+    }
+
+    /// Decodes a whole packet, header and payload.
+    pub fn dissect(&mut self, direction: PacketDirection, bytes: &[u8]) -> Result<DissectedPacket, TinkerforgeError> {
+        if bytes.len() < PacketHeader::SIZE {
+            return Err(TinkerforgeError::InvalidRecording(format!("Packet of {} bytes is shorter than its header", bytes.len())));
+        }
+        let header = PacketHeader::from_le_byte_slice(bytes);
+        let payload = &bytes[PacketHeader::SIZE..];
+        if direction == PacketDirection::Received
+            && header.error_code == 0
+            && matches!(header.function_id, CALLBACK_ENUMERATE | FUNCTION_GET_IDENTITY)
+            && payload.len() >= DEVICE_IDENTIFIER_OFFSET + 2
+        {
+            if let Ok(device) = u16::from_le_byte_slice(&payload[DEVICE_IDENTIFIER_OFFSET..]).try_into() {
+                self.register(header.uid, device);
+            }
+        }
+        let device = self.device(header.uid);
+        let packet = self.packet(header.uid, device, header.function_id);
+        let fields = match packet {
+            Some(packet) if header.error_code == 0 => {
+                let direction = match direction {
+                    PacketDirection::Sent => Direction::In,
+                    PacketDirection::Received => Direction::Out,
+                };
+                let constant_groups = device.map(|device| metadata::device(device).constant_groups).unwrap_or_default();
+                decode_fields(packet.elements.iter().filter(|element| element.direction == direction), constant_groups, payload)
+            }
+            _ => Vec::new(),
+        };
+        Ok(DissectedPacket {
+            direction,
+            uid: header.uid,
+            function_id: header.function_id,
+            sequence_number: header.sequence_number,
+            response_expected: header.response_expected,
+            error_code: header.error_code,
+            device,
+            packet,
+            fields,
+            payload: payload.to_vec(),
+        })
+    }
+
+    fn packet(&self, uid: Uid, device: Option<DeviceIdentifier>, function_id: u8) -> Option<&'static PacketMetadata> {
+        if u32::from(uid) == BRICK_DAEMON_UID {
+            return BRICK_DAEMON_PACKETS.iter().find(|packet| packet.function_id == function_id);
+        }
+        device
+            .and_then(|device| metadata::device(device).packet(function_id))
+            .or_else(|| COMMON_PACKETS.iter().find(|packet| packet.function_id == function_id))
+    }
+}
+
+fn decode_fields(
+    elements: impl Iterator<Item = &'static ElementMetadata>,
+    constant_groups: &'static [ConstantGroupMetadata],
+    payload: &[u8],
+) -> Vec<Field> {
+    let mut offset = 0;
+    elements
+        .map(|element| {
+            let size = element.element_type.byte_count(element.count);
+            let bytes = payload.get(offset..offset + size);
+            offset += size;
+            let constant_group = element.constant_group.and_then(|name| constant_groups.iter().find(|group| group.name == name));
+            let value = bytes.map(|bytes| decode_element(element, constant_group, bytes)).unwrap_or(FieldValue::Missing);
+            Field { element, value }
+        })
+        .collect()
+}
+
+fn decode_element(element: &ElementMetadata, constant_group: Option<&ConstantGroupMetadata>, bytes: &[u8]) -> FieldValue {
+    match (element.element_type, element.count) {
+        (ElementType::String, _) => FieldValue::String(String::from_le_byte_slice(bytes)),
+        (element_type, 1) => decode_value(element_type, constant_group, bytes),
+        (ElementType::Bool, count) => {
+            FieldValue::Array((0..count).map(|index| FieldValue::Bool(bytes[index / 8] & (1 << (index % 8)) != 0)).collect())
+        }
+        (element_type, _) => FieldValue::Array(
+            bytes.chunks_exact(element_type.byte_count(1)).map(|chunk| decode_value(element_type, constant_group, chunk)).collect(),
+        ),
+    }
+}
+
+fn decode_value(element_type: ElementType, constant_group: Option<&ConstantGroupMetadata>, bytes: &[u8]) -> FieldValue {
+    let value = match element_type {
+        ElementType::U8 => FieldValue::Unsigned(u8::from_le_byte_slice(bytes).into()),
+        ElementType::I8 => FieldValue::Integer(i8::from_le_byte_slice(bytes).into()),
+        ElementType::U16 => FieldValue::Unsigned(u16::from_le_byte_slice(bytes).into()),
+        ElementType::I16 => FieldValue::Integer(i16::from_le_byte_slice(bytes).into()),
+        ElementType::U32 => FieldValue::Unsigned(u32::from_le_byte_slice(bytes).into()),
+        ElementType::I32 => FieldValue::Integer(i32::from_le_byte_slice(bytes).into()),
+        ElementType::U64 => FieldValue::Unsigned(u64::from_le_byte_slice(bytes)),
+        ElementType::I64 => FieldValue::Integer(i64::from_le_byte_slice(bytes)),
+        ElementType::Bool => FieldValue::Bool(bytes[0] != 0),
+        ElementType::Float => FieldValue::Float(f32::from_le_byte_slice(bytes)),
+        ElementType::Char | ElementType::String => FieldValue::Char(char::from_le_byte_slice(bytes)),
+    };
+    let raw = match value {
+        FieldValue::Integer(value) => value,
+        FieldValue::Unsigned(value) => value as i64,
+        FieldValue::Bool(value) => value.into(),
+        FieldValue::Char(value) => value as i64,
+        _ => return value,
+    };
+    match constant_group.and_then(|group| group.constant_name(raw)) {
+        Some(name) => FieldValue::Constant { name, value: raw },
+        None => value,
+    }
+}
@@ -0,0 +1,448 @@
+//! Devices driven by the JSON definitions of the JSON bindings, loaded at runtime.
+//!
+//! Functions and elements are addressed by their snake case names. Requests and responses are JSON objects
+//! with one entry per element, values of a constant group are given by the constant's name:
+//! ```no_run
+//! # async fn example(connection: tinkerforge_async::ip_connection::async_io::AsyncIpConnection) -> Result<(), tinkerforge_async::error::TinkerforgeError> {
+//! use std::sync::Arc;
+//!
+//! use serde_json::json;
+//! use tinkerforge_async::dynamic::{DeviceDefinition, DynamicDevice};
+//!
+//! let definition = DeviceDefinition::load("bindings/bricklet_temperature_v2.json")?;
+//! let mut device = DynamicDevice::new("ZQH".parse().unwrap(), connection, Arc::new(definition));
+//! let response = device.call("get_temperature", json!({})).await?;
+//! println!("{}", response["temperature"]);
+//! # Ok(())
+//! # }
+//! ```
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use futures_core::Stream;
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    byte_converter::{FromByteSlice, ToBytes},
+    device::{check_error_code, DEFAULT_TIMEOUT},
+    error::TinkerforgeError,
+    ip_connection::async_io::AsyncIpConnection,
+    metadata::{ConstantGroupMetadata, DeviceMetadata, Direction, ElementMetadata, ElementType, PacketKind},
+    trace::RequestSpan,
+};
+
+/// A device as described by a file of the JSON bindings.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DeviceDefinition {
+    pub name: String,
+    pub category: String,
+    pub device_identifier: u16,
+    pub packets: Vec<PacketDefinition>,
+}
+
+/// Level of a packet, high level packets are assembled from several low level packets on the client.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketLevel {
+    Normal,
+    Low,
+    High,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PacketDefinition {
+    pub level: PacketLevel,
+    #[serde(rename = "type")]
+    pub kind: PacketKind,
+    /// Space separated name, e.g. `Get Temperature`.
+    pub name: String,
+    pub function_id: u8,
+    pub elements: Vec<ElementDefinition>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElementDefinition {
+    /// Space separated name, e.g. `Temperature`.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub element_type: ElementType,
+    pub cardinality: usize,
+    pub direction: Direction,
+    #[serde(default)]
+    pub extra: Vec<ElementExtra>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ElementExtra {
+    pub constant_group: Option<ConstantGroupDefinition>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConstantGroupDefinition {
+    pub name: String,
+    pub constants: Vec<ConstantDefinition>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConstantDefinition {
+    pub name: String,
+    pub value: Value,
+}
+
+impl DeviceDefinition {
+    pub fn from_json(json: &str) -> Result<DeviceDefinition, TinkerforgeError> {
+        Ok(serde_json::from_str(json)?)
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<DeviceDefinition, TinkerforgeError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+    /// Loads all `*.json` files of a directory, like the `bindings` directory of the JSON bindings.
+    pub fn load_directory(path: impl AsRef<Path>) -> Result<Vec<DeviceDefinition>, TinkerforgeError> {
+        let mut definitions = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                definitions.push(Self::load(path)?);
+            }
+        }
+        definitions.sort_by_key(|definition| definition.device_identifier);
+        Ok(definitions)
+    }
+    /// Returns the packet with the given snake case name, high level packets are not available on the wire.
+    pub fn packet(&self, kind: PacketKind, name: &str) -> Option<&PacketDefinition> {
+        self.packets.iter().find(|packet| packet.kind == kind && packet.level != PacketLevel::High && snake_case(&packet.name) == name)
+    }
+}
+
+/// Definition of a device known to the bindings, so no JSON files are needed for it.
+impl From<&DeviceMetadata> for DeviceDefinition {
+    fn from(device: &DeviceMetadata) -> Self {
+        let packets = device
+            .packets
+            .iter()
+            .map(|packet| PacketDefinition {
+                level: PacketLevel::Normal,
+                kind: packet.kind,
+                name: packet.name.to_string(),
+                function_id: packet.function_id,
+                elements: packet.elements.iter().map(|element| ElementDefinition::from_metadata(device, element)).collect(),
+            })
+            .collect();
+        DeviceDefinition {
+            name: device.display_name.to_string(),
+            category: device.category.to_string(),
+            device_identifier: device.identifier.into(),
+            packets,
+        }
+    }
+}
+
+impl ElementDefinition {
+    fn from_metadata(device: &DeviceMetadata, element: &ElementMetadata) -> ElementDefinition {
+        let constant_group = element.constant_group.and_then(|name| device.constant_group(name)).map(ConstantGroupDefinition::from);
+        ElementDefinition {
+            name: element.name.to_string(),
+            element_type: element.element_type,
+            cardinality: element.count,
+            direction: element.direction,
+            extra: vec![ElementExtra { constant_group }],
+        }
+    }
+    fn constant_group(&self) -> Option<&ConstantGroupDefinition> {
+        self.extra.first().and_then(|extra| extra.constant_group.as_ref())
+    }
+    fn byte_count(&self) -> usize {
+        self.element_type.byte_count(self.cardinality)
+    }
+}
+
+/// A device whose functions are resolved by name from a [`DeviceDefinition`].
+#[derive(Clone, Debug)]
+pub struct DynamicDevice {
+    uid: Uid,
+    connection: AsyncIpConnection,
+    definition: Arc<DeviceDefinition>,
+    timeout: Duration,
+}
+
+impl DynamicDevice {
+    pub fn new(uid: Uid, connection: AsyncIpConnection, definition: Arc<DeviceDefinition>) -> DynamicDevice {
+        DynamicDevice { uid, connection, definition, timeout: DEFAULT_TIMEOUT }
+    }
+    /// Maximum time to wait for the response of a getter.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    pub fn uid(&self) -> Uid {
+        self.uid
+    }
+    pub fn definition(&self) -> &DeviceDefinition {
+        &self.definition
+    }
+
+    /// Calls the function with the given snake case name, `request` holds the input elements.
+    pub async fn call(&mut self, function: &str, request: Value) -> Result<Value, TinkerforgeError> {
+        let packet = self
+            .definition
+            .packet(PacketKind::Function, function)
+            .ok_or_else(|| TinkerforgeError::InvalidCall(format!("{} has no function {function}", self.definition.name)))?;
+        let payload = encode_request(packet, &request)?;
+        if packet.elements.iter().any(|element| element.direction == Direction::Out) {
+            let span = RequestSpan::new(self.uid, packet.function_id, Some(&self.definition.name), Some(function));
+            let response = span.run(self.connection.get(self.uid, packet.function_id, &payload, self.timeout)).await?;
+            Ok(decode_response(packet, check_error_code(response)?.body()))
+        } else {
+            let span = RequestSpan::new(self.uid, packet.function_id, Some(&self.definition.name), Some(function));
+            if let Some(response) =
+                span.run(self.connection.set(self.uid, packet.function_id, &payload, Some(Duration::from_secs(20)))).await?
+            {
+                check_error_code(response)?;
+            }
+            Ok(Value::Object(Map::new()))
+        }
+    }
+
+    /// Stream of the callback with the given snake case name, decoded like the response of [`call`](Self::call).
+    pub async fn callback_stream(&mut self, callback: &str) -> Result<impl Stream<Item = Value>, TinkerforgeError> {
+        let packet = self
+            .definition
+            .packet(PacketKind::Callback, callback)
+            .ok_or_else(|| TinkerforgeError::InvalidCall(format!("{} has no callback {callback}", self.definition.name)))?
+            .clone();
+        Ok(self.connection.callback_stream(self.uid, packet.function_id).await.map(move |data| decode_response(&packet, data.body())))
+    }
+}
+
+impl From<&ConstantGroupMetadata> for ConstantGroupDefinition {
+    fn from(group: &ConstantGroupMetadata) -> Self {
+        let constants = group
+            .constants
+            .iter()
+            .map(|constant| ConstantDefinition {
+                name: split_camel_case(constant.name),
+                value: match group.element_type {
+                    ElementType::Bool => Value::Bool(constant.value != 0),
+                    ElementType::Char => {
+                        u8::try_from(constant.value).map(|byte| Value::from(char::from(byte).to_string())).unwrap_or_default()
+                    }
+                    _ => Value::from(constant.value),
+                },
+            })
+            .collect();
+        ConstantGroupDefinition { name: group.name.to_string(), constants }
+    }
+}
+
+/// Turns `FastBlink` into `Fast Blink`, the form used by the JSON bindings.
+fn split_camel_case(name: &str) -> String {
+    let mut split = String::with_capacity(name.len() + 4);
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if c.is_uppercase() && previous_lowercase {
+            split.push(' ');
+        }
+        previous_lowercase = c.is_lowercase() || c.is_ascii_digit();
+        split.push(c);
+    }
+    split
+}
+
+fn snake_case(name: &str) -> String {
+    name.to_lowercase().replace([' ', '-'], "_")
+}
+
+fn encode_request(packet: &PacketDefinition, request: &Value) -> Result<Vec<u8>, TinkerforgeError> {
+    let elements = packet.elements.iter().filter(|element| element.direction == Direction::In);
+    let mut payload = vec![0; elements.clone().map(ElementDefinition::byte_count).sum()];
+    let mut offset = 0;
+    for element in elements {
+        let name = snake_case(&element.name);
+        let value = request.get(&name).ok_or_else(|| TinkerforgeError::InvalidCall(format!("Missing element {name}")))?;
+        let target = &mut payload[offset..offset + element.byte_count()];
+        encode_element(element, value, target).map_err(|reason| TinkerforgeError::InvalidCall(format!("Invalid {name}: {reason}")))?;
+        offset += element.byte_count();
+    }
+    Ok(payload)
+}
+
+fn encode_element(element: &ElementDefinition, value: &Value, target: &mut [u8]) -> Result<(), String> {
+    let constant_group = element.constant_group();
+    match (element.element_type, element.cardinality) {
+        (ElementType::String, length) => {
+            let text = value.as_str().ok_or("expected a string")?;
+            text.try_write_to_slice(length, target).map_err(|_| format!("expected at most {length} ISO-8859-1 characters"))
+        }
+        (element_type, 1) => encode_value(element_type, constant_group, value, target),
+        (ElementType::Bool, cardinality) => {
+            let values = array(value, cardinality)?;
+            for (index, value) in values.iter().enumerate() {
+                if resolve_constant(constant_group, value).as_bool().ok_or("expected a bool")? {
+                    target[index / 8] |= 1 << (index % 8);
+                }
+            }
+            Ok(())
+        }
+        (element_type, cardinality) => {
+            let values = array(value, cardinality)?;
+            let size = element_type.byte_count(1);
+            for (index, value) in values.iter().enumerate() {
+                encode_value(element_type, constant_group, value, &mut target[index * size..(index + 1) * size])?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn array(value: &Value, cardinality: usize) -> Result<&Vec<Value>, String> {
+    match value.as_array() {
+        Some(values) if values.len() == cardinality => Ok(values),
+        _ => Err(format!("expected an array of {cardinality} values")),
+    }
+}
+
+/// Replaces the name of a constant by its value.
+fn resolve_constant<'a>(constant_group: Option<&'a ConstantGroupDefinition>, value: &'a Value) -> &'a Value {
+    match (constant_group, value.as_str()) {
+        (Some(group), Some(name)) => group
+            .constants
+            .iter()
+            .find(|constant| snake_case(&constant.name) == snake_case(name))
+            .map(|constant| &constant.value)
+            .unwrap_or(value),
+        _ => value,
+    }
+}
+
+fn encode_value(
+    element_type: ElementType,
+    constant_group: Option<&ConstantGroupDefinition>,
+    value: &Value,
+    target: &mut [u8],
+) -> Result<(), String> {
+    let value = resolve_constant(constant_group, value);
+    let out_of_range = || format!("{value} is not a valid {element_type:?}");
+    let integer = || value.as_i64().ok_or_else(out_of_range);
+    match element_type {
+        ElementType::U8 => u8::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::I8 => i8::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::U16 => u16::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::I16 => i16::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::U32 => u32::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::I32 => i32::try_from(integer()?).map_err(|_| out_of_range())?.write_to_slice(target),
+        ElementType::U64 => value.as_u64().ok_or_else(out_of_range)?.write_to_slice(target),
+        ElementType::I64 => integer()?.write_to_slice(target),
+        ElementType::Bool => value.as_bool().ok_or_else(out_of_range)?.write_to_slice(target),
+        ElementType::Float => (value.as_f64().ok_or_else(out_of_range)? as f32).write_to_slice(target),
+        ElementType::Char | ElementType::String => {
+            let mut chars = value.as_str().ok_or_else(out_of_range)?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c.try_write_to_slice(1, target).map_err(|_| out_of_range())?,
+                _ => return Err(out_of_range()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_response(packet: &PacketDefinition, body: &[u8]) -> Value {
+    let mut response = Map::new();
+    let mut offset = 0;
+    for element in packet.elements.iter().filter(|element| element.direction == Direction::Out) {
+        let end = (offset + element.byte_count()).min(body.len());
+        response.insert(snake_case(&element.name), decode_element(element, &body[offset.min(end)..end]));
+        offset += element.byte_count();
+    }
+    Value::Object(response)
+}
+
+fn decode_element(element: &ElementDefinition, bytes: &[u8]) -> Value {
+    let constant_group = element.constant_group();
+    match (element.element_type, element.cardinality) {
+        (ElementType::String, _) => Value::String(String::from_le_byte_slice(bytes)),
+        (element_type, 1) => decode_value(element_type, constant_group, bytes),
+        (ElementType::Bool, cardinality) => Value::Array(
+            (0..cardinality)
+                .map(|index| {
+                    name_constant(constant_group, Value::Bool(bytes.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0)))
+                })
+                .collect(),
+        ),
+        (element_type, _) => Value::Array(
+            bytes.chunks_exact(element_type.byte_count(1)).map(|chunk| decode_value(element_type, constant_group, chunk)).collect(),
+        ),
+    }
+}
+
+fn decode_value(element_type: ElementType, constant_group: Option<&ConstantGroupDefinition>, bytes: &[u8]) -> Value {
+    if bytes.len() < element_type.byte_count(1) {
+        return Value::Null;
+    }
+    let value = match element_type {
+        ElementType::U8 => Value::from(u8::from_le_byte_slice(bytes)),
+        ElementType::I8 => Value::from(i8::from_le_byte_slice(bytes)),
+        ElementType::U16 => Value::from(u16::from_le_byte_slice(bytes)),
+        ElementType::I16 => Value::from(i16::from_le_byte_slice(bytes)),
+        ElementType::U32 => Value::from(u32::from_le_byte_slice(bytes)),
+        ElementType::I32 => Value::from(i32::from_le_byte_slice(bytes)),
+        ElementType::U64 => Value::from(u64::from_le_byte_slice(bytes)),
+        ElementType::I64 => Value::from(i64::from_le_byte_slice(bytes)),
+        ElementType::Bool => Value::from(bool::from_le_byte_slice(bytes)),
+        ElementType::Float => Number::from_f64(f64::from(f32::from_le_byte_slice(bytes))).map(Value::Number).unwrap_or(Value::Null),
+        ElementType::Char | ElementType::String => Value::from(char::from_le_byte_slice(bytes).to_string()),
+    };
+    name_constant(constant_group, value)
+}
+
+/// Replaces a known constant value by its snake case name, unknown values are kept as they are.
+fn name_constant(constant_group: Option<&ConstantGroupDefinition>, value: Value) -> Value {
+    constant_group
+        .and_then(|group| group.constants.iter().find(|constant| constant.value == value))
+        .map(|constant| Value::String(snake_case(&constant.name)))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::dynamic::{decode_response, encode_request, DeviceDefinition};
+    use crate::{metadata, metadata::PacketKind, DeviceIdentifier};
+
+    const DEFINITION: &str = r#"{
+        "name": "Test", "category": "Bricklet", "device_identifier": 1,
+        "packets": [{
+            "level": "normal", "type": "function", "name": "Set Mode", "function_id": 1,
+            "elements": [
+                {"name": "Mode", "type": "uint8", "cardinality": 1, "direction": "in", "extra": [{"constant_group": {
+                    "name": "Mode", "constants": [{"name": "Off", "value": 0}, {"name": "Fast Blink", "value": 2}]}}]},
+                {"name": "Text", "type": "string", "cardinality": 4, "direction": "in"},
+                {"name": "Flags", "type": "bool", "cardinality": 3, "direction": "in"},
+                {"name": "Mode", "type": "uint8", "cardinality": 1, "direction": "out", "extra": [{"constant_group": {
+                    "name": "Mode", "constants": [{"name": "Off", "value": 0}, {"name": "Fast Blink", "value": 2}]}}]},
+                {"name": "Values", "type": "int16", "cardinality": 2, "direction": "out"}
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn test_encode_and_decode() {
+        let definition = DeviceDefinition::from_json(DEFINITION).unwrap();
+        let packet = definition.packet(PacketKind::Function, "set_mode").unwrap();
+        let payload = encode_request(packet, &json!({"mode": "fast_blink", "text": "°C", "flags": [true, false, true]})).unwrap();
+        assert_eq!(payload, vec![2, 0xB0, b'C', 0, 0, 0b101]);
+        assert!(encode_request(packet, &json!({"mode": 300, "text": "", "flags": [true, false, true]})).is_err());
+        assert_eq!(decode_response(packet, &[2, 0xFF, 0xFF, 7, 0]), json!({"mode": "fast_blink", "values": [-1, 7]}));
+    }
+
+    #[test]
+    fn test_definition_from_metadata() {
+        let definition = DeviceDefinition::from(metadata::device(DeviceIdentifier::TemperatureV2Bricklet));
+        assert_eq!(definition.device_identifier, 2113);
+        let packet = definition.packet(PacketKind::Function, "set_status_led_config").unwrap();
+        assert_eq!(encode_request(packet, &json!({"config": "show_heartbeat"})).unwrap(), vec![2]);
+        let packet = definition.packet(PacketKind::Function, "get_status_led_config").unwrap();
+        assert_eq!(decode_response(packet, &[3]), json!({"config": "show_status"}));
+    }
+}
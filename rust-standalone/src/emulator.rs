@@ -0,0 +1,770 @@
+//! Emulates a Brick Daemon with virtual devices, to run integration tests without hardware.
+//!
+//! The devices are described by a TOML or JSON scenario. Functions answer with scripted values, unscripted functions
+//! answer with zeros, and callbacks are sent periodically:
+//! ```
+//! use tinkerforge_async::{
+//!     emulator::{Emulator, Scenario},
+//!     ip_connection::async_io::AsyncIpConnection,
+//!     temperature_v_2::TemperatureV2Bricklet,
+//! };
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let scenario = Scenario::from_toml(
+//!     r#"
+//!     [[devices]]
+//!     uid = "Tmp"
+//!     device = "TemperatureV2Bricklet"
+//!
+//!     [[devices.functions]]
+//!     function = "get_temperature"
+//!     responses = [[2312], [2400]]
+//!     "#,
+//! )
+//! .unwrap();
+//! let emulator = Emulator::bind("127.0.0.1:0", scenario).await.unwrap();
+//!
+//! let connection = AsyncIpConnection::new(emulator.local_addr()).await.unwrap();
+//! let mut bricklet = TemperatureV2Bricklet::new("Tmp".parse().unwrap(), connection);
+//! assert_eq!(bricklet.get_temperature().await.unwrap(), 2312);
+//! assert_eq!(bricklet.get_temperature().await.unwrap(), 2400);
+//! assert_eq!(bricklet.get_temperature().await.unwrap(), 2400);
+//! # });
+//! ```
+//! A scenario may additionally set a `secret` clients have to authenticate with, a `latency_ms` for every response and
+//! `disconnect_after_requests` to drop connections. Devices implementing [`EmulatedDevice`] can be added at runtime.
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use sha1::Sha1;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{broadcast, mpsc, Mutex},
+    task::AbortHandle,
+};
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    byte_converter::{FromByteSlice, ToBytes},
+    converting_receiver::BrickletError,
+    error::TinkerforgeError,
+    ip_connection::{PacketHeader, Version},
+    metadata::{DeviceMetadata, ElementMetadata, ElementType},
+    server::{CallbackEmitter, CallbackPacket},
+};
+
+/// Uid of the Brick Daemon itself, which answers the authentication functions.
+const BRICK_DAEMON_UID: u32 = 1;
+const FUNCTION_GET_AUTHENTICATION_NONCE: u8 = 1;
+const FUNCTION_AUTHENTICATE: u8 = 2;
+const FUNCTION_DISCONNECT_PROBE: u8 = 128;
+const CALLBACK_ENUMERATE: u8 = 253;
+const FUNCTION_ENUMERATE: u8 = 254;
+const FUNCTION_GET_IDENTITY: u8 = 255;
+const ENUMERATION_TYPE_AVAILABLE: u8 = 0;
+const ENUMERATION_TYPE_CONNECTED: u8 = 1;
+const ENUMERATION_TYPE_DISCONNECTED: u8 = 2;
+const MAX_PACKET_SIZE: u8 = 80;
+const CALLBACK_CAPACITY: usize = 512;
+
+/// Devices and faults served by an [`Emulator`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Secret clients have to authenticate with before they can access the devices.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Delay before every response, in milliseconds.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Closes a connection after it sent this many requests.
+    #[serde(default)]
+    pub disconnect_after_requests: Option<usize>,
+    #[serde(default)]
+    pub devices: Vec<DeviceScenario>,
+}
+
+impl Scenario {
+    pub fn from_toml(toml: &str) -> Result<Scenario, TinkerforgeError> {
+        toml::from_str(toml).map_err(|error| TinkerforgeError::InvalidScenario(error.to_string()))
+    }
+    pub fn from_json(json: &str) -> Result<Scenario, TinkerforgeError> {
+        serde_json::from_str(json).map_err(|error| TinkerforgeError::InvalidScenario(error.to_string()))
+    }
+    /// Reads a scenario, files ending in `.json` are parsed as JSON, all others as TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, TinkerforgeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            Scenario::from_json(&content)
+        } else {
+            Scenario::from_toml(&content)
+        }
+    }
+}
+
+/// A virtual device of a scenario.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceScenario {
+    pub uid: Uid,
+    pub device: DeviceIdentifier,
+    #[serde(default)]
+    pub connected_uid: Uid,
+    #[serde(default = "default_position")]
+    pub position: char,
+    #[serde(default = "default_hardware_version")]
+    pub hardware_version: [u8; 3],
+    #[serde(default = "default_firmware_version")]
+    pub firmware_version: [u8; 3],
+    #[serde(default)]
+    pub functions: Vec<FunctionScenario>,
+    #[serde(default)]
+    pub callbacks: Vec<CallbackScenario>,
+}
+
+fn default_position() -> char {
+    'a'
+}
+
+fn default_hardware_version() -> [u8; 3] {
+    [1, 0, 0]
+}
+
+fn default_firmware_version() -> [u8; 3] {
+    [2, 0, 0]
+}
+
+/// A function or callback, given by its snake case name or function id.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PacketRef {
+    Id(u8),
+    Name(String),
+}
+
+/// Scripted answers of a function.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionScenario {
+    pub function: PacketRef,
+    /// Values of the response elements, one list per call. The last response is repeated.
+    #[serde(default)]
+    pub responses: Vec<Vec<Value>>,
+    /// Encoded responses, used instead of `responses`.
+    #[serde(default)]
+    pub raw_responses: Vec<Vec<u8>>,
+    /// Answers with this error code instead of a response.
+    #[serde(default)]
+    pub error_code: Option<u8>,
+    /// Additional delay of this function, in milliseconds.
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+}
+
+/// A callback sent periodically.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CallbackScenario {
+    pub callback: PacketRef,
+    pub period_ms: u64,
+    /// Values of the callback elements, one list per callback. The values are sent in a cycle.
+    #[serde(default)]
+    pub values: Vec<Vec<Value>>,
+    /// Encoded callbacks, used instead of `values`.
+    #[serde(default)]
+    pub raw_values: Vec<Vec<u8>>,
+}
+
+/// Identification of an emulated device, as reported by enumerate and `get_identity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub uid: Uid,
+    pub connected_uid: Uid,
+    pub position: char,
+    pub hardware_version: Version,
+    pub firmware_version: Version,
+    pub device_identifier: u16,
+}
+
+/// A device served by the [`Emulator`]. Enumerate and `get_identity` are answered by the emulator.
+pub trait EmulatedDevice: Send + 'static {
+    fn identity(&self) -> Identity;
+
+    /// Answers a request, the response is only sent if the client expects one.
+    fn handle<'a>(
+        &'a mut self,
+        function_id: u8,
+        payload: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, BrickletError>> + Send + 'a>>;
+
+    /// Called when the device is added to the emulator, to start sending callbacks.
+    fn start(&mut self, _callbacks: CallbackEmitter) {}
+}
+
+/// A device answering with the values of a [`DeviceScenario`].
+pub struct ScriptedDevice {
+    identity: Identity,
+    metadata: &'static DeviceMetadata,
+    functions: HashMap<u8, FunctionScript>,
+    callbacks: Vec<(u8, Duration, Vec<Vec<u8>>)>,
+    callback_tasks: Vec<AbortHandle>,
+}
+
+struct FunctionScript {
+    responses: Vec<Vec<u8>>,
+    next: usize,
+    error: Option<BrickletError>,
+    latency: Duration,
+}
+
+impl ScriptedDevice {
+    pub fn new(scenario: DeviceScenario) -> Result<ScriptedDevice, TinkerforgeError> {
+        let metadata = scenario.device.metadata();
+        let identity = Identity {
+            uid: scenario.uid,
+            connected_uid: scenario.connected_uid,
+            position: scenario.position,
+            hardware_version: Version::from_le_byte_slice(&scenario.hardware_version),
+            firmware_version: Version::from_le_byte_slice(&scenario.firmware_version),
+            device_identifier: scenario.device.into(),
+        };
+        let mut functions = HashMap::new();
+        for function in scenario.functions {
+            let packet = match &function.function {
+                PacketRef::Id(function_id) => metadata.packet(*function_id),
+                PacketRef::Name(name) => metadata.function(name),
+            }
+            .ok_or_else(|| TinkerforgeError::InvalidScenario(format!("{} has no function {:?}", metadata.name, function.function)))?;
+            let mut responses = function.raw_responses;
+            for values in &function.responses {
+                responses.push(encode_values(metadata, packet.responses(), values).map_err(|error| {
+                    TinkerforgeError::InvalidScenario(format!("Invalid response of {}.{}: {error}", metadata.name, packet.name))
+                })?);
+            }
+            functions.insert(
+                packet.function_id,
+                FunctionScript {
+                    responses,
+                    next: 0,
+                    error: function.error_code.map(BrickletError::from),
+                    latency: Duration::from_millis(function.latency_ms.unwrap_or_default()),
+                },
+            );
+        }
+        let mut callbacks = Vec::new();
+        for callback in scenario.callbacks {
+            let packet = match &callback.callback {
+                PacketRef::Id(function_id) => metadata.packet(*function_id),
+                PacketRef::Name(name) => metadata.callback(name),
+            }
+            .ok_or_else(|| TinkerforgeError::InvalidScenario(format!("{} has no callback {:?}", metadata.name, callback.callback)))?;
+            let mut values = callback.raw_values;
+            for callback_values in &callback.values {
+                values.push(encode_values(metadata, packet.responses(), callback_values).map_err(|error| {
+                    TinkerforgeError::InvalidScenario(format!("Invalid callback {}.{}: {error}", metadata.name, packet.name))
+                })?);
+            }
+            if values.is_empty() {
+                values.push(vec![0; response_size(packet.responses())]);
+            }
+            callbacks.push((packet.function_id, Duration::from_millis(callback.period_ms.max(1)), values));
+        }
+        Ok(ScriptedDevice { identity, metadata, functions, callbacks, callback_tasks: Vec::new() })
+    }
+}
+
+impl EmulatedDevice for ScriptedDevice {
+    fn identity(&self) -> Identity {
+        self.identity
+    }
+
+    fn handle<'a>(
+        &'a mut self,
+        function_id: u8,
+        _payload: &'a [u8],
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, BrickletError>> + Send + 'a>> {
+        let result = if let Some(script) = self.functions.get_mut(&function_id) {
+            let response = match script.error {
+                Some(error) => Err(error),
+                None => match script.responses.get(script.next.min(script.responses.len().saturating_sub(1))) {
+                    Some(response) => Ok(response.clone()),
+                    None => {
+                        Ok(self.metadata.packet(function_id).map(|packet| vec![0; response_size(packet.responses())]).unwrap_or_default())
+                    }
+                },
+            };
+            script.next += 1;
+            (response, script.latency)
+        } else if let Some(packet) = self.metadata.function_by_id(function_id) {
+            (Ok(vec![0; response_size(packet.responses())]), Duration::ZERO)
+        } else {
+            (Err(BrickletError::FunctionNotSupported), Duration::ZERO)
+        };
+        Box::pin(async move {
+            let (response, latency) = result;
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+            response
+        })
+    }
+
+    fn start(&mut self, callbacks: CallbackEmitter) {
+        for (function_id, period, values) in self.callbacks.iter().cloned() {
+            let callbacks = callbacks.clone();
+            self.callback_tasks.push(
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(period);
+                    for payload in values.iter().cycle() {
+                        interval.tick().await;
+                        callbacks.emit(function_id, payload.clone());
+                    }
+                })
+                .abort_handle(),
+            );
+        }
+    }
+}
+
+impl Drop for ScriptedDevice {
+    fn drop(&mut self) {
+        for task in &self.callback_tasks {
+            task.abort();
+        }
+    }
+}
+
+impl DeviceMetadata {
+    fn function_by_id(&self, function_id: u8) -> Option<&'static crate::metadata::PacketMetadata> {
+        self.packet(function_id).filter(|packet| packet.kind == crate::metadata::PacketKind::Function)
+    }
+}
+
+fn response_size<'a>(elements: impl Iterator<Item = &'a ElementMetadata>) -> usize {
+    elements.map(|element| element.element_type.byte_count(element.count)).sum()
+}
+
+/// Encodes one value per element, arrays are given as lists and constants by their name.
+fn encode_values<'a>(
+    device: &DeviceMetadata,
+    elements: impl Iterator<Item = &'a ElementMetadata>,
+    values: &[Value],
+) -> Result<Vec<u8>, String> {
+    let elements = elements.collect::<Vec<_>>();
+    if elements.len() != values.len() {
+        return Err(format!("expected {} values, got {}", elements.len(), values.len()));
+    }
+    let mut payload = Vec::new();
+    for (element, value) in elements.into_iter().zip(values) {
+        let constant_group = element.constant_group.and_then(|name| device.constant_group(name));
+        let value = match (constant_group, value) {
+            (Some(group), Value::String(name)) => match group.constants.iter().find(|constant| constant.name == name) {
+                Some(constant) => Value::from(constant.value),
+                None => return Err(format!("unknown constant {name} for {}", element.name)),
+            },
+            _ => value.clone(),
+        };
+        let values = match (&value, element.count) {
+            (Value::Array(values), _) if element.element_type != ElementType::String => values.clone(),
+            (value, 1) => vec![value.clone()],
+            (value, _) if element.element_type == ElementType::String => vec![value.clone()],
+            _ => return Err(format!("{} needs {} values", element.name, element.count)),
+        };
+        match element.element_type {
+            ElementType::String => {
+                let string = values[0].as_str().ok_or_else(|| format!("{} is not a string", element.name))?;
+                let mut bytes = vec![0; element.count];
+                string.try_write_to_slice(element.count, &mut bytes).map_err(|_| format!("{} cannot be encoded", element.name))?;
+                payload.extend(bytes);
+            }
+            ElementType::Bool => {
+                let mut bytes = vec![0; element.element_type.byte_count(element.count)];
+                for (index, value) in values.iter().enumerate().take(element.count) {
+                    if value.as_bool().ok_or_else(|| format!("{} is not a bool", element.name))? {
+                        bytes[index / 8] |= 1 << (index % 8);
+                    }
+                }
+                payload.extend(bytes);
+            }
+            element_type => {
+                if values.len() != element.count {
+                    return Err(format!("{} needs {} values", element.name, element.count));
+                }
+                for value in values {
+                    encode_scalar(element_type, &value, &mut payload)
+                        .ok_or_else(|| format!("{value} is not valid for {}", element.name))?;
+                }
+            }
+        }
+    }
+    Ok(payload)
+}
+
+fn encode_scalar(element_type: ElementType, value: &Value, payload: &mut Vec<u8>) -> Option<()> {
+    match element_type {
+        ElementType::U8 => payload.extend(u8::try_from(value.as_u64()?).ok()?.to_le_bytes()),
+        ElementType::I8 => payload.extend(i8::try_from(value.as_i64()?).ok()?.to_le_bytes()),
+        ElementType::U16 => payload.extend(u16::try_from(value.as_u64()?).ok()?.to_le_bytes()),
+        ElementType::I16 => payload.extend(i16::try_from(value.as_i64()?).ok()?.to_le_bytes()),
+        ElementType::U32 => payload.extend(u32::try_from(value.as_u64()?).ok()?.to_le_bytes()),
+        ElementType::I32 => payload.extend(i32::try_from(value.as_i64()?).ok()?.to_le_bytes()),
+        ElementType::U64 => payload.extend(value.as_u64()?.to_le_bytes()),
+        ElementType::I64 => payload.extend(value.as_i64()?.to_le_bytes()),
+        ElementType::Float => payload.extend((value.as_f64()? as f32).to_le_bytes()),
+        ElementType::Char => {
+            let byte = match value {
+                Value::String(string) if string.chars().count() == 1 => crate::byte_converter::latin1_byte(string.chars().next()?)?,
+                value => u8::try_from(value.as_u64()?).ok()?,
+            };
+            payload.push(byte)
+        }
+        ElementType::Bool | ElementType::String => return None,
+    }
+    Some(())
+}
+
+struct Shared {
+    devices: Mutex<BTreeMap<Uid, Box<dyn EmulatedDevice>>>,
+    callbacks: broadcast::Sender<CallbackPacket>,
+    disconnect: broadcast::Sender<()>,
+    latency_ms: AtomicU64,
+    secret: Option<String>,
+    disconnect_after_requests: Option<usize>,
+}
+
+/// A Brick Daemon serving emulated devices over TCP, stops when dropped.
+pub struct Emulator {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept_task: AbortHandle,
+}
+
+impl Emulator {
+    /// Starts serving the devices of the scenario, use port 0 to let the system choose a free port.
+    pub async fn bind(address: impl ToSocketAddrs, scenario: Scenario) -> Result<Emulator, TinkerforgeError> {
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            devices: Mutex::new(BTreeMap::new()),
+            callbacks: broadcast::channel(CALLBACK_CAPACITY).0,
+            disconnect: broadcast::channel(1).0,
+            latency_ms: AtomicU64::new(scenario.latency_ms),
+            secret: scenario.secret,
+            disconnect_after_requests: scenario.disconnect_after_requests,
+        });
+        {
+            let mut devices = shared.devices.lock().await;
+            for device in scenario.devices {
+                let mut device = ScriptedDevice::new(device)?;
+                let uid = device.identity().uid;
+                device.start(CallbackEmitter::with_sender(uid, shared.callbacks.clone()));
+                devices.insert(uid, Box::new(device));
+            }
+        }
+        let accept_shared = shared.clone();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        info!("Client {peer} connected");
+                        tokio::spawn(serve_connection(accept_shared.clone(), stream, peer));
+                    }
+                    Err(error) => warn!("Cannot accept connection: {error}"),
+                }
+            }
+        })
+        .abort_handle();
+        Ok(Emulator { shared, local_addr, accept_task })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Adds a device and announces it with a connected enumeration.
+    pub async fn add_device(&self, mut device: impl EmulatedDevice) {
+        let identity = device.identity();
+        device.start(self.callback_emitter(identity.uid));
+        self.shared.devices.lock().await.insert(identity.uid, Box::new(device));
+        self.enumerate(&identity, ENUMERATION_TYPE_CONNECTED);
+    }
+
+    /// Removes a device and announces it with a disconnected enumeration.
+    pub async fn remove_device(&self, uid: Uid) -> bool {
+        let Some(device) = self.shared.devices.lock().await.remove(&uid) else {
+            return false;
+        };
+        self.enumerate(&device.identity(), ENUMERATION_TYPE_DISCONNECTED);
+        true
+    }
+
+    /// Emitter sending callbacks on behalf of the device with the given uid to all clients.
+    pub fn callback_emitter(&self, uid: Uid) -> CallbackEmitter {
+        CallbackEmitter::with_sender(uid, self.shared.callbacks.clone())
+    }
+
+    /// Changes the delay before every response.
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.latency_ms.store(latency.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Closes all current connections.
+    pub fn disconnect_clients(&self) {
+        let _ = self.shared.disconnect.send(());
+    }
+
+    fn enumerate(&self, identity: &Identity, enumeration_type: u8) {
+        self.callback_emitter(identity.uid).emit(CALLBACK_ENUMERATE, enumerate_payload(identity, enumeration_type));
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+        self.disconnect_clients();
+    }
+}
+
+fn enumerate_payload(identity: &Identity, enumeration_type: u8) -> Vec<u8> {
+    let mut payload = vec![0; 26];
+    payload[0..8].copy_from_slice(&uid_string(identity.uid));
+    if enumeration_type != ENUMERATION_TYPE_DISCONNECTED {
+        payload[0..25].copy_from_slice(&identity_payload(identity));
+    }
+    payload[25] = enumeration_type;
+    payload
+}
+
+fn identity_payload(identity: &Identity) -> Vec<u8> {
+    let mut payload = vec![0; 25];
+    payload[0..8].copy_from_slice(&uid_string(identity.uid));
+    payload[8..16].copy_from_slice(&uid_string(identity.connected_uid));
+    identity.position.write_to_slice(&mut payload[16..17]);
+    identity.hardware_version.write_to_slice(&mut payload[17..20]);
+    identity.firmware_version.write_to_slice(&mut payload[20..23]);
+    identity.device_identifier.write_to_slice(&mut payload[23..25]);
+    payload
+}
+
+/// Uids are transferred as base58 string in enumerations, the uid 0 as `"0"`.
+fn uid_string(uid: Uid) -> [u8; 8] {
+    let string = if u32::from(uid) == 0 { "0".to_string() } else { uid.to_string() };
+    let mut bytes = [0; 8];
+    for (byte, char) in bytes.iter_mut().zip(string.bytes()) {
+        *byte = char;
+    }
+    bytes
+}
+
+fn encode_packet(header: PacketHeader, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; PacketHeader::SIZE + payload.len()];
+    header.write_to_slice(&mut packet[0..PacketHeader::SIZE]);
+    packet[PacketHeader::SIZE..].copy_from_slice(payload);
+    packet
+}
+
+fn error_code(error: BrickletError) -> u8 {
+    match error {
+        BrickletError::InvalidParameter => 1,
+        BrickletError::FunctionNotSupported => 2,
+        _ => 3,
+    }
+}
+
+struct Connection {
+    shared: Arc<Shared>,
+    authenticated: Arc<AtomicBool>,
+    server_nonce: Option<[u8; 4]>,
+    request_count: usize,
+    responses: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+async fn serve_connection(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (responses, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+    let authenticated = Arc::new(AtomicBool::new(shared.secret.is_none()));
+    let writer_task = tokio::spawn(async move {
+        while let Some(packet) = outgoing.recv().await {
+            if writer.write_all(&packet).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut callbacks = shared.callbacks.subscribe();
+    let callback_responses = responses.clone();
+    let callback_authenticated = authenticated.clone();
+    let callback_task = tokio::spawn(async move {
+        loop {
+            match callbacks.recv().await {
+                Ok(callback) if callback_authenticated.load(Ordering::Relaxed) => {
+                    let header = PacketHeader::with_payload(callback.uid, callback.function_id, 0, true, callback.payload.len() as u8);
+                    if callback_responses.send(encode_packet(header, &callback.payload)).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(count)) => warn!("Client {peer} skipped {count} callbacks"),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+    let mut disconnect = shared.disconnect.subscribe();
+    let mut connection = Connection { shared, authenticated, server_nonce: None, request_count: 0, responses };
+    tokio::select! {
+        _ = connection.serve(&mut reader) => {}
+        _ = disconnect.recv() => {}
+    }
+    info!("Client {peer} disconnected");
+    callback_task.abort();
+    writer_task.abort();
+}
+
+impl Connection {
+    /// Handles requests until the client disconnects or the connection has to be closed.
+    async fn serve(&mut self, reader: &mut OwnedReadHalf) {
+        loop {
+            let mut header_bytes = [0; PacketHeader::SIZE];
+            if reader.read_exact(&mut header_bytes).await.is_err() {
+                return;
+            }
+            let header = PacketHeader::from_le_byte_slice(&header_bytes);
+            if header.length < PacketHeader::SIZE as u8 || header.length > MAX_PACKET_SIZE || header.sequence_number == 0 {
+                warn!("Invalid request {header:?}, closing connection");
+                return;
+            }
+            let mut payload = vec![0; header.length as usize - PacketHeader::SIZE];
+            if reader.read_exact(&mut payload).await.is_err() {
+                return;
+            }
+            debug!("Received {header:?}: {payload:?}");
+            if !self.handle(header, &payload).await {
+                return;
+            }
+            self.request_count += 1;
+            if self.shared.disconnect_after_requests.is_some_and(|limit| self.request_count >= limit) {
+                info!("Closing connection after {} requests", self.request_count);
+                return;
+            }
+        }
+    }
+
+    /// Returns false if the connection has to be closed.
+    async fn handle(&mut self, header: PacketHeader, payload: &[u8]) -> bool {
+        let uid = u32::from(header.uid);
+        // the bindings request the nonce with the broadcast uid, so both are accepted
+        if uid == BRICK_DAEMON_UID || (uid == 0 && matches!(header.function_id, FUNCTION_GET_AUTHENTICATION_NONCE | FUNCTION_AUTHENTICATE))
+        {
+            return self.handle_brick_daemon(header, payload);
+        }
+        if !self.authenticated.load(Ordering::Relaxed) {
+            debug!("Dropping request of unauthenticated client");
+            return true;
+        }
+        let latency = self.shared.latency_ms.load(Ordering::Relaxed);
+        if latency > 0 {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+        let mut devices = self.shared.devices.lock().await;
+        if uid == 0 {
+            if header.function_id != FUNCTION_DISCONNECT_PROBE {
+                for device in devices.values_mut() {
+                    self.handle_device(device.as_mut(), header, payload).await;
+                }
+            }
+        } else if let Some(device) = devices.get_mut(&header.uid) {
+            self.handle_device(device.as_mut(), header, payload).await;
+        } else {
+            debug!("Dropping request for unknown uid {:?}", header.uid);
+        }
+        true
+    }
+
+    async fn handle_device(&self, device: &mut dyn EmulatedDevice, header: PacketHeader, payload: &[u8]) {
+        let identity = device.identity();
+        let result = match header.function_id {
+            FUNCTION_ENUMERATE => {
+                let _ = self.shared.callbacks.send(CallbackPacket {
+                    uid: identity.uid,
+                    function_id: CALLBACK_ENUMERATE,
+                    payload: enumerate_payload(&identity, ENUMERATION_TYPE_AVAILABLE),
+                });
+                Ok(Vec::new())
+            }
+            FUNCTION_GET_IDENTITY => Ok(identity_payload(&identity)),
+            function_id => device.handle(function_id, payload).await,
+        };
+        if header.response_expected {
+            self.respond(PacketHeader { uid: identity.uid, ..header }, result);
+        }
+    }
+
+    fn handle_brick_daemon(&mut self, header: PacketHeader, payload: &[u8]) -> bool {
+        let result = match header.function_id {
+            FUNCTION_GET_AUTHENTICATION_NONCE => {
+                let nonce = rand::random::<[u8; 4]>();
+                self.server_nonce = Some(nonce);
+                Ok(nonce.to_vec())
+            }
+            FUNCTION_AUTHENTICATE => {
+                if !self.authenticate(payload) {
+                    warn!("Authentication failed, closing connection");
+                    return false;
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(BrickletError::FunctionNotSupported),
+        };
+        if header.response_expected || header.function_id == FUNCTION_GET_AUTHENTICATION_NONCE {
+            self.respond(header, result);
+        }
+        true
+    }
+
+    /// Checks the HMAC-SHA1 of server and client nonce, keyed with the secret.
+    fn authenticate(&mut self, payload: &[u8]) -> bool {
+        let Some(secret) = &self.shared.secret else {
+            return true;
+        };
+        let (Some(server_nonce), Some(client_nonce), Some(digest)) = (self.server_nonce.take(), payload.get(0..4), payload.get(4..24))
+        else {
+            return false;
+        };
+        let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(&server_nonce);
+        mac.update(client_nonce);
+        let valid = mac.verify_slice(digest).is_ok();
+        self.authenticated.store(valid, Ordering::Relaxed);
+        valid
+    }
+
+    fn respond(&self, mut header: PacketHeader, result: Result<Vec<u8>, BrickletError>) {
+        let payload = match result {
+            Ok(payload) => payload,
+            Err(error) => {
+                header.error_code = error_code(error);
+                Vec::new()
+            }
+        };
+        let header = PacketHeader { length: (PacketHeader::SIZE + payload.len()) as u8, ..header };
+        let _ = self.responses.send(encode_packet(header, &payload));
+    }
+}
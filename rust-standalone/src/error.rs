@@ -1,4 +1,4 @@
-use crate::converting_receiver::BrickletError;
+use crate::{converting_receiver::BrickletError, ip_connection::Version};
 use std::array::TryFromSliceError;
 use std::io;
 use thiserror::Error;
@@ -16,4 +16,21 @@ pub enum TinkerforgeError {
     PackedDecodingError(#[from] TryFromSliceError),
     #[error("Legacy Error: {0}")]
     BrickletError(#[from] BrickletError),
+    #[error("{function} requires firmware {required} or newer, but the device runs {actual}")]
+    UnsupportedFirmware { function: &'static str, required: Version, actual: Version },
+    #[error("Invalid call: {0}")]
+    InvalidCall(String),
+    #[cfg(feature = "dynamic")]
+    #[error("Cannot parse device definition: {0}")]
+    DefinitionError(#[from] serde_json::Error),
+    #[error("Invalid recording: {0}")]
+    InvalidRecording(String),
+    #[error("Replay mismatch: {0}")]
+    ReplayMismatch(String),
+    #[cfg(feature = "emulator")]
+    #[error("Invalid scenario: {0}")]
+    InvalidScenario(String),
+    #[cfg(any(feature = "proxy", feature = "mqtt", feature = "modbus", feature = "logger"))]
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
 }
@@ -0,0 +1,392 @@
+//! Serves the measured values of all devices of a Brick Daemon to Prometheus.
+//!
+//! The exporter enumerates the devices and tracks them as they are plugged in and removed. On every scrape of
+//! `/metrics` it reads the measured values of each device (see [`DeviceMetadata::primary_values`]) and reports them in
+//! SI units, e.g. `tinkerforge_temperature_celsius` or `tinkerforge_air_pressure_pascals`, labeled with the uid, device
+//! type, position and connected uid:
+//! ```text
+//! tinkerforge_temperature_celsius{connected_uid="0",device_type="temperature_v2_bricklet",position="a",uid="ZQH"} 23.12
+//! ```
+//! As only devices present at the time of the scrape are reported, series of removed devices end right away. Values
+//! that cannot be read in time are left out of the scrape. The metrics of the connection to the Brick Daemon, like
+//! timeouts and reconnects, are served along with them (see [`metrics`](crate::metrics)).
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use prometheus::{Encoder, Gauge, GaugeVec, Opts, Registry, TextEncoder};
+use serde_json::{Map, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{watch, Semaphore},
+    task::{AbortHandle, JoinSet},
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    dynamic::{DeviceDefinition, DynamicDevice},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerateResponse, EnumerationType},
+    metadata::{DeviceMetadata, ElementMetadata},
+    metrics::ConnectionMetrics,
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Reads in flight at once, one less than there are sequence numbers.
+const MAX_PENDING_REQUESTS: usize = 14;
+const MAX_REQUEST_SIZE: usize = 8192;
+const LABELS: [&str; 4] = ["uid", "device_type", "position", "connected_uid"];
+
+/// Brick Daemon of an [`Exporter`].
+#[derive(Clone, Debug)]
+pub struct ExporterConfig {
+    pub brick_daemon: String,
+    pub secret: Option<String>,
+    /// Maximum time to wait for a value during a scrape.
+    pub timeout: Duration,
+}
+
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        ExporterConfig { brick_daemon: "localhost:4223".to_string(), secret: None, timeout: Duration::from_secs(1) }
+    }
+}
+
+/// A Prometheus exporter serving `/metrics` over HTTP, stops when dropped.
+pub struct Exporter {
+    local_addr: SocketAddr,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Exporter {
+    /// Starts serving scrapes and connects to the Brick Daemon, use port 0 to let the system choose a free port.
+    pub async fn bind(address: impl ToSocketAddrs, config: ExporterConfig) -> Result<Exporter, TinkerforgeError> {
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            connection: watch::channel(None).0,
+            devices: Mutex::new(BTreeMap::new()),
+            definitions: Mutex::new(BTreeMap::new()),
+            pending_requests: Arc::new(Semaphore::new(MAX_PENDING_REQUESTS)),
+            connection_metrics: ConnectionMetrics::new(),
+        });
+        let connection_task = tokio::spawn(maintain_connection(shared.clone())).abort_handle();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve_scrape(shared.clone(), stream));
+                    }
+                    Err(error) => warn!("Cannot accept connection: {error}"),
+                }
+            }
+        })
+        .abort_handle();
+        Ok(Exporter { local_addr, tasks: vec![connection_task, accept_task] })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct Shared {
+    config: ExporterConfig,
+    connection: watch::Sender<Option<AsyncIpConnection>>,
+    /// Latest enumeration of every connected device.
+    devices: Mutex<BTreeMap<Uid, EnumerateResponse>>,
+    definitions: Mutex<BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>>,
+    pending_requests: Arc<Semaphore>,
+    connection_metrics: ConnectionMetrics,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Shared {
+    fn definition(&self, device: &DeviceMetadata) -> Arc<DeviceDefinition> {
+        lock(&self.definitions).entry(device.identifier).or_insert_with(|| Arc::new(DeviceDefinition::from(device))).clone()
+    }
+
+    /// Reads the values of all devices and encodes them in the text format.
+    async fn scrape(&self) -> Vec<u8> {
+        let registry = Registry::new();
+        let connection = self.connection.borrow().clone();
+        let up = Gauge::new("tinkerforge_up", "Whether the Brick Daemon is connected.").expect("valid metric");
+        up.set(if connection.is_some() { 1.0 } else { 0.0 });
+        registry.register(Box::new(up.clone())).expect("unique metric");
+        self.connection_metrics.register(&registry).expect("unique metric");
+        let info_labels = [LABELS.as_slice(), &["hardware_version", "firmware_version"]].concat();
+        let info = GaugeVec::new(Opts::new("tinkerforge_device_info", "Devices connected to the Brick Daemon."), &info_labels)
+            .expect("valid metric");
+        registry.register(Box::new(info.clone())).expect("unique metric");
+
+        let devices = lock(&self.devices).values().copied().collect::<Vec<_>>();
+        let mut reads = JoinSet::new();
+        for enumeration in devices {
+            let hardware_version = enumeration.hardware_version.to_string();
+            let firmware_version = enumeration.firmware_version.to_string();
+            let labels = labels(&enumeration);
+            let info_values = labels.iter().map(String::as_str).chain([hardware_version.as_str(), firmware_version.as_str()]);
+            info.with_label_values(&info_values.collect::<Vec<_>>()).set(1.0);
+            let (Some(connection), Some(device)) = (&connection, enumeration.device_identifier.parsed()) else {
+                continue;
+            };
+            for (packet, element) in device.metadata().primary_values() {
+                let mut device = DynamicDevice::new(enumeration.uid, connection.clone(), self.definition(device.metadata()));
+                device.set_timeout(self.config.timeout);
+                let pending_requests = self.pending_requests.clone();
+                let labels = labels.clone();
+                reads.spawn(async move {
+                    let _permit = pending_requests.acquire_owned().await.ok()?;
+                    match device.call(packet.name, Value::Object(Map::new())).await {
+                        Ok(response) => Some((element, labels, response[element.name].as_f64()?)),
+                        Err(error) => {
+                            debug!("Cannot read {} of {}: {error}", packet.name, device.uid());
+                            None
+                        }
+                    }
+                });
+            }
+        }
+
+        let mut gauges = BTreeMap::<String, GaugeVec>::new();
+        while let Some(read) = reads.join_next().await {
+            let Ok(Some((element, labels, value))) = read else {
+                continue;
+            };
+            let name = metric_name(element);
+            let gauge = gauges.entry(name).or_insert_with_key(|name| {
+                let gauge = GaugeVec::new(Opts::new(name, metric_help(element)), &LABELS).expect("valid metric");
+                registry.register(Box::new(gauge.clone())).expect("unique metric");
+                gauge
+            });
+            let factor = element.si_unit.map_or(1.0, |si_unit| si_unit.factor);
+            gauge.with_label_values(&labels.iter().map(String::as_str).collect::<Vec<_>>()).set(value * factor);
+        }
+        let mut text = Vec::new();
+        if let Err(error) = TextEncoder::new().encode(&registry.gather(), &mut text) {
+            warn!("Cannot encode metrics: {error}");
+        }
+        text
+    }
+}
+
+fn labels(enumeration: &EnumerateResponse) -> Vec<String> {
+    let uid_text = |uid: Uid| if u32::from(uid) == 0 { "0".to_string() } else { uid.to_string() };
+    let device_type = enumeration
+        .device_identifier
+        .parsed()
+        .map(|device| format!("{}_{}", device.metadata().name, device.metadata().category).to_lowercase().replace([' ', '-'], "_"))
+        .unwrap_or_else(|| enumeration.device_identifier.raw().to_string());
+    vec![uid_text(enumeration.uid), device_type, enumeration.position.to_string(), uid_text(enumeration.connected_uid)]
+}
+
+/// Name of the metric of a value, with the unit in the plural form Prometheus uses, e.g. `tinkerforge_voltage_volts`.
+fn metric_name(element: &ElementMetadata) -> String {
+    let unit = match element.si_unit.map(|si_unit| si_unit.symbol) {
+        Some("°C") => "celsius",
+        Some("K") => "kelvin",
+        Some("°") => "degrees",
+        Some("%") => "percent",
+        Some("ppm") => "ppm",
+        Some("Pa") => "pascals",
+        Some("m") => "meters",
+        Some("m/s") => "meters_per_second",
+        Some("A") => "amperes",
+        Some("V") => "volts",
+        Some("W") => "watts",
+        Some("W/m²") => "watts_per_square_meter",
+        Some("lx") => "lux",
+        Some("T") => "teslas",
+        Some("g") => "grams",
+        Some("g/m³") => "grams_per_cubic_meter",
+        Some("dB") => "decibels",
+        _ => "",
+    };
+    match unit {
+        "" => format!("tinkerforge_{}", element.name),
+        unit if element.name.ends_with(unit.trim_end_matches('s')) => format!("tinkerforge_{}", element.name),
+        unit => format!("tinkerforge_{}_{unit}", element.name),
+    }
+}
+
+fn metric_help(element: &ElementMetadata) -> String {
+    let mut help = element.name.replace('_', " ");
+    help[..1].make_ascii_uppercase();
+    match element.si_unit.map(|si_unit| si_unit.symbol).filter(|symbol| !symbol.is_empty()) {
+        Some(symbol) => format!("{help} in {symbol}."),
+        None => format!("{help}."),
+    }
+}
+
+/// Connects to the Brick Daemon and keeps track of its devices, reconnecting when the connection fails.
+async fn maintain_connection(shared: Arc<Shared>) {
+    let address = &shared.config.brick_daemon;
+    loop {
+        let mut connection = match AsyncIpConnection::with_metrics(address.clone(), shared.connection_metrics.clone()).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Cannot connect to {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if let Some(secret) = &shared.config.secret {
+            if let Err(error) = connection.authenticate(secret).await {
+                warn!("Cannot authenticate at {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        let enumerations = match connection.enumerate().await {
+            Ok(enumerations) => enumerations,
+            Err(error) => {
+                warn!("Cannot enumerate devices of {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Connected to {address}");
+        shared.connection.send_replace(Some(connection));
+        tokio::pin!(enumerations);
+        while let Some(enumeration) = enumerations.next().await {
+            let mut devices = lock(&shared.devices);
+            if enumeration.enumeration_type == EnumerationType::Disconnected {
+                devices.remove(&enumeration.uid);
+            } else {
+                devices.insert(enumeration.uid, enumeration);
+            }
+        }
+        warn!("Lost connection to {address}");
+        shared.connection.send_replace(None);
+        lock(&shared.devices).clear();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Answers a single HTTP request, only `GET /metrics` is served.
+async fn serve_scrape(shared: Arc<Shared>, mut stream: TcpStream) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(count) => request.extend_from_slice(&buffer[..count]),
+        }
+        if request.len() > MAX_REQUEST_SIZE {
+            return;
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+    let path = path.split('?').next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", prometheus::TEXT_FORMAT, shared.scrape().await),
+        ("GET", _) => ("404 Not Found", "text/plain", b"Metrics are served at /metrics\n".to_vec()),
+        _ => ("405 Method Not Allowed", "text/plain", Vec::new()),
+    };
+    let header =
+        format!("HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+    if stream.write_all(header.as_bytes()).await.is_ok() {
+        let _ = stream.write_all(&body).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        exporter::metric_name,
+        metadata::{self, DeviceMetadata},
+        DeviceIdentifier,
+    };
+
+    #[test]
+    fn test_metric_names() {
+        let names = |device: &DeviceMetadata| device.primary_values().map(|(_, element)| metric_name(element)).collect::<Vec<_>>();
+        assert_eq!(names(metadata::device(DeviceIdentifier::TemperatureV2Bricklet)), vec!["tinkerforge_temperature_celsius"]);
+        assert_eq!(
+            names(metadata::device(DeviceIdentifier::BarometerV2Bricklet)),
+            vec!["tinkerforge_air_pressure_pascals", "tinkerforge_altitude_meters", "tinkerforge_temperature_celsius"]
+        );
+    }
+}
+
+#[cfg(all(test, feature = "emulator"))]
+mod emulator_test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use crate::{
+        emulator::{Emulator, Scenario},
+        exporter::{Exporter, ExporterConfig},
+    };
+
+    async fn scrape(address: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn test_hot_plug() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let scenario = Scenario::from_toml(
+                r#"
+                [[devices]]
+                uid = "ZQH"
+                device = "TemperatureV2Bricklet"
+                functions = [{ function = "get_temperature", responses = [[2312]] }]
+                "#,
+            )
+            .unwrap();
+            let emulator = Emulator::bind("127.0.0.1:0", scenario).await.unwrap();
+            let config = ExporterConfig { brick_daemon: emulator.local_addr().to_string(), ..ExporterConfig::default() };
+            let exporter = Exporter::bind("127.0.0.1:0", config).await.unwrap();
+            let series =
+                r#"tinkerforge_temperature_celsius{connected_uid="0",device_type="temperature_v2_bricklet",position="a",uid="ZQH"} 23.12"#;
+            let mut metrics = String::new();
+            for _ in 0..50 {
+                metrics = scrape(exporter.local_addr()).await;
+                if metrics.contains(series) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert!(metrics.starts_with("HTTP/1.1 200 OK"), "{metrics}");
+            assert!(metrics.contains(series), "{metrics}");
+
+            emulator.remove_device("ZQH".parse().unwrap()).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let metrics = scrape(exporter.local_addr()).await;
+            // only the counters of the connection still mention the removed device
+            assert!(!metrics.lines().any(|line| line.contains("ZQH") && !line.starts_with("tinkerforge_callbacks_total")), "{metrics}");
+            assert!(metrics.contains("tinkerforge_up 1"), "{metrics}");
+            assert!(metrics.contains("tinkerforge_connected 1"), "{metrics}");
+        });
+    }
+}
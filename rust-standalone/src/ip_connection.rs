@@ -4,6 +4,9 @@ use std::{
     str::{self, FromStr},
 };
 
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
 use crate::{
     base58::{Base58Error, Uid},
     bindings::DeviceIdentifier,
@@ -13,18 +16,22 @@ use crate::{
 pub mod async_io {
     use std::{
         borrow::BorrowMut,
-        fmt::Debug,
+        collections::HashSet,
+        fmt::{Debug, Formatter},
+        fs::File,
+        io::{BufWriter, Write},
         ops::{Deref, DerefMut},
+        path::Path,
         sync::{
             atomic::{AtomicBool, Ordering},
             Arc,
         },
-        time::Duration,
+        time::{Duration, Instant},
     };
 
     use log::{debug, error, info, warn};
     use tokio::{
-        io::{self, AsyncReadExt, AsyncWriteExt, WriteHalf},
+        io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
         net::{TcpStream, ToSocketAddrs},
         sync::{
             broadcast::{self, Receiver},
@@ -42,12 +49,22 @@ pub mod async_io {
         base58::{Base58Error, Uid},
         byte_converter::{FromByteSlice, ToBytes},
         error::TinkerforgeError,
-        ip_connection::{EnumerateResponse, PacketHeader},
+        ip_connection::{authentication_digest, AuthenticateError, EnumerateResponse, PacketHeader},
+        metrics::Metrics,
+        recording::{record, PacketDirection, Recorder, SharedRecorder},
+        trace::{self, RequestSpan},
     };
 
+    /// Uid of the Brick Daemon itself, which answers the authentication functions.
+    const BRICK_DAEMON_UID: u32 = 1;
+    const FUNCTION_GET_AUTHENTICATION_NONCE: u8 = 1;
+    const FUNCTION_AUTHENTICATE: u8 = 2;
+    const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(5);
+
     #[derive(Debug, Clone)]
     pub struct AsyncIpConnection {
         inner: Arc<Mutex<InnerAsyncIpConnection>>,
+        metrics: Metrics,
     }
 
     impl AsyncIpConnection {
@@ -81,34 +98,169 @@ pub mod async_io {
         pub(crate) async fn callback_stream(&mut self, uid: Uid, function_id: u8) -> impl Stream<Item = PacketData> {
             self.inner.borrow_mut().lock().await.callback_stream(uid, function_id).await
         }
+        /// Authenticates the connection with the secret of the Brick Daemon or Ethernet/WIFI Extension.
+        pub async fn authenticate(&mut self, secret: &str) -> Result<(), TinkerforgeError> {
+            if !secret.is_ascii() {
+                return Err(TinkerforgeError::InvalidCall(AuthenticateError::SecretInvalid.to_string()));
+            }
+            let brick_daemon = Uid::from(BRICK_DAEMON_UID);
+            let server_nonce = RequestSpan::new(brick_daemon, FUNCTION_GET_AUTHENTICATION_NONCE, None, Some("get_authentication_nonce"))
+                .run(self.get(brick_daemon, FUNCTION_GET_AUTHENTICATION_NONCE, &[], AUTHENTICATION_TIMEOUT))
+                .await?;
+            if server_nonce.body().len() != 4 {
+                return Err(TinkerforgeError::InvalidCall(AuthenticateError::CouldNotGetServerNonce.to_string()));
+            }
+            let client_nonce = rand::random::<[u8; 4]>();
+            let mut payload = [0; 24];
+            payload[0..4].copy_from_slice(&client_nonce);
+            payload[4..24].copy_from_slice(&authentication_digest(secret.as_bytes(), server_nonce.body(), &client_nonce));
+            RequestSpan::new(brick_daemon, FUNCTION_AUTHENTICATE, None, Some("authenticate"))
+                .run(self.set(brick_daemon, FUNCTION_AUTHENTICATE, &payload, Some(AUTHENTICATION_TIMEOUT)))
+                .await?;
+            Ok(())
+        }
+        /// Sends a request and waits for its response without blocking other requests meanwhile, e.g. to forward
+        /// requests of other clients. Returns `None` if no response is expected.
+        #[cfg_attr(not(feature = "proxy"), allow(dead_code))]
+        pub(crate) async fn forward(
+            &mut self,
+            uid: Uid,
+            function_id: u8,
+            payload: &[u8],
+            response_expected: bool,
+            timeout: Duration,
+        ) -> Result<Option<PacketData>, TinkerforgeError> {
+            let started = Instant::now();
+            let (seq, stream) = self.inner.lock().await.send_request(uid, function_id, payload, response_expected).await?;
+            trace::request_sent(seq);
+            if !response_expected {
+                trace::request_finished(Ok(None), started);
+                return Ok(None);
+            }
+            let _in_flight = self.metrics.in_flight();
+            let stream = stream.timeout(timeout);
+            tokio::pin!(stream);
+            let response = stream.next().await;
+            self.inner.lock().await.pending_sequence_numbers.remove(&seq);
+            let result = match response {
+                Some(Ok(Ok(packet))) => Ok(Some(packet)),
+                Some(Err(_)) => {
+                    self.metrics.timeout(uid, function_id);
+                    Err(TinkerforgeError::NoResponseReceived)
+                }
+                _ => Err(TinkerforgeError::NoResponseReceived),
+            };
+            trace::request_finished(result.as_ref().map(Option::as_ref), started);
+            result
+        }
+        /// All packets received from now on, ends when the connection is closed.
+        #[cfg_attr(not(feature = "proxy"), allow(dead_code))]
+        pub(crate) async fn received_packets(&mut self) -> impl Stream<Item = PacketData> {
+            let inner = self.inner.lock().await;
+            BroadcastStream::new(inner.receiver.resubscribe()).map_while(inner.while_some()).filter_map(Result::ok)
+        }
+        /// Writes all packets sent and received from now on to `writer`, replacing a running recording. See
+        /// [`recording`](crate::recording) for the format.
+        pub async fn start_recording(&mut self, writer: impl Write + Send + 'static) {
+            *self.inner.lock().await.recorder.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Recorder::new(writer));
+        }
+        /// Writes all packets sent and received from now on to a file, replacing a running recording.
+        pub async fn record_to_file(&mut self, path: impl AsRef<Path>) -> Result<(), TinkerforgeError> {
+            let file = File::create(path)?;
+            self.start_recording(BufWriter::new(file)).await;
+            Ok(())
+        }
+        /// Writes all packets sent and received from now on to `writer` as pcap file, replacing a running recording.
+        /// See [`pcap`](crate::pcap) for the format.
+        pub async fn start_pcap_capture(&mut self, writer: impl Write + Send + 'static) -> Result<(), TinkerforgeError> {
+            let recorder = Recorder::pcap(writer)?;
+            *self.inner.lock().await.recorder.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(recorder);
+            Ok(())
+        }
+        /// Writes all packets sent and received from now on to a pcap file, replacing a running recording.
+        pub async fn capture_to_pcap_file(&mut self, path: impl AsRef<Path>) -> Result<(), TinkerforgeError> {
+            let file = File::create(path)?;
+            self.start_pcap_capture(BufWriter::new(file)).await
+        }
+        /// Stops a running recording or capture and flushes it.
+        pub async fn stop_recording(&mut self) -> Result<(), TinkerforgeError> {
+            let recorder = self.inner.lock().await.recorder.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+            if let Some(mut recorder) = recorder {
+                recorder.flush()?;
+            }
+            Ok(())
+        }
     }
 
     impl AsyncIpConnection {
         pub async fn new<T: ToSocketAddrs + Debug + Clone + Send + 'static>(addr: T) -> Result<Self, TinkerforgeError> {
-            Ok(Self { inner: Arc::new(Mutex::new(InnerAsyncIpConnection::new(addr).await?)) })
+            Self::connect(addr, Metrics::default()).await
+        }
+        /// Connects like [`new`](Self::new) and counts the traffic of the connection in `metrics`, see
+        /// [`metrics`](crate::metrics).
+        #[cfg(feature = "prometheus")]
+        pub async fn with_metrics<T: ToSocketAddrs + Debug + Clone + Send + 'static>(
+            addr: T,
+            metrics: crate::metrics::ConnectionMetrics,
+        ) -> Result<Self, TinkerforgeError> {
+            Self::connect(addr, Metrics::new(metrics)).await
+        }
+        async fn connect<T: ToSocketAddrs + Debug + Clone + Send + 'static>(addr: T, metrics: Metrics) -> Result<Self, TinkerforgeError> {
+            let inner = InnerAsyncIpConnection::new(addr, metrics.clone()).await?;
+            Ok(Self { inner: Arc::new(Mutex::new(inner)), metrics })
+        }
+        /// Creates a connection over another transport than TCP, e.g. an in-memory stream for replays.
+        pub(crate) fn from_stream<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S, peer: String, metrics: Metrics) -> Self {
+            Self { inner: Arc::new(Mutex::new(InnerAsyncIpConnection::from_stream(stream, peer, metrics.clone()))), metrics }
+        }
+        #[cfg(feature = "prometheus")]
+        pub(crate) fn metrics(&self) -> &Metrics {
+            &self.metrics
         }
     }
 
-    #[derive(Debug)]
     struct InnerAsyncIpConnection {
-        write_stream: WriteHalf<TcpStream>,
+        write_stream: Box<dyn AsyncWrite + Send + Unpin>,
         receiver: Receiver<Option<PacketData>>,
         seq_num: u8,
         running: Arc<AtomicBool>,
         abort_handle: AbortHandle,
+        recorder: SharedRecorder,
+        /// Sequence numbers of forwarded requests still waiting for their response.
+        pending_sequence_numbers: HashSet<u8>,
+        metrics: Metrics,
+    }
+
+    impl Debug for InnerAsyncIpConnection {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("InnerAsyncIpConnection")
+                .field("receiver", &self.receiver)
+                .field("seq_num", &self.seq_num)
+                .field("running", &self.running)
+                .field("abort_handle", &self.abort_handle)
+                .finish_non_exhaustive()
+        }
     }
 
     impl InnerAsyncIpConnection {
-        pub async fn new<T: ToSocketAddrs + Clone + Debug + Send + 'static>(addr: T) -> Result<Self, TinkerforgeError> {
+        pub async fn new<T: ToSocketAddrs + Clone + Debug + Send + 'static>(addr: T, metrics: Metrics) -> Result<Self, TinkerforgeError> {
             let socket = TcpStream::connect(addr.clone()).await?;
             Self::enable_keepalive(&socket)?;
+            Ok(Self::from_stream(socket, format!("{addr:?}"), metrics))
+        }
 
-            let (mut rd, write_stream) = io::split(socket);
+        fn from_stream<S: AsyncRead + AsyncWrite + Send + 'static>(stream: S, addr: String, metrics: Metrics) -> Self {
+            let (mut rd, write_stream) = io::split(stream);
+            let recorder = SharedRecorder::default();
+            let receive_recorder = recorder.clone();
             let (enum_tx, receiver) = broadcast::channel(512);
             let running = Arc::new(AtomicBool::new(true));
             let running_clone = running.clone();
+            metrics.connected();
+            trace::connected(&addr);
+            let receive_metrics = metrics.clone();
             let abort_handle = tokio::spawn(async move {
-                loop {
+                let reason = loop {
                     let mut header_buffer = Box::new([0; PacketHeader::SIZE]);
                     match rd.read_exact(header_buffer.deref_mut()).await {
                         Ok(8) => {
@@ -122,34 +274,50 @@ pub mod async_io {
                                 }
                                 Err(e) => panic!("Error from socket: {}", e),
                             }
+                            record(&receive_recorder, PacketDirection::Received, header_buffer.deref(), &body);
+                            receive_metrics.received(&header);
+                            if header.sequence_number == 0 {
+                                trace::callback_received(&header);
+                            }
                             let packet_data = PacketData { header, body };
                             debug!("Received: {packet_data:?}");
                             if let Err(error) = enum_tx.send(Some(packet_data)) {
-                                warn!("Cannot process packet from {addr:?}: {error}");
-                                break;
+                                warn!("Cannot process packet from {addr}: {error}");
+                                break "connection dropped".to_string();
                             }
                         }
                         Ok(n) => {
-                            error!("Unexpected read count from {addr:?}: {}", n);
+                            error!("Unexpected read count from {addr}: {}", n);
                             if let Err(error) = enum_tx.send(None) {
                                 warn!("Cannot close connection on read error: {error}");
                             }
-                            break;
+                            break format!("unexpected read count {n}");
                         }
                         Err(e) => {
-                            error!("Error from socket {addr:?}: {e}");
+                            error!("Error from socket {addr}: {e}");
                             if let Err(error) = enum_tx.send(None) {
                                 warn!("Cannot close connection on communication error: {error}");
                             }
-                            break;
+                            break e.to_string();
                         }
                     };
-                }
+                };
                 running_clone.store(false, Ordering::Relaxed);
+                receive_metrics.disconnected();
+                trace::disconnected(&addr, &reason);
                 info!("Terminated receiver thread");
             })
             .abort_handle();
-            Ok(Self { write_stream, abort_handle, seq_num: 1, receiver, running })
+            Self {
+                write_stream: Box::new(write_stream),
+                abort_handle,
+                seq_num: 1,
+                receiver,
+                running,
+                recorder,
+                pending_sequence_numbers: HashSet::new(),
+                metrics,
+            }
         }
 
         fn enable_keepalive(socket: &TcpStream) -> Result<(), TinkerforgeError> {
@@ -164,7 +332,7 @@ pub mod async_io {
                 return Ok(Box::new(empty()));
             }
             let request = Request::Set { uid: Uid::zero(), function_id: 254, payload: &[] };
-            let stream = BroadcastStream::new(self.receiver.resubscribe()).map_while(Self::while_some).filter_map(|p| match p {
+            let stream = BroadcastStream::new(self.receiver.resubscribe()).map_while(self.while_some()).filter_map(|p| match p {
                 Ok(p) if p.header.function_id == 253 => Result::<EnumerateResponse, Base58Error>::from_le_byte_slice(&p.body).ok(),
                 _ => None,
             });
@@ -181,7 +349,7 @@ pub mod async_io {
         async fn get_authentication_nonce(&mut self) -> Result<[u8; 4], TinkerforgeError> {
             let request = Request::Set { uid: Uid::zero(), function_id: 1, payload: &[] };
             let seq = self.next_seq();
-            let stream = BroadcastStream::new(self.receiver.resubscribe()).map_while(Self::while_some).timeout(Duration::from_secs(5));
+            let stream = BroadcastStream::new(self.receiver.resubscribe()).map_while(self.while_some()).timeout(Duration::from_secs(5));
             self.send_packet(&request, seq, true).await?;
             tokio::pin!(stream);
             let option = stream.next().await;
@@ -210,19 +378,34 @@ pub mod async_io {
             let seq = self.next_seq();
             if let Some(timeout) = timeout {
                 let stream = BroadcastStream::new(self.receiver.resubscribe())
-                    .map_while(Self::while_some)
+                    .map_while(self.while_some())
                     .filter(Self::filter_response(uid, function_id, seq))
                     .timeout(timeout);
-                self.send_packet(&request, seq, true).await?;
+                let _in_flight = self.metrics.in_flight();
+                let started = Instant::now();
                 tokio::pin!(stream);
-                if let Some(done) = stream.next().await {
-                    Ok(Some(done.map_err(|_| TinkerforgeError::NoResponseReceived)??))
-                } else {
-                    Err(TinkerforgeError::NoResponseReceived)
-                }
+                let result = match self.send_packet(&request, seq, true).await {
+                    Ok(()) => {
+                        trace::request_sent(seq);
+                        match stream.next().await {
+                            Some(Ok(response)) => response.map(Some).map_err(TinkerforgeError::from),
+                            Some(Err(_)) => {
+                                self.metrics.timeout(uid, function_id);
+                                Err(TinkerforgeError::NoResponseReceived)
+                            }
+                            None => Err(TinkerforgeError::NoResponseReceived),
+                        }
+                    }
+                    Err(error) => Err(error),
+                };
+                trace::request_finished(result.as_ref().map(Option::as_ref), started);
+                result
             } else {
-                self.send_packet(&request, seq, false).await?;
-                Ok(None)
+                let started = Instant::now();
+                let result = self.send_packet(&request, seq, false).await.map(|()| None);
+                trace::request_sent(seq);
+                trace::request_finished(result.as_ref().map(Option::as_ref), started);
+                result
             }
         }
 
@@ -239,22 +422,80 @@ pub mod async_io {
             let request = Request::Get { uid, function_id, payload };
             let seq = self.next_seq();
             let stream = BroadcastStream::new(self.receiver.resubscribe())
-                .map_while(Self::while_some)
+                .map_while(self.while_some())
                 .filter(Self::filter_response(uid, function_id, seq))
                 .timeout(timeout);
             tokio::pin!(stream);
-            self.send_packet(&request, seq, true).await?;
-            Ok(stream.next().await.ok_or(TinkerforgeError::NoResponseReceived)?.map_err(|_| TinkerforgeError::NoResponseReceived)??)
+            let _in_flight = self.metrics.in_flight();
+            let started = Instant::now();
+            let result = match self.send_packet(&request, seq, true).await {
+                Ok(()) => {
+                    trace::request_sent(seq);
+                    match stream.next().await {
+                        Some(Ok(response)) => response.map_err(TinkerforgeError::from),
+                        Some(Err(_)) => {
+                            self.metrics.timeout(uid, function_id);
+                            Err(TinkerforgeError::NoResponseReceived)
+                        }
+                        None => Err(TinkerforgeError::NoResponseReceived),
+                    }
+                }
+                Err(error) => Err(error),
+            };
+            trace::request_finished(result.as_ref().map(Some), started);
+            result
+        }
+
+        /// Sends a request with a sequence number not used by another forwarded request and returns the stream its
+        /// response arrives on.
+        #[cfg_attr(not(feature = "proxy"), allow(dead_code))]
+        async fn send_request(
+            &mut self,
+            uid: Uid,
+            function_id: u8,
+            payload: &[u8],
+            response_expected: bool,
+        ) -> Result<(u8, impl Stream<Item = Result<PacketData, BroadcastStreamRecvError>>), TinkerforgeError> {
+            let request = Request::Set { uid, function_id, payload };
+            let mut seq = self.next_seq();
+            for _ in 0..15 {
+                if !self.pending_sequence_numbers.contains(&seq) {
+                    break;
+                }
+                seq = self.next_seq();
+            }
+            if self.pending_sequence_numbers.contains(&seq) {
+                return Err(TinkerforgeError::InvalidCall("All sequence numbers are in use".to_string()));
+            }
+            let stream = BroadcastStream::new(self.receiver.resubscribe()).map_while(self.while_some()).filter(Self::filter_response(
+                uid,
+                function_id,
+                seq,
+            ));
+            self.send_packet(&request, seq, response_expected).await?;
+            if response_expected {
+                self.pending_sequence_numbers.insert(seq);
+            }
+            Ok((seq, stream))
         }
 
-        fn while_some(v: Result<Option<PacketData>, BroadcastStreamRecvError>) -> Option<Result<PacketData, BroadcastStreamRecvError>> {
-            match v {
+        /// Ends a stream of received packets when the connection is closed, counting skipped packets.
+        fn while_some(
+            &self,
+        ) -> impl FnMut(Result<Option<PacketData>, BroadcastStreamRecvError>) -> Option<Result<PacketData, BroadcastStreamRecvError>>
+        {
+            let metrics = self.metrics.clone();
+            move |v| match v {
                 Ok(None) => None,
                 Ok(Some(p)) => Some(Ok(p)),
-                Err(e) => Some(Err(e)),
+                Err(BroadcastStreamRecvError::Lagged(count)) => {
+                    metrics.lagged(count);
+                    Some(Err(BroadcastStreamRecvError::Lagged(count)))
+                }
             }
         }
         pub(crate) async fn callback_stream(&mut self, uid: Uid, function_id: u8) -> impl Stream<Item = PacketData> {
+            let metrics = self.metrics.clone();
             BroadcastStream::new(self.receiver.resubscribe())
                 .map_while(move |result| match result {
                     Ok(Some(p)) => {
@@ -279,6 +520,7 @@ pub mod async_io {
                     }
                     Ok(None) => None,
                     Err(BroadcastStreamRecvError::Lagged(count)) => {
+                        metrics.lagged(count);
                         warn!("Slow receiver, skipped {count} Packets");
                         Some(None)
                     }
@@ -298,7 +540,10 @@ pub mod async_io {
             if !payload.is_empty() {
                 result[8..].copy_from_slice(payload);
             }
+            // recorded before writing, otherwise a fast response could be recorded ahead of its request
+            record(&self.recorder, PacketDirection::Sent, &result[0..PacketHeader::SIZE], &result[PacketHeader::SIZE..]);
             self.write_stream.write_all(&result[..]).await?;
+            self.metrics.sent(result.len());
             debug!("Sent: {request:?}");
             Ok(())
         }
@@ -324,7 +569,6 @@ pub mod async_io {
     }
 
     impl PacketData {
-        #[allow(dead_code)]
         pub fn header(&self) -> PacketHeader {
             self.header
         }
@@ -361,12 +605,12 @@ pub mod async_io {
 
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub(crate) struct PacketHeader {
-    uid: Uid,
-    length: u8,
-    function_id: u8,
-    sequence_number: u8,
-    response_expected: bool,
-    error_code: u8,
+    pub(crate) uid: Uid,
+    pub(crate) length: u8,
+    pub(crate) function_id: u8,
+    pub(crate) sequence_number: u8,
+    pub(crate) response_expected: bool,
+    pub(crate) error_code: u8,
 }
 
 impl PacketHeader {
@@ -408,6 +652,7 @@ impl ToBytes for PacketHeader {
 
 /// Type of enumeration of a device.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EnumerationType {
     /// Device is available (enumeration triggered by user: [`Enumerate`](crate::ip_connection::IpConnection::enumerate())).
     /// This enumeration type can occur multiple times for the same device.
@@ -433,12 +678,28 @@ impl From<u8> for EnumerationType {
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Version {
     major: u8,
     minor: u8,
     patch: u8,
 }
 
+impl Version {
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Version { major, minor, patch }
+    }
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+    pub fn patch(&self) -> u8 {
+        self.patch
+    }
+}
+
 impl Display for Version {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
@@ -472,6 +733,7 @@ impl ToBytes for Version {
 /// Devices send `EnumerateResponse`s when they are connected, disconnected or when an enumeration was
 /// triggered by the user using the [`Enumerate`](crate::ip_connection::IpConnection::enumerate) method.
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnumerateResponse {
     /// The UID of the device.
     pub uid: Uid,
@@ -510,7 +772,8 @@ impl FromByteSlice for Result<EnumerateResponse, Base58Error> {
         )?;
         let string =
             str::from_utf8(&bytes[8..16]).expect("Could not convert to string. This is a bug in the rust bindings.").replace('\u{0}', "");
-        let connected_uid = Uid::from_str(&string)?;
+        // disconnected enumerations only carry the uid of the device
+        let connected_uid = if string.is_empty() { Uid::zero() } else { Uid::from_str(&string)? };
         Ok(EnumerateResponse {
             uid,
             connected_uid,
@@ -527,6 +790,14 @@ impl FromByteSlice for Result<EnumerateResponse, Base58Error> {
     }
 }
 
+/// HMAC-SHA1 of server and client nonce keyed with the secret, as sent to authenticate a connection.
+pub(crate) fn authentication_digest(secret: &[u8], server_nonce: &[u8], client_nonce: &[u8]) -> [u8; 20] {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(server_nonce);
+    mac.update(client_nonce);
+    mac.finalize().into_bytes().into()
+}
+
 struct ServerNonce([u8; 4]);
 
 impl FromByteSlice for ServerNonce {
@@ -24,8 +24,36 @@ pub mod converting_callback_receiver;
 pub mod converting_high_level_callback_receiver;
 pub mod converting_receiver;
 pub mod device;
+pub mod dissector;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+#[cfg(feature = "emulator")]
+pub mod emulator;
 pub mod error;
+#[cfg(feature = "exporter")]
+pub mod exporter;
 pub mod ip_connection;
+#[cfg(feature = "logger")]
+pub mod logger;
 pub mod low_level_traits;
+pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(feature = "modbus")]
+pub mod modbus;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod pcap;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+pub mod recording;
+#[cfg(feature = "rest")]
+pub mod rest;
+#[cfg(feature = "server")]
+pub mod server;
+mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
 
 //mod generator;
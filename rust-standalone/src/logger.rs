@@ -0,0 +1,982 @@
+//! Logs values of devices to files and databases.
+//!
+//! The configuration selects devices by uid or type and the values to log of them, either by calling a getter
+//! periodically or by listening to a callback. Every sample is written to all sinks:
+//! ```toml
+//! brick_daemon = "localhost:4223"
+//!
+//! [[sinks]]
+//! type = "csv"
+//! path = "values.csv"
+//!
+//! [[sinks]]
+//! type = "influx"
+//! path = "values.lp"
+//! measurement = "tinkerforge"
+//!
+//! # polls all Temperature Bricklets 2.0 every ten seconds
+//! [[values]]
+//! device = "temperature_v2_bricklet"
+//! getter = "get_temperature"
+//! interval_ms = 10000
+//!
+//! # logs every callback of one device, the callback is configured whenever the device appears
+//! [[values]]
+//! uid = "Hum"
+//! callback = "humidity"
+//! setup = [
+//!     { function = "set_humidity_callback_configuration", arguments = { period = 1000, value_has_to_change = false, option = "x", min = 0, max = 0 } },
+//! ]
+//! ```
+//! The sink types are `csv`, `json_lines`, `influx` for InfluxDB line protocol and `sqlite` with the `sqlite` feature.
+//! Further sinks implement [`Sink`] and are passed to [`DataLogger::with_sinks`]. CSV and SQLite store one row per
+//! value, arrays are split into one value per item with the index appended to the element name.
+//!
+//! Missing values are marked by a [`Gap`]: when the connection to the Brick Daemon is lost or cannot be established,
+//! when a device disconnects, when its setup fails and when a getter starts failing. Logging resumes by itself after
+//! reconnecting, the samples following a gap are complete again.
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{
+    sync::mpsc,
+    task::{AbortHandle, JoinHandle},
+    time::MissedTickBehavior,
+};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    dynamic::{DeviceDefinition, DynamicDevice},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerateResponse, EnumerationType},
+    metadata::{devices, DeviceMetadata},
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Entries buffered for the sinks, logging waits when they fall behind.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Brick Daemon, sinks and logged values of a [`DataLogger`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerConfig {
+    #[serde(default = "default_brick_daemon")]
+    pub brick_daemon: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+    #[serde(default)]
+    pub values: Vec<ValueConfig>,
+}
+
+/// A file or database the entries are written to.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SinkConfig {
+    /// Appends rows `time,uid,device,source,element,value,gap` to a CSV file.
+    Csv { path: PathBuf },
+    /// Appends one JSON object per sample or gap to a file.
+    JsonLines { path: PathBuf },
+    /// Appends InfluxDB line protocol to a file, gaps are points of the measurement suffixed with `_gap`.
+    Influx {
+        path: PathBuf,
+        #[serde(default = "default_measurement")]
+        measurement: String,
+    },
+    /// Inserts into the tables `samples` and `gaps` of an SQLite database, which are created if missing.
+    #[cfg(feature = "sqlite")]
+    Sqlite { path: PathBuf },
+}
+
+/// Values logged of the selected devices.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValueConfig {
+    /// Selects a single device.
+    #[serde(default)]
+    pub uid: Option<Uid>,
+    /// Selects devices by type, like `temperature_v2_bricklet`.
+    #[serde(default)]
+    pub device: Option<String>,
+    /// Getter called every `interval_ms`.
+    #[serde(default)]
+    pub getter: Option<String>,
+    /// Arguments of the getter.
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+    #[serde(default = "default_interval")]
+    pub interval_ms: u64,
+    /// Callback logged whenever it is received, instead of a getter.
+    #[serde(default)]
+    pub callback: Option<String>,
+    /// Functions called whenever the device appears, e.g. to configure the callback.
+    #[serde(default)]
+    pub setup: Vec<SetupCall>,
+}
+
+/// A function called with fixed arguments.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetupCall {
+    pub function: String,
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+}
+
+fn default_brick_daemon() -> String {
+    "localhost:4223".to_string()
+}
+
+fn default_measurement() -> String {
+    "tinkerforge".to_string()
+}
+
+fn default_interval() -> u64 {
+    1000
+}
+
+/// Type of a device as used in the configuration, like `temperature_v2_bricklet`.
+fn device_type(device: &DeviceMetadata) -> String {
+    format!("{}_{}", device.name, device.category).to_lowercase().replace([' ', '-'], "_")
+}
+
+impl LoggerConfig {
+    pub fn from_toml(toml: &str) -> Result<LoggerConfig, TinkerforgeError> {
+        let config: LoggerConfig = toml::from_str(toml).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+    pub fn from_json(json: &str) -> Result<LoggerConfig, TinkerforgeError> {
+        let config: LoggerConfig = serde_json::from_str(json).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+    /// Reads a configuration, files ending in `.json` are parsed as JSON, all others as TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<LoggerConfig, TinkerforgeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            LoggerConfig::from_json(&content)
+        } else {
+            LoggerConfig::from_toml(&content)
+        }
+    }
+
+    /// Checks that every value selects devices and a getter or callback, which have to exist if the type is given.
+    fn validate(&self) -> Result<(), TinkerforgeError> {
+        let invalid = |reason: String| Err(TinkerforgeError::InvalidConfig(reason));
+        if self.values.is_empty() {
+            return invalid("no values to log".to_string());
+        }
+        for (index, value) in self.values.iter().enumerate() {
+            let invalid = |reason: &str| invalid(format!("value {}: {reason}", index + 1));
+            if value.uid.is_none() && value.device.is_none() {
+                return invalid("select devices by uid or device");
+            }
+            match (&value.getter, &value.callback) {
+                (Some(_), None) if value.interval_ms == 0 => return invalid("interval_ms has to be positive"),
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => return invalid("choose either a getter or a callback"),
+            }
+            let Some(device) = &value.device else {
+                continue;
+            };
+            let Some(device) = devices().iter().find(|metadata| device_type(metadata) == *device) else {
+                return invalid(&format!("unknown device {device}"));
+            };
+            if let Some(getter) = &value.getter {
+                if device.function(getter).and_then(|packet| packet.responses().next()).is_none() {
+                    return invalid(&format!("{} has no getter {getter}", device.display_name));
+                }
+            }
+            if let Some(callback) = &value.callback {
+                if device.callback(callback).is_none() {
+                    return invalid(&format!("{} has no callback {callback}", device.display_name));
+                }
+            }
+            if let Some(call) = value.setup.iter().find(|call| device.function(&call.function).is_none()) {
+                return invalid(&format!("{} has no function {}", device.display_name, call.function));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ValueConfig {
+    fn selects(&self, uid: Uid, device_type: &str) -> bool {
+        self.uid.iter().all(|selected| *selected == uid) && self.device.iter().all(|selected| selected == device_type)
+    }
+}
+
+impl SinkConfig {
+    /// Opens the file or database, creating it if it does not exist yet.
+    pub fn open(&self) -> io::Result<Box<dyn Sink>> {
+        let append = |path: &Path| OpenOptions::new().create(true).append(true).open(path);
+        Ok(match self {
+            SinkConfig::Csv { path } => {
+                let file = append(path)?;
+                let empty = file.metadata()?.len() == 0;
+                let mut sink = CsvSink::new(BufWriter::new(file));
+                sink.header_written = !empty;
+                Box::new(sink)
+            }
+            SinkConfig::JsonLines { path } => Box::new(JsonLinesSink::new(BufWriter::new(append(path)?))),
+            SinkConfig::Influx { path, measurement } => Box::new(InfluxSink::new(BufWriter::new(append(path)?), measurement.clone())),
+            #[cfg(feature = "sqlite")]
+            SinkConfig::Sqlite { path } => Box::new(SqliteSink::open(path)?),
+        })
+    }
+}
+
+/// A record written to the sinks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Entry {
+    Sample(Sample),
+    Gap(Gap),
+}
+
+/// Values returned by a getter or received with a callback.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub uid: Uid,
+    /// Type of the device, like `temperature_v2_bricklet`.
+    pub device: String,
+    /// Name of the getter or callback.
+    pub source: String,
+    /// Output elements by name.
+    pub values: Map<String, Value>,
+}
+
+/// Marks that values may be missing from `time` on, until the next sample of the same values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gap {
+    pub time: SystemTime,
+    /// The device without values, `None` if all devices are affected.
+    pub uid: Option<Uid>,
+    /// The getter or callback without values, `None` if all values of the device are affected.
+    pub source: Option<String>,
+    pub reason: String,
+}
+
+impl Entry {
+    fn gap(uid: Option<Uid>, source: Option<&str>, reason: impl Into<String>) -> Entry {
+        Entry::Gap(Gap { time: SystemTime::now(), uid, source: source.map(str::to_string), reason: reason.into() })
+    }
+}
+
+/// Destination of the logged entries, called from a thread that may block.
+pub trait Sink: Send {
+    fn write(&mut self, entry: &Entry) -> io::Result<()>;
+    /// Called after every batch of entries, written entries have to be persisted.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Values of a sample with arrays split into one value per item, named with the index appended.
+fn fields(values: &Map<String, Value>) -> Vec<(Cow<'_, str>, &Value)> {
+    let mut fields = Vec::with_capacity(values.len());
+    for (name, value) in values {
+        match value {
+            Value::Array(items) => fields.extend(items.iter().enumerate().map(|(index, item)| (format!("{name}_{index}").into(), item))),
+            value => fields.push((name.as_str().into(), value)),
+        }
+    }
+    fields
+}
+
+/// Formats a time as RFC 3339 in UTC with milliseconds, like `2024-02-29T12:00:00.000Z`.
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = (since_epoch.as_secs() / 86400, since_epoch.as_secs() % 86400);
+    // civil date of the days since 1970-01-01 in the proleptic Gregorian calendar, eras of 400 years start on March 1st
+    let days = days + 719_468;
+    let (era, day_of_era) = (days / 146_097, days % 146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Uid as text, `0` for the placeholder of devices connected to the Brick Daemon directly.
+fn uid_text(uid: Uid) -> String {
+    if u32::from(uid) == 0 {
+        "0".to_string()
+    } else {
+        uid.to_string()
+    }
+}
+
+/// Text of a single value, strings are not quoted.
+fn value_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::Null => "".into(),
+        Value::String(text) => text.as_str().into(),
+        value => value.to_string().into(),
+    }
+}
+
+/// Writes rows `time,uid,device,source,element,value,gap`, one per value, gaps have the reason in the last column.
+pub struct CsvSink<W> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Writes the header before the first row.
+    pub fn new(writer: W) -> CsvSink<W> {
+        CsvSink { writer, header_written: false }
+    }
+
+    fn row(&mut self, columns: [&str; 7]) -> io::Result<()> {
+        let escaped = columns.map(|column| {
+            if column.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", column.replace('"', "\"\"")).into()
+            } else {
+                Cow::Borrowed(column)
+            }
+        });
+        writeln!(self.writer, "{}", escaped.join(","))
+    }
+}
+
+impl<W: Write + Send> Sink for CsvSink<W> {
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        if !self.header_written {
+            self.row(["time", "uid", "device", "source", "element", "value", "gap"])?;
+            self.header_written = true;
+        }
+        match entry {
+            Entry::Sample(sample) => {
+                let (time, uid) = (format_time(sample.time), uid_text(sample.uid));
+                for (element, value) in fields(&sample.values) {
+                    self.row([&time, &uid, &sample.device, &sample.source, &element, &value_text(value), ""])?;
+                }
+                Ok(())
+            }
+            Entry::Gap(gap) => {
+                let uid = gap.uid.map(uid_text).unwrap_or_default();
+                self.row([&format_time(gap.time), &uid, "", gap.source.as_deref().unwrap_or_default(), "", "", &gap.reason])
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes one JSON object per line, samples with the fields of [`Sample`] and gaps with the reason in `gap`.
+pub struct JsonLinesSink<W> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> JsonLinesSink<W> {
+        JsonLinesSink { writer }
+    }
+}
+
+impl<W: Write + Send> Sink for JsonLinesSink<W> {
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let line = match entry {
+            Entry::Sample(sample) => json!({
+                "time": format_time(sample.time),
+                "uid": uid_text(sample.uid),
+                "device": sample.device,
+                "source": sample.source,
+                "values": sample.values,
+            }),
+            Entry::Gap(gap) => json!({
+                "time": format_time(gap.time),
+                "uid": gap.uid.map(uid_text),
+                "source": gap.source,
+                "gap": gap.reason,
+            }),
+        };
+        writeln!(self.writer, "{line}")
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes InfluxDB line protocol with nanosecond timestamps, tagged with `uid`, `device` and `source`.
+pub struct InfluxSink<W> {
+    writer: W,
+    measurement: String,
+}
+
+impl<W: Write> InfluxSink<W> {
+    pub fn new(writer: W, measurement: String) -> InfluxSink<W> {
+        InfluxSink { writer, measurement }
+    }
+}
+
+/// Escapes measurements, tag keys and values as well as field keys.
+fn influx_escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn influx_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Field value in line protocol, `None` for values that cannot be stored like `null`.
+fn influx_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Number(number) => Some(match number.as_i64() {
+            Some(integer) => format!("{integer}i"),
+            None => number.as_f64().unwrap_or_default().to_string(),
+        }),
+        Value::String(text) => Some(influx_string(text)),
+        value => Some(influx_string(&value.to_string())),
+    }
+}
+
+impl<W: Write + Send> Sink for InfluxSink<W> {
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let nanos = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        match entry {
+            Entry::Sample(sample) => {
+                let fields = fields(&sample.values)
+                    .into_iter()
+                    .filter_map(|(name, value)| Some(format!("{}={}", influx_escape(&name), influx_value(value)?)))
+                    .collect::<Vec<_>>();
+                if fields.is_empty() {
+                    return Ok(());
+                }
+                writeln!(
+                    self.writer,
+                    "{},uid={},device={},source={} {} {}",
+                    influx_escape(&self.measurement),
+                    influx_escape(&uid_text(sample.uid)),
+                    influx_escape(&sample.device),
+                    influx_escape(&sample.source),
+                    fields.join(","),
+                    nanos(sample.time)
+                )
+            }
+            Entry::Gap(gap) => {
+                let mut tags = String::new();
+                if let Some(uid) = gap.uid {
+                    tags.push_str(&format!(",uid={}", influx_escape(&uid_text(uid))));
+                }
+                if let Some(source) = &gap.source {
+                    tags.push_str(&format!(",source={}", influx_escape(source)));
+                }
+                let measurement = influx_escape(&format!("{}_gap", self.measurement));
+                writeln!(self.writer, "{measurement}{tags} reason={} {}", influx_string(&gap.reason), nanos(gap.time))
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteSink;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{io, path::Path};
+
+    use rusqlite::{params, types::Value as SqlValue, Connection};
+    use serde_json::Value;
+
+    use super::{fields, format_time, uid_text, Entry, Sink};
+
+    /// Inserts one row per value into `samples(time, uid, device, source, element, value)` and gaps into
+    /// `gaps(time, uid, source, reason)`, each batch in a transaction.
+    pub struct SqliteSink {
+        connection: Connection,
+        in_transaction: bool,
+    }
+
+    fn sql_error(error: rusqlite::Error) -> io::Error {
+        io::Error::other(error)
+    }
+
+    fn sql_value(value: &Value) -> SqlValue {
+        match value {
+            Value::Null => SqlValue::Null,
+            Value::Bool(value) => SqlValue::Integer((*value).into()),
+            Value::Number(number) => match number.as_i64() {
+                Some(integer) => SqlValue::Integer(integer),
+                None => SqlValue::Real(number.as_f64().unwrap_or_default()),
+            },
+            Value::String(text) => SqlValue::Text(text.clone()),
+            value => SqlValue::Text(value.to_string()),
+        }
+    }
+
+    impl SqliteSink {
+        /// Opens or creates the database and its tables.
+        pub fn open(path: impl AsRef<Path>) -> io::Result<SqliteSink> {
+            SqliteSink::new(Connection::open(path).map_err(sql_error)?)
+        }
+
+        pub fn new(connection: Connection) -> io::Result<SqliteSink> {
+            connection
+                .execute_batch(
+                    "CREATE TABLE IF NOT EXISTS samples (time TEXT NOT NULL, uid TEXT NOT NULL, device TEXT NOT NULL, \
+                     source TEXT NOT NULL, element TEXT NOT NULL, value);
+                     CREATE TABLE IF NOT EXISTS gaps (time TEXT NOT NULL, uid TEXT, source TEXT, reason TEXT NOT NULL);",
+                )
+                .map_err(sql_error)?;
+            Ok(SqliteSink { connection, in_transaction: false })
+        }
+
+        pub fn connection(&self) -> &Connection {
+            &self.connection
+        }
+    }
+
+    impl Sink for SqliteSink {
+        fn write(&mut self, entry: &Entry) -> io::Result<()> {
+            if !self.in_transaction {
+                self.connection.execute_batch("BEGIN").map_err(sql_error)?;
+                self.in_transaction = true;
+            }
+            match entry {
+                Entry::Sample(sample) => {
+                    let mut insert = self
+                        .connection
+                        .prepare_cached("INSERT INTO samples (time, uid, device, source, element, value) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+                        .map_err(sql_error)?;
+                    let (time, uid) = (format_time(sample.time), uid_text(sample.uid));
+                    for (element, value) in fields(&sample.values) {
+                        insert.execute(params![time, uid, sample.device, sample.source, element, sql_value(value)]).map_err(sql_error)?;
+                    }
+                }
+                Entry::Gap(gap) => {
+                    self.connection
+                        .execute(
+                            "INSERT INTO gaps (time, uid, source, reason) VALUES (?1, ?2, ?3, ?4)",
+                            params![format_time(gap.time), gap.uid.map(uid_text), gap.source, gap.reason],
+                        )
+                        .map_err(sql_error)?;
+                }
+            }
+            Ok(())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            if self.in_transaction {
+                self.in_transaction = false;
+                self.connection.execute_batch("COMMIT").map_err(sql_error)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Logs the configured values of a Brick Daemon, stops when dropped.
+pub struct DataLogger {
+    shared: Arc<Shared>,
+    connection_task: AbortHandle,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl DataLogger {
+    /// Opens the sinks of the configuration and starts logging.
+    pub async fn start(config: LoggerConfig) -> Result<DataLogger, TinkerforgeError> {
+        let sinks = config.sinks.iter().map(SinkConfig::open).collect::<io::Result<Vec<_>>>()?;
+        DataLogger::with_sinks(config, sinks).await
+    }
+
+    /// Starts logging to the given sinks instead of the ones of the configuration.
+    pub async fn with_sinks(config: LoggerConfig, sinks: Vec<Box<dyn Sink>>) -> Result<DataLogger, TinkerforgeError> {
+        config.validate()?;
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let writer = tokio::task::spawn_blocking(move || write_entries(receiver, sinks));
+        let shared =
+            Arc::new(Shared { config, entries: sender, devices: Mutex::new(HashMap::new()), definitions: Mutex::new(BTreeMap::new()) });
+        let connection_task = tokio::spawn(maintain_connection(shared.clone())).abort_handle();
+        Ok(DataLogger { shared, connection_task, writer: Some(writer) })
+    }
+
+    /// Stops logging and waits until all entries are written.
+    pub async fn stop(mut self) {
+        let writer = self.writer.take();
+        drop(self);
+        if let Some(writer) = writer {
+            let _ = writer.await;
+        }
+    }
+}
+
+impl Drop for DataLogger {
+    fn drop(&mut self) {
+        self.connection_task.abort();
+        for task in lock(&self.shared.devices).drain().flat_map(|(_, tasks)| tasks) {
+            task.abort();
+        }
+    }
+}
+
+/// Writes batches of entries to all sinks until the logger stops.
+fn write_entries(mut receiver: mpsc::Receiver<Entry>, mut sinks: Vec<Box<dyn Sink>>) {
+    while let Some(entry) = receiver.blocking_recv() {
+        let mut batch = vec![entry];
+        while batch.len() < QUEUE_CAPACITY {
+            match receiver.try_recv() {
+                Ok(entry) => batch.push(entry),
+                Err(_) => break,
+            }
+        }
+        for sink in &mut sinks {
+            if let Err(error) = batch.iter().try_for_each(|entry| sink.write(entry)).and_then(|_| sink.flush()) {
+                warn!("Cannot write {} entries: {error}", batch.len());
+            }
+        }
+    }
+}
+
+struct Shared {
+    config: LoggerConfig,
+    entries: mpsc::Sender<Entry>,
+    /// Tasks logging the values of every device seen in enumerations.
+    devices: Mutex<HashMap<Uid, Vec<AbortHandle>>>,
+    definitions: Mutex<BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Shared {
+    async fn log(&self, entry: Entry) {
+        // the writer only stops after the logger
+        let _ = self.entries.send(entry).await;
+    }
+
+    fn definition(&self, device: &DeviceMetadata) -> Arc<DeviceDefinition> {
+        lock(&self.definitions).entry(device.identifier).or_insert_with(|| Arc::new(DeviceDefinition::from(device))).clone()
+    }
+
+    /// Stops logging a device, returns whether it was logged.
+    fn stop_device(&self, uid: Uid) -> bool {
+        let tasks = lock(&self.devices).remove(&uid);
+        tasks.iter().flatten().for_each(AbortHandle::abort);
+        tasks.is_some()
+    }
+
+    /// Starts logging the selected values of a device, again if it was logged before to repeat its setup.
+    fn start_device(self: &Arc<Self>, connection: &AsyncIpConnection, enumeration: &EnumerateResponse) {
+        let Some(device) = enumeration.device_identifier.parsed().map(|device| device.metadata()) else {
+            return;
+        };
+        let (uid, device_type) = (enumeration.uid, device_type(device));
+        let definition = self.definition(device);
+        let tasks = (0..self.config.values.len())
+            .filter(|index| self.config.values[*index].selects(uid, &device_type))
+            .map(|index| {
+                let dynamic = DynamicDevice::new(uid, connection.clone(), definition.clone());
+                tokio::spawn(log_value(self.clone(), dynamic, device_type.clone(), index)).abort_handle()
+            })
+            .collect::<Vec<_>>();
+        if !tasks.is_empty() {
+            info!("Logging {} values of {uid}", tasks.len());
+            self.stop_device(uid);
+            lock(&self.devices).insert(uid, tasks);
+        }
+    }
+}
+
+/// Connects to the Brick Daemon and logs the values of its devices, reconnecting when the connection fails.
+async fn maintain_connection(shared: Arc<Shared>) {
+    let address = &shared.config.brick_daemon;
+    // a gap is logged once per outage, not on every failed attempt to reconnect
+    let mut in_gap = false;
+    loop {
+        let (connection, enumerations) = match connect(&shared.config).await {
+            Ok(connected) => connected,
+            Err(error) => {
+                warn!("{error}");
+                if !in_gap {
+                    shared.log(Entry::gap(None, None, error)).await;
+                    in_gap = true;
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Connected to {address}");
+        tokio::pin!(enumerations);
+        while let Some(enumeration) = enumerations.next().await {
+            let logged = lock(&shared.devices).contains_key(&enumeration.uid);
+            match enumeration.enumeration_type {
+                EnumerationType::Disconnected => {
+                    if shared.stop_device(enumeration.uid) {
+                        shared.log(Entry::gap(Some(enumeration.uid), None, "device disconnected")).await;
+                    }
+                }
+                EnumerationType::Available if logged => {}
+                _ => shared.start_device(&connection, &enumeration),
+            }
+        }
+        warn!("Lost connection to {address}");
+        for task in lock(&shared.devices).drain().flat_map(|(_, tasks)| tasks) {
+            task.abort();
+        }
+        shared.log(Entry::gap(None, None, format!("lost connection to {address}"))).await;
+        in_gap = true;
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn connect(config: &LoggerConfig) -> Result<(AsyncIpConnection, impl Stream<Item = EnumerateResponse>), String> {
+    let address = &config.brick_daemon;
+    let mut connection = AsyncIpConnection::new(address.clone()).await.map_err(|error| format!("Cannot connect to {address}: {error}"))?;
+    if let Some(secret) = &config.secret {
+        connection.authenticate(secret).await.map_err(|error| format!("Cannot authenticate at {address}: {error}"))?;
+    }
+    let enumerations = connection.enumerate().await.map_err(|error| format!("Cannot enumerate devices of {address}: {error}"))?;
+    Ok((connection, enumerations))
+}
+
+/// Runs the setup of a value of a device and logs it until aborted.
+async fn log_value(shared: Arc<Shared>, mut device: DynamicDevice, device_type: String, index: usize) {
+    let value = &shared.config.values[index];
+    let uid = device.uid();
+    let sample = |source: &str, values| {
+        Entry::Sample(Sample { time: SystemTime::now(), uid, device: device_type.clone(), source: source.to_string(), values })
+    };
+    let source = value.getter.as_deref().or(value.callback.as_deref()).unwrap_or_default();
+    for call in &value.setup {
+        if let Err(error) = device.call(&call.function, Value::Object(call.arguments.clone())).await {
+            warn!("Setup of {uid} failed, {} returned {error}", call.function);
+            shared.log(Entry::gap(Some(uid), Some(source), format!("setup failed: {} returned {error}", call.function))).await;
+            return;
+        }
+    }
+    if let Some(getter) = &value.getter {
+        let mut interval = tokio::time::interval(Duration::from_millis(value.interval_ms));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut failing = false;
+        loop {
+            interval.tick().await;
+            match device.call(getter, Value::Object(value.arguments.clone())).await {
+                Ok(Value::Object(values)) => {
+                    failing = false;
+                    shared.log(sample(getter, values)).await;
+                }
+                Ok(_) => {
+                    warn!("{getter} of {uid} returns no values");
+                    shared.log(Entry::gap(Some(uid), Some(getter), "no values returned")).await;
+                    return;
+                }
+                Err(error) if !failing => {
+                    warn!("Cannot read {getter} of {uid}: {error}");
+                    failing = true;
+                    shared.log(Entry::gap(Some(uid), Some(getter), error.to_string())).await;
+                }
+                Err(_) => {}
+            }
+        }
+    } else if let Some(callback) = &value.callback {
+        match device.callback_stream(callback).await {
+            Ok(stream) => {
+                tokio::pin!(stream);
+                while let Some(values) = stream.next().await {
+                    if let Value::Object(values) = values {
+                        shared.log(sample(callback, values)).await;
+                    }
+                }
+            }
+            Err(error) => {
+                warn!("Cannot receive {callback} of {uid}: {error}");
+                shared.log(Entry::gap(Some(uid), Some(callback), error.to_string())).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde_json::json;
+
+    use crate::logger::{format_time, CsvSink, Entry, Gap, InfluxSink, JsonLinesSink, LoggerConfig, Sample, Sink};
+
+    fn entries() -> [Entry; 2] {
+        let time = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let values = json!({ "voltages": [1200, 3300], "label": "a, \"b\"" }).as_object().unwrap().clone();
+        [
+            Entry::Sample(Sample {
+                time,
+                uid: "ZQH".parse().unwrap(),
+                device: "voltage_bricklet".to_string(),
+                source: "get values".to_string(),
+                values,
+            }),
+            Entry::Gap(Gap { time, uid: None, source: None, reason: "lost connection".to_string() }),
+        ]
+    }
+
+    fn write_all(sink: &mut impl Sink) {
+        entries().iter().for_each(|entry| sink.write(entry).unwrap());
+        sink.flush().unwrap();
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00.000Z");
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)), "2023-11-14T22:13:20.123Z");
+    }
+
+    #[test]
+    fn test_sinks() {
+        let mut csv = Vec::new();
+        write_all(&mut CsvSink::new(&mut csv));
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "time,uid,device,source,element,value,gap\n\
+             2023-11-14T22:13:20.123Z,ZQH,voltage_bricklet,get values,label,\"a, \"\"b\"\"\",\n\
+             2023-11-14T22:13:20.123Z,ZQH,voltage_bricklet,get values,voltages_0,1200,\n\
+             2023-11-14T22:13:20.123Z,ZQH,voltage_bricklet,get values,voltages_1,3300,\n\
+             2023-11-14T22:13:20.123Z,,,,,,lost connection\n"
+        );
+
+        let mut json_lines = Vec::new();
+        write_all(&mut JsonLinesSink::new(&mut json_lines));
+        let lines = String::from_utf8(json_lines).unwrap();
+        let lines = lines.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+        assert_eq!(lines[0]["values"]["voltages"], json!([1200, 3300]));
+        assert_eq!(lines[1], json!({ "time": "2023-11-14T22:13:20.123Z", "uid": null, "source": null, "gap": "lost connection" }));
+
+        let mut influx = Vec::new();
+        write_all(&mut InfluxSink::new(&mut influx, "tinkerforge".to_string()));
+        assert_eq!(
+            String::from_utf8(influx).unwrap(),
+            "tinkerforge,uid=ZQH,device=voltage_bricklet,source=get\\ values label=\"a, \\\"b\\\"\",voltages_0=1200i,voltages_1=3300i \
+             1700000000123000000\n\
+             tinkerforge_gap reason=\"lost connection\" 1700000000123000000\n"
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_sink() {
+        let mut sink = crate::logger::SqliteSink::new(rusqlite::Connection::open_in_memory().unwrap()).unwrap();
+        write_all(&mut sink);
+        let value: i64 = sink
+            .connection()
+            .query_row("SELECT value FROM samples WHERE uid = 'ZQH' AND element = 'voltages_1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(value, 3300);
+        let gap: (Option<String>, String) =
+            sink.connection().query_row("SELECT uid, reason FROM gaps", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        assert_eq!(gap, (None, "lost connection".to_string()));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = |values: &str| LoggerConfig::from_toml(&format!("[[sinks]]\ntype = \"csv\"\npath = \"values.csv\"\n{values}"));
+        assert!(config("[[values]]\ndevice = \"temperature_v2_bricklet\"\ngetter = \"get_temperature\"").is_ok());
+        assert!(config("[[values]]\nuid = \"ZQH\"\ncallback = \"temperature\"\nsetup = [{ function = \"anything\" }]").is_ok());
+        assert!(config("").is_err());
+        assert!(config("[[values]]\ngetter = \"get_temperature\"").is_err());
+        assert!(config("[[values]]\nuid = \"ZQH\"\ngetter = \"get_temperature\"\ncallback = \"temperature\"").is_err());
+        assert!(config("[[values]]\ndevice = \"temperature_v2_bricklet\"\ngetter = \"set_temperature\"").is_err());
+        assert!(config("[[values]]\ndevice = \"temperature_v2_bricklet\"\ncallback = \"humidity\"").is_err());
+        assert!(config("[[values]]\ndevice = \"thermometer\"\ngetter = \"get_temperature\"").is_err());
+        assert!(LoggerConfig::from_toml("[[sinks]]\ntype = \"csv\"\npath = \"values.csv\"\nmeasurement = \"x\"").is_err());
+    }
+}
+
+#[cfg(all(test, feature = "emulator"))]
+mod emulator_test {
+    use std::time::Duration;
+
+    use crate::{
+        emulator::{Emulator, Scenario},
+        logger::{DataLogger, LoggerConfig},
+    };
+
+    #[test]
+    fn test_logging() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let scenario = Scenario::from_toml(
+                r#"
+                [[devices]]
+                uid = "ZQH"
+                device = "TemperatureV2Bricklet"
+                functions = [{ function = "get_temperature", responses = [[2312]] }]
+                callbacks = [{ callback = "temperature", period_ms = 10, values = [[2400]] }]
+                "#,
+            )
+            .unwrap();
+            let emulator = Emulator::bind("127.0.0.1:0", scenario).await.unwrap();
+            let directory = std::env::temp_dir().join(format!("tinkerforge-logger-{}", std::process::id()));
+            std::fs::create_dir_all(&directory).unwrap();
+            let path = directory.join("values.jsonl");
+            let _ = std::fs::remove_file(&path);
+            let config = LoggerConfig::from_toml(&format!(
+                r#"
+                brick_daemon = "{}"
+                sinks = [{{ type = "json_lines", path = "{}" }}]
+
+                [[values]]
+                device = "temperature_v2_bricklet"
+                getter = "get_temperature"
+                interval_ms = 20
+
+                [[values]]
+                uid = "ZQH"
+                callback = "temperature"
+                setup = [{{ function = "set_status_led_config", arguments = {{ config = "off" }} }}]
+                "#,
+                emulator.local_addr(),
+                path.display()
+            ))
+            .unwrap();
+            let logger = DataLogger::start(config).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            emulator.remove_device("ZQH".parse().unwrap()).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            logger.stop().await;
+
+            let lines = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_dir_all(&directory).unwrap();
+            let lines = lines.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()).collect::<Vec<_>>();
+            let sample = |source: &str, temperature: i64| {
+                lines.iter().any(|line| {
+                    line["uid"] == "ZQH"
+                        && line["device"] == "temperature_v2_bricklet"
+                        && line["source"] == source
+                        && line["values"]["temperature"] == temperature
+                })
+            };
+            assert!(sample("get_temperature", 2312), "{lines:?}");
+            assert!(sample("temperature", 2400), "{lines:?}");
+            let gap = lines.iter().position(|line| line["gap"] == "device disconnected").expect("gap missing");
+            assert_eq!(lines[gap]["uid"], "ZQH");
+        });
+    }
+}
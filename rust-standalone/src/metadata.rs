@@ -0,0 +1,209 @@
+//! Static description of all devices known to the bindings, as read from the generator configs.
+//!
+//! This allows tooling to walk the capabilities of a device generically, e.g. to name the function id of a packet:
+//! ```
+//! use tinkerforge_async::{metadata, DeviceIdentifier};
+//!
+//! let device = metadata::device(DeviceIdentifier::TemperatureV2Bricklet);
+//! let packet = device.packet(1).unwrap();
+//! assert_eq!(packet.name, "get_temperature");
+//! ```
+use crate::bindings::DeviceIdentifier;
+
+/// Category of a device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeviceKind {
+    Brick,
+    Bricklet,
+    /// Devices of the TNG family.
+    Tng,
+}
+
+/// Distinguishes requests sent to a device from callbacks sent by the device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum PacketKind {
+    Function,
+    Callback,
+}
+
+/// Whether an element is sent to the device or received from it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// Type of an element on the wire, serialized with the names used in the configs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ElementType {
+    #[cfg_attr(feature = "serde", serde(rename = "uint8"))]
+    U8,
+    #[cfg_attr(feature = "serde", serde(rename = "int8"))]
+    I8,
+    #[cfg_attr(feature = "serde", serde(rename = "uint16"))]
+    U16,
+    #[cfg_attr(feature = "serde", serde(rename = "int16"))]
+    I16,
+    #[cfg_attr(feature = "serde", serde(rename = "uint32"))]
+    U32,
+    #[cfg_attr(feature = "serde", serde(rename = "int32"))]
+    I32,
+    #[cfg_attr(feature = "serde", serde(rename = "uint64"))]
+    U64,
+    #[cfg_attr(feature = "serde", serde(rename = "int64"))]
+    I64,
+    #[cfg_attr(feature = "serde", serde(rename = "bool"))]
+    Bool,
+    #[cfg_attr(feature = "serde", serde(rename = "char"))]
+    Char,
+    #[cfg_attr(feature = "serde", serde(rename = "string"))]
+    String,
+    #[cfg_attr(feature = "serde", serde(rename = "float"))]
+    Float,
+}
+
+impl ElementType {
+    /// Number of bytes `count` values of this type occupy in a packet, bools are packed into bits.
+    pub fn byte_count(self, count: usize) -> usize {
+        match self {
+            ElementType::U8 | ElementType::I8 | ElementType::Char | ElementType::String => count,
+            ElementType::U16 | ElementType::I16 => count * 2,
+            ElementType::U32 | ElementType::I32 | ElementType::Float => count * 4,
+            ElementType::U64 | ElementType::I64 => count * 8,
+            ElementType::Bool => count.div_ceil(8),
+        }
+    }
+}
+
+/// Conversion of a raw value into its SI base unit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SiUnit {
+    /// Symbol of the base unit, e.g. `°C`.
+    pub symbol: &'static str,
+    /// Factor to multiply the raw value with.
+    pub factor: f64,
+}
+
+/// A single element of a packet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ElementMetadata {
+    /// Name of the element in snake case.
+    pub name: &'static str,
+    pub element_type: ElementType,
+    /// Number of values, strings count their characters.
+    pub count: usize,
+    pub direction: Direction,
+    /// Unit name as written in the config, e.g. `Degree Celsius`.
+    pub unit: Option<&'static str>,
+    pub si_unit: Option<SiUnit>,
+    /// Name of the constant group the values are taken from.
+    pub constant_group: Option<&'static str>,
+}
+
+/// A function or callback of a device.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PacketMetadata {
+    pub function_id: u8,
+    /// Name of the packet in snake case, as used for the generated function.
+    pub name: &'static str,
+    pub kind: PacketKind,
+    pub elements: &'static [ElementMetadata],
+}
+
+impl PacketMetadata {
+    /// Elements sent to the device.
+    pub fn requests(&self) -> impl Iterator<Item = &'static ElementMetadata> {
+        self.elements.iter().filter(|element| element.direction == Direction::In)
+    }
+    /// Elements received from the device.
+    pub fn responses(&self) -> impl Iterator<Item = &'static ElementMetadata> {
+        self.elements.iter().filter(|element| element.direction == Direction::Out)
+    }
+}
+
+/// A single named constant.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ConstantMetadata {
+    pub name: &'static str,
+    /// Value as transferred, chars and bools are given by their byte value.
+    pub value: i64,
+}
+
+/// A group of named constants, generated as enum.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ConstantGroupMetadata {
+    /// Name of the group in upper camel case, as used for the generated enum.
+    pub name: &'static str,
+    pub element_type: ElementType,
+    pub constants: &'static [ConstantMetadata],
+}
+
+impl ConstantGroupMetadata {
+    /// Returns the name of the constant with the given value.
+    pub fn constant_name(&self, value: i64) -> Option<&'static str> {
+        self.constants.iter().find(|constant| constant.value == value).map(|constant| constant.name)
+    }
+}
+
+/// Everything known about a device type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DeviceMetadata {
+    pub identifier: DeviceIdentifier,
+    /// Name of the device as used by [`DeviceIdentifier::name`].
+    pub name: &'static str,
+    pub display_name: &'static str,
+    /// `Brick` or `Bricklet`.
+    pub category: &'static str,
+    /// Own packets as well as packets of common features, ordered by function id.
+    pub packets: &'static [PacketMetadata],
+    pub constant_groups: &'static [ConstantGroupMetadata],
+}
+
+impl DeviceMetadata {
+    /// Returns the function or callback with the given function id.
+    pub fn packet(&self, function_id: u8) -> Option<&'static PacketMetadata> {
+        self.packets.iter().find(|packet| packet.function_id == function_id)
+    }
+    /// Returns the function with the given snake case name.
+    pub fn function(&self, name: &str) -> Option<&'static PacketMetadata> {
+        self.packets.iter().find(|packet| packet.kind == PacketKind::Function && packet.name == name)
+    }
+    /// Returns the callback with the given snake case name.
+    pub fn callback(&self, name: &str) -> Option<&'static PacketMetadata> {
+        self.packets.iter().find(|packet| packet.kind == PacketKind::Callback && packet.name == name)
+    }
+    /// Returns the constant group with the given upper camel case name.
+    pub fn constant_group(&self, name: &str) -> Option<&'static ConstantGroupMetadata> {
+        self.constant_groups.iter().find(|group| group.name == name)
+    }
+    /// Measured values of the device: getters without parameters returning a single value with a unit, for which
+    /// the device also offers a callback, e.g. `get_temperature` of the Temperature Bricklet 2.0.
+    pub fn primary_values(&self) -> impl Iterator<Item = (&'static PacketMetadata, &'static ElementMetadata)> + '_ {
+        self.packets.iter().filter_map(|packet| {
+            let value = packet.name.strip_prefix("get_").filter(|value| self.callback(value).is_some())?;
+            let mut elements = packet.elements.iter();
+            match (packet.kind, elements.next(), elements.next()) {
+                (PacketKind::Function, Some(element), None)
+                    if element.direction == Direction::Out && element.count == 1 && element.si_unit.is_some() && element.name == value =>
+                {
+                    Some((packet, element))
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Returns the metadata of all known devices, ordered by device identifier.
+pub fn devices() -> &'static [&'static DeviceMetadata] {
+    crate::bindings::DEVICE_METADATA
+}
+
+/// Returns the metadata of the given device.
+pub fn device(identifier: DeviceIdentifier) -> &'static DeviceMetadata {
+    identifier.metadata()
+}
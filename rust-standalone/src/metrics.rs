@@ -0,0 +1,334 @@
+//! Prometheus metrics of a connection to a Brick Daemon.
+//!
+//! [`ConnectionMetrics`] counts the traffic, timeouts, error codes and callbacks of the connections it is attached to
+//! and measures the request latencies of the devices using them. It is not registered anywhere by itself: register it
+//! on the registry of the application, or on a separate registry per connection, test or Brick Daemon. Constant labels
+//! tell several connections apart in one registry. Without the `prometheus` feature nothing is measured.
+//!
+//! A `ConnectionMetrics` can be attached to the successive connections of a reconnect loop, they are counted as
+//! reconnects.
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus_metrics::ConnectionMetrics;
+pub(crate) use self::prometheus_metrics::Metrics;
+
+#[cfg(feature = "prometheus")]
+mod prometheus_metrics {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    use prometheus::{
+        core::{Collector, Desc},
+        proto::MetricFamily,
+        HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    };
+
+    use crate::{base58::Uid, ip_connection::PacketHeader};
+
+    /// Metrics of one or more connections, cloning shares the values.
+    ///
+    /// ```no_run
+    /// use std::collections::HashMap;
+    ///
+    /// use prometheus::Registry;
+    /// use tinkerforge_async::{ip_connection::async_io::AsyncIpConnection, metrics::ConnectionMetrics};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let registry = Registry::new();
+    /// let metrics = ConnectionMetrics::with_const_labels(HashMap::from([("brick_daemon".to_string(), "cellar".to_string())]))?;
+    /// metrics.register(&registry)?;
+    /// let connection = AsyncIpConnection::with_metrics("cellar:4223", metrics).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[derive(Clone)]
+    pub struct ConnectionMetrics {
+        inner: Arc<InnerMetrics>,
+    }
+
+    struct InnerMetrics {
+        request_duration: HistogramVec,
+        timeouts: IntCounterVec,
+        error_codes: IntCounterVec,
+        callbacks: IntCounterVec,
+        sent_bytes: IntCounter,
+        received_bytes: IntCounter,
+        sent_packets: IntCounter,
+        received_packets: IntCounter,
+        lagged_packets: IntCounter,
+        reconnects: IntCounter,
+        connected: IntGauge,
+        requests_in_flight: IntGauge,
+        connected_before: AtomicBool,
+    }
+
+    impl ConnectionMetrics {
+        pub fn new() -> Self {
+            Self::with_const_labels(HashMap::new()).expect("valid metrics")
+        }
+        /// Adds the same labels to all metrics, e.g. the name of the Brick Daemon.
+        pub fn with_const_labels(labels: HashMap<String, String>) -> Result<Self, prometheus::Error> {
+            let opts = |name: &str, help: &str| Opts::new(name, help).const_labels(labels.clone());
+            let request_duration = HistogramVec::new(
+                HistogramOpts::new("tinkerforge_request", "The Tinkerforge response times latencies in seconds.")
+                    .const_labels(labels.clone()),
+                &["device_type", "function_id", "method"],
+            )?;
+            let device_labels = &["uid", "function_id"];
+            Ok(Self {
+                inner: Arc::new(InnerMetrics {
+                    request_duration,
+                    timeouts: IntCounterVec::new(opts("tinkerforge_timeouts_total", "Requests without response in time."), device_labels)?,
+                    error_codes: IntCounterVec::new(
+                        opts("tinkerforge_error_responses_total", "Responses with an error code."),
+                        &["uid", "function_id", "error_code"],
+                    )?,
+                    callbacks: IntCounterVec::new(opts("tinkerforge_callbacks_total", "Callbacks received."), device_labels)?,
+                    sent_bytes: IntCounter::with_opts(opts("tinkerforge_sent_bytes_total", "Bytes sent."))?,
+                    received_bytes: IntCounter::with_opts(opts("tinkerforge_received_bytes_total", "Bytes received."))?,
+                    sent_packets: IntCounter::with_opts(opts("tinkerforge_sent_packets_total", "Packets sent."))?,
+                    received_packets: IntCounter::with_opts(opts("tinkerforge_received_packets_total", "Packets received."))?,
+                    lagged_packets: IntCounter::with_opts(opts(
+                        "tinkerforge_lagged_packets_total",
+                        "Received packets skipped by slow consumers of the connection.",
+                    ))?,
+                    reconnects: IntCounter::with_opts(opts(
+                        "tinkerforge_reconnects_total",
+                        "Connections established after the first one.",
+                    ))?,
+                    connected: IntGauge::with_opts(opts("tinkerforge_connected", "Whether the connection is open."))?,
+                    requests_in_flight: IntGauge::with_opts(opts(
+                        "tinkerforge_requests_in_flight",
+                        "Requests waiting for their response.",
+                    ))?,
+                    connected_before: AtomicBool::new(false),
+                }),
+            })
+        }
+        /// Registers all metrics on `registry`.
+        pub fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
+            registry.register(Box::new(self.clone()))
+        }
+        fn collectors(&self) -> [&dyn Collector; 12] {
+            let inner = &*self.inner;
+            [
+                &inner.request_duration,
+                &inner.timeouts,
+                &inner.error_codes,
+                &inner.callbacks,
+                &inner.sent_bytes,
+                &inner.received_bytes,
+                &inner.sent_packets,
+                &inner.received_packets,
+                &inner.lagged_packets,
+                &inner.reconnects,
+                &inner.connected,
+                &inner.requests_in_flight,
+            ]
+        }
+    }
+
+    impl Default for ConnectionMetrics {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl std::fmt::Debug for ConnectionMetrics {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("ConnectionMetrics").finish_non_exhaustive()
+        }
+    }
+
+    impl Collector for ConnectionMetrics {
+        fn desc(&self) -> Vec<&Desc> {
+            self.collectors().into_iter().flat_map(Collector::desc).collect()
+        }
+        fn collect(&self) -> Vec<MetricFamily> {
+            self.collectors().into_iter().flat_map(Collector::collect).collect()
+        }
+    }
+
+    /// The metrics of a connection, if any, as seen by the connection and the devices.
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct Metrics(Option<ConnectionMetrics>);
+
+    /// Counts a request as in flight until dropped.
+    pub(crate) struct InFlight(Option<IntGauge>);
+
+    impl Drop for InFlight {
+        fn drop(&mut self) {
+            if let Some(gauge) = &self.0 {
+                gauge.dec();
+            }
+        }
+    }
+
+    impl Metrics {
+        pub(crate) fn new(metrics: ConnectionMetrics) -> Self {
+            Self(Some(metrics))
+        }
+        pub(crate) fn connected(&self) {
+            if let Some(metrics) = &self.0 {
+                if metrics.inner.connected_before.swap(true, Ordering::Relaxed) {
+                    metrics.inner.reconnects.inc();
+                }
+                metrics.inner.connected.set(1);
+            }
+        }
+        pub(crate) fn disconnected(&self) {
+            if let Some(metrics) = &self.0 {
+                metrics.inner.connected.set(0);
+            }
+        }
+        pub(crate) fn sent(&self, bytes: usize) {
+            if let Some(metrics) = &self.0 {
+                metrics.inner.sent_packets.inc();
+                metrics.inner.sent_bytes.inc_by(bytes as u64);
+            }
+        }
+        pub(crate) fn received(&self, header: &PacketHeader) {
+            if let Some(metrics) = &self.0 {
+                let inner = &metrics.inner;
+                inner.received_packets.inc();
+                inner.received_bytes.inc_by(header.length as u64);
+                let uid = uid_label(header.uid);
+                let function_id = header.function_id.to_string();
+                if header.sequence_number == 0 {
+                    inner.callbacks.with_label_values(&[&uid, &function_id]).inc();
+                }
+                if header.error_code != 0 {
+                    inner.error_codes.with_label_values(&[&uid, &function_id, &header.error_code.to_string()]).inc();
+                }
+            }
+        }
+        pub(crate) fn lagged(&self, count: u64) {
+            if let Some(metrics) = &self.0 {
+                metrics.inner.lagged_packets.inc_by(count);
+            }
+        }
+        pub(crate) fn timeout(&self, uid: Uid, function_id: u8) {
+            if let Some(metrics) = &self.0 {
+                metrics.inner.timeouts.with_label_values(&[&uid_label(uid), &function_id.to_string()]).inc();
+            }
+        }
+        pub(crate) fn in_flight(&self) -> InFlight {
+            InFlight(self.0.as_ref().map(|metrics| {
+                let gauge = metrics.inner.requests_in_flight.clone();
+                gauge.inc();
+                gauge
+            }))
+        }
+        pub(crate) fn request_timer(&self, device_type: &str, function_id: u8, method: &str) -> Option<HistogramTimer> {
+            self.0.as_ref().map(|metrics| {
+                metrics.inner.request_duration.with_label_values(&[device_type, &function_id.to_string(), method]).start_timer()
+            })
+        }
+    }
+
+    fn uid_label(uid: Uid) -> String {
+        if uid == Uid::zero() {
+            "0".to_string()
+        } else {
+            uid.to_string()
+        }
+    }
+}
+
+#[cfg(not(feature = "prometheus"))]
+mod prometheus_metrics {
+    use crate::{base58::Uid, ip_connection::PacketHeader};
+
+    /// Without the `prometheus` feature nothing is measured.
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct Metrics(());
+
+    pub(crate) struct InFlight;
+
+    impl Metrics {
+        pub(crate) fn connected(&self) {}
+        pub(crate) fn disconnected(&self) {}
+        pub(crate) fn sent(&self, _bytes: usize) {}
+        pub(crate) fn received(&self, _header: &PacketHeader) {}
+        pub(crate) fn lagged(&self, _count: u64) {}
+        pub(crate) fn timeout(&self, _uid: Uid, _function_id: u8) {}
+        pub(crate) fn in_flight(&self) -> InFlight {
+            InFlight
+        }
+    }
+}
+
+#[cfg(all(test, feature = "prometheus"))]
+mod test {
+    use std::time::Duration;
+
+    use prometheus::{Encoder, Registry, TextEncoder};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{ConnectionMetrics, Metrics};
+    use crate::{base58::Uid, error::TinkerforgeError, ip_connection::async_io::AsyncIpConnection};
+
+    fn text(registry: &Registry) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn test_connection_metrics() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let registry = Registry::new();
+            let metrics = ConnectionMetrics::new();
+            metrics.register(&registry).unwrap();
+            // a second set of metrics does not collide in its own registry
+            ConnectionMetrics::new().register(&Registry::new()).unwrap();
+
+            let (client, mut server) = tokio::io::duplex(1024);
+            let mut connection = AsyncIpConnection::from_stream(client, "test".to_string(), Metrics::new(metrics.clone()));
+            let uid: Uid = 2.into();
+            let responder = tokio::spawn(async move {
+                let mut request = [0; 8];
+                server.read_exact(&mut request).await.unwrap();
+                // answers with "function not supported" and sends a callback of function 5
+                let mut response = request;
+                response[7] = 2 << 6;
+                server.write_all(&response).await.unwrap();
+                let mut callback = request;
+                callback[5] = 5;
+                callback[6] = 0;
+                server.write_all(&callback).await.unwrap();
+                // the second request is never answered
+                server.read_exact(&mut request).await.unwrap();
+                server
+            });
+
+            let response = connection.get(uid, 1, &[], Duration::from_secs(1)).await.unwrap();
+            assert_eq!(2, response.header().error_code);
+            let result = connection.get(uid, 1, &[], Duration::from_millis(50)).await;
+            assert!(matches!(result, Err(TinkerforgeError::NoResponseReceived)));
+            drop(responder.await.unwrap());
+            tokio::time::sleep(Duration::from_millis(50)).await;
+
+            let text = text(&registry);
+            for line in [
+                "tinkerforge_sent_packets_total 2",
+                "tinkerforge_sent_bytes_total 16",
+                "tinkerforge_received_packets_total 2",
+                "tinkerforge_received_bytes_total 16",
+                "tinkerforge_error_responses_total{error_code=\"2\",function_id=\"1\",uid=\"3\"} 1",
+                "tinkerforge_callbacks_total{function_id=\"5\",uid=\"3\"} 1",
+                "tinkerforge_timeouts_total{function_id=\"1\",uid=\"3\"} 1",
+                "tinkerforge_requests_in_flight 0",
+                "tinkerforge_connected 0",
+                "tinkerforge_reconnects_total 0",
+            ] {
+                assert!(text.lines().any(|l| l == line), "{line} missing in\n{text}");
+            }
+        });
+    }
+}
@@ -0,0 +1,207 @@
+//! Scriptable stand-ins for devices, to unit test application logic without hardware.
+//!
+//! Every device comes with a trait of all its functions, e.g. `Lcd128X64Api`, which is implemented by the real
+//! device as well as by a mock like `Lcd128X64Mock`. Application code written against the trait can be driven by the
+//! mock in tests:
+//! ```
+//! use tinkerforge_async::{base58::Uid, temperature_v_2::{TemperatureV2Api, TemperatureV2Mock}};
+//! use tokio_stream::StreamExt;
+//!
+//! async fn read<D: TemperatureV2Api>(device: &mut D) -> i16 {
+//!     device.get_temperature().await.unwrap()
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut device = TemperatureV2Mock::new(Uid::from(42));
+//! let mock = device.mock().clone();
+//! mock.returns("get_temperature", 2312i16);
+//! assert_eq!(read(&mut device).await, 2312);
+//! assert_eq!(mock.call_count("get_temperature"), 1);
+//!
+//! let mut stream = device.temperature_stream().await;
+//! mock.inject_callback("temperature_stream", 2400i16);
+//! assert_eq!(stream.next().await, Some(2400));
+//! # });
+//! ```
+//! Functions are scripted by their name, so derived functions like `get_temperature_si` are scripted on their own.
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use futures_core::Stream;
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+
+use crate::{
+    base58::Uid,
+    device::{GetResponseExpectedError, ResponseExpectedFlag, SetResponseExpectedError},
+    error::TinkerforgeError,
+    ip_connection::Version,
+};
+
+const CALLBACK_CAPACITY: usize = 64;
+
+/// A call received by a mocked device.
+pub struct MockCall {
+    /// Name of the called function.
+    pub function: &'static str,
+    request: Box<dyn Any + Send>,
+}
+
+impl MockCall {
+    /// The request parameter of the call, `()` for functions without parameters. String parameters are recorded as
+    /// `String`.
+    pub fn request<T: 'static>(&self) -> Option<&T> {
+        self.request.downcast_ref()
+    }
+}
+
+impl Debug for MockCall {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockCall").field("function", &self.function).finish_non_exhaustive()
+    }
+}
+
+enum Scripted {
+    Value(Box<dyn Any + Send>),
+    Error(TinkerforgeError),
+}
+
+struct MockState {
+    firmware_version: Option<Version>,
+    response_expected: [ResponseExpectedFlag; 256],
+    returns: HashMap<&'static str, VecDeque<Scripted>>,
+    calls: Vec<MockCall>,
+    callbacks: HashMap<&'static str, Box<dyn Any + Send>>,
+}
+
+/// State shared by all clones of a mocked device.
+///
+/// Return values are scripted per function and consumed in order. Functions without a scripted value fail with
+/// [`TinkerforgeError::InvalidCall`], except functions returning nothing, which succeed.
+#[derive(Clone)]
+pub struct MockDevice {
+    pub(crate) api_version: [u8; 3],
+    uid: Uid,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Debug for MockDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockDevice").field("api_version", &self.api_version).field("uid", &self.uid).finish_non_exhaustive()
+    }
+}
+
+impl MockDevice {
+    pub(crate) fn new(api_version: [u8; 3], uid: Uid, response_expected: &[(u8, ResponseExpectedFlag)]) -> MockDevice {
+        let mut flags = [ResponseExpectedFlag::InvalidFunctionId; 256];
+        for (function_id, flag) in response_expected {
+            flags[*function_id as usize] = *flag;
+        }
+        MockDevice {
+            api_version,
+            uid,
+            state: Arc::new(Mutex::new(MockState {
+                firmware_version: None,
+                response_expected: flags,
+                returns: Default::default(),
+                calls: Default::default(),
+                callbacks: Default::default(),
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Queues a successful return value for the next call of `function`.
+    pub fn returns<R: Send + 'static>(&self, function: &'static str, value: R) -> &Self {
+        self.state().returns.entry(function).or_default().push_back(Scripted::Value(Box::new(value)));
+        self
+    }
+
+    /// Queues an error for the next call of `function`.
+    pub fn fails(&self, function: &'static str, error: TinkerforgeError) -> &Self {
+        self.state().returns.entry(function).or_default().push_back(Scripted::Error(error));
+        self
+    }
+
+    /// Sets the firmware version reported by the device.
+    pub fn set_firmware_version(&self, firmware_version: Option<Version>) {
+        self.state().firmware_version = firmware_version;
+    }
+
+    /// Removes and returns all calls recorded so far.
+    pub fn take_calls(&self) -> Vec<MockCall> {
+        std::mem::take(&mut self.state().calls)
+    }
+
+    /// Number of recorded calls of `function`.
+    pub fn call_count(&self, function: &str) -> usize {
+        self.state().calls.iter().filter(|call| call.function == function).count()
+    }
+
+    /// Request parameters of all recorded calls of `function`, in call order.
+    pub fn requests<T: Clone + 'static>(&self, function: &str) -> Vec<T> {
+        self.state().calls.iter().filter(|call| call.function == function).filter_map(|call| call.request::<T>().cloned()).collect()
+    }
+
+    /// Sends a value to all streams of the callback `stream`, named like the stream function, e.g.
+    /// `temperature_stream`. Returns the number of streams the value was delivered to, streams opened afterwards
+    /// don't see it.
+    pub fn inject_callback<T: Clone + Send + 'static>(&self, stream: &'static str, value: T) -> usize {
+        self.callback_sender::<T>(stream).send(value).unwrap_or(0)
+    }
+
+    fn callback_sender<T: Clone + Send + 'static>(&self, stream: &'static str) -> broadcast::Sender<T> {
+        let mut state = self.state();
+        let sender = state.callbacks.entry(stream).or_insert_with(|| Box::new(broadcast::channel::<T>(CALLBACK_CAPACITY).0));
+        match sender.downcast_ref::<broadcast::Sender<T>>() {
+            Some(sender) => sender.clone(),
+            None => panic!("Callback {stream} does not yield {}", type_name::<T>()),
+        }
+    }
+
+    pub(crate) fn call<R: 'static>(&self, function: &'static str, request: impl Send + 'static) -> Result<R, TinkerforgeError> {
+        let mut state = self.state();
+        state.calls.push(MockCall { function, request: Box::new(request) });
+        let value = match state.returns.get_mut(function).and_then(VecDeque::pop_front) {
+            Some(Scripted::Value(value)) => value,
+            Some(Scripted::Error(error)) => return Err(error),
+            None if TypeId::of::<R>() == TypeId::of::<()>() => Box::new(()),
+            None => return Err(TinkerforgeError::InvalidCall(format!("No return value scripted for {function}"))),
+        };
+        value
+            .downcast()
+            .map(|value| *value)
+            .map_err(|_| TinkerforgeError::InvalidCall(format!("Scripted value for {function} is not a {}", type_name::<R>())))
+    }
+
+    pub(crate) fn callback_stream<T: Clone + Send + 'static>(&self, stream: &'static str) -> impl Stream<Item = T> {
+        BroadcastStream::new(self.callback_sender(stream).subscribe()).filter_map(Result::ok)
+    }
+
+    pub(crate) fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    pub(crate) fn firmware_version(&self) -> Option<Version> {
+        self.state().firmware_version
+    }
+
+    pub(crate) fn get_response_expected(&self, function_id: u8) -> Result<bool, GetResponseExpectedError> {
+        crate::device::get_response_expected(&self.state().response_expected, function_id)
+    }
+
+    pub(crate) fn set_response_expected(&mut self, function_id: u8, response_expected: bool) -> Result<(), SetResponseExpectedError> {
+        crate::device::set_response_expected(&mut self.state().response_expected, function_id, response_expected)
+    }
+
+    pub(crate) fn set_response_expected_all(&mut self, response_expected: bool) {
+        crate::device::set_response_expected_all(&mut self.state().response_expected, response_expected)
+    }
+}
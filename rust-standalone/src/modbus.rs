@@ -0,0 +1,665 @@
+//! Exposes devices to Modbus TCP clients like PLCs and SCADA systems.
+//!
+//! Every register, coil and discrete input is mapped to a getter of a device, writable ones also to a setter. Reading
+//! calls the getter, writing calls the setter with the written value. The mapping is read from TOML or JSON:
+//! ```toml
+//! brick_daemon = "localhost:4223"
+//!
+//! # read with function 4, 32 bit types occupy two registers with the high word first
+//! [[input_registers]]
+//! address = 0
+//! uid = "ZQH"
+//! getter = "get_temperature"
+//! type = "i16"
+//!
+//! # read with function 3, written with functions 6 and 16
+//! [[holding_registers]]
+//! address = 0
+//! uid = "ZQH"
+//! getter = "get_temperature_callback_configuration"
+//! element = "period"
+//! setter = "set_temperature_callback_configuration"
+//! arguments = { value_has_to_change = false, option = "x", min = 0, max = 0 }
+//! type = "u32"
+//!
+//! # read with function 1, written with functions 5 and 15
+//! [[coils]]
+//! address = 0
+//! uid = "Hdw"
+//! getter = "get_value"
+//! index = 0
+//! setter = "set_selected_value"
+//! arguments = { channel = 0 }
+//!
+//! # read with function 2
+//! [[discrete_inputs]]
+//! address = 0
+//! uid = "Hdx"
+//! getter = "get_value"
+//! index = 0
+//! ```
+//! `element` selects the value of getters with several output elements, `index` one value of an array. The setter
+//! receives the value as its only argument besides the fixed `arguments`, or as `argument` if given. If that argument
+//! is an array, the other values are read with the getter first. Constants are exchanged by their value.
+//!
+//! Unmapped addresses are answered with an illegal data address exception, unreachable devices with a gateway
+//! exception.
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+    task::AbortHandle,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    byte_converter::FromByteSlice,
+    device::DEFAULT_TIMEOUT,
+    dynamic::{DeviceDefinition, DynamicDevice},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerationType},
+    metadata::{DeviceMetadata, ElementType},
+};
+
+const FUNCTION_GET_IDENTITY: u8 = 255;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+/// Size of the MBAP header preceding every request and response.
+const HEADER_SIZE: usize = 7;
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+/// Brick Daemon and register mapping of a [`ModbusGateway`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusConfig {
+    #[serde(default = "default_brick_daemon")]
+    pub brick_daemon: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub coils: Vec<RegisterMapping>,
+    #[serde(default)]
+    pub discrete_inputs: Vec<RegisterMapping>,
+    #[serde(default)]
+    pub holding_registers: Vec<RegisterMapping>,
+    #[serde(default)]
+    pub input_registers: Vec<RegisterMapping>,
+}
+
+/// A register, coil or discrete input mapped to functions of a device.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterMapping {
+    pub address: u16,
+    pub uid: Uid,
+    pub getter: String,
+    /// Output element of the getter, may be omitted if there is only one.
+    #[serde(default)]
+    pub element: Option<String>,
+    /// Index of the value if the element is an array.
+    #[serde(default)]
+    pub index: Option<usize>,
+    /// Setter of coils and holding registers.
+    #[serde(default)]
+    pub setter: Option<String>,
+    /// Argument of the setter receiving the value, may be omitted if it is the only one not in `arguments`.
+    #[serde(default)]
+    pub argument: Option<String>,
+    /// Further arguments of the setter.
+    #[serde(default)]
+    pub arguments: Map<String, Value>,
+    /// Encoding of registers, ignored for coils and discrete inputs.
+    #[serde(default, rename = "type")]
+    pub register_type: RegisterType,
+}
+
+/// Encoding of a value in registers.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegisterType {
+    #[default]
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+}
+
+impl RegisterType {
+    fn width(self) -> u16 {
+        match self {
+            RegisterType::I16 | RegisterType::U16 => 1,
+            RegisterType::I32 | RegisterType::U32 | RegisterType::F32 => 2,
+        }
+    }
+
+    /// Encodes a value, integers have to be in range of the type.
+    fn encode(self, value: f64) -> Option<Vec<u16>> {
+        let integer = value.round();
+        let in_range = |min: f64, max: f64| (min..=max).contains(&integer).then_some(integer);
+        let words = |value: u32| vec![(value >> 16) as u16, value as u16];
+        match self {
+            RegisterType::I16 => in_range(i16::MIN.into(), i16::MAX.into()).map(|value| vec![value as i16 as u16]),
+            RegisterType::U16 => in_range(0.0, u16::MAX.into()).map(|value| vec![value as u16]),
+            RegisterType::I32 => in_range(i32::MIN.into(), i32::MAX.into()).map(|value| words(value as i32 as u32)),
+            RegisterType::U32 => in_range(0.0, u32::MAX.into()).map(|value| words(value as u32)),
+            RegisterType::F32 => Some(words((value as f32).to_bits())),
+        }
+    }
+
+    fn decode(self, words: &[u16]) -> f64 {
+        let double_word = || (u32::from(words[0]) << 16) | u32::from(words[1]);
+        match self {
+            RegisterType::I16 => (words[0] as i16).into(),
+            RegisterType::U16 => words[0].into(),
+            RegisterType::I32 => (double_word() as i32).into(),
+            RegisterType::U32 => double_word().into(),
+            RegisterType::F32 => f32::from_bits(double_word()).into(),
+        }
+    }
+}
+
+fn default_brick_daemon() -> String {
+    "localhost:4223".to_string()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Table {
+    Coils,
+    DiscreteInputs,
+    HoldingRegisters,
+    InputRegisters,
+}
+
+impl Table {
+    fn is_writable(self) -> bool {
+        matches!(self, Table::Coils | Table::HoldingRegisters)
+    }
+    fn holds_bits(self) -> bool {
+        matches!(self, Table::Coils | Table::DiscreteInputs)
+    }
+    fn width(self, mapping: &RegisterMapping) -> u16 {
+        if self.holds_bits() {
+            1
+        } else {
+            mapping.register_type.width()
+        }
+    }
+}
+
+/// Mappings of the four tables by their first address.
+type RegisterMap = [BTreeMap<u16, RegisterMapping>; 4];
+
+impl ModbusConfig {
+    pub fn from_toml(toml: &str) -> Result<ModbusConfig, TinkerforgeError> {
+        toml::from_str(toml).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    pub fn from_json(json: &str) -> Result<ModbusConfig, TinkerforgeError> {
+        serde_json::from_str(json).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    /// Reads a configuration, files ending in `.json` are parsed as JSON, all others as TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<ModbusConfig, TinkerforgeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            ModbusConfig::from_json(&content)
+        } else {
+            ModbusConfig::from_toml(&content)
+        }
+    }
+
+    /// Checks that writable tables have setters and that no mappings overlap.
+    fn register_map(&self) -> Result<RegisterMap, TinkerforgeError> {
+        let mut map = RegisterMap::default();
+        let tables = [
+            (Table::Coils, &self.coils, "coil"),
+            (Table::DiscreteInputs, &self.discrete_inputs, "discrete input"),
+            (Table::HoldingRegisters, &self.holding_registers, "holding register"),
+            (Table::InputRegisters, &self.input_registers, "input register"),
+        ];
+        for (table, mappings, name) in tables {
+            let registers = &mut map[table as usize];
+            for mapping in mappings {
+                let invalid = |reason: &str| Err(TinkerforgeError::InvalidConfig(format!("{name} {}: {reason}", mapping.address)));
+                if table.is_writable() != mapping.setter.is_some() {
+                    return invalid(if table.is_writable() { "setter missing" } else { "cannot be written" });
+                }
+                let end = u32::from(mapping.address) + u32::from(table.width(mapping));
+                if end > 0x10000 {
+                    return invalid("exceeds the address range");
+                }
+                let previous = registers.range(..mapping.address).next_back().map(|(_, previous)| previous);
+                let next = registers.range(mapping.address..).next().map(|(address, _)| u32::from(*address));
+                let overlaps_previous = previous
+                    .is_some_and(|previous| u32::from(previous.address) + u32::from(table.width(previous)) > mapping.address.into());
+                if overlaps_previous || next.is_some_and(|next| next < end) {
+                    return invalid("overlaps another mapping");
+                }
+                registers.insert(mapping.address, mapping.clone());
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Exception codes of the Modbus application protocol.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Exception {
+    IllegalFunction = 1,
+    IllegalDataAddress = 2,
+    IllegalDataValue = 3,
+    ServerDeviceFailure = 4,
+    GatewayTargetDeviceFailedToRespond = 11,
+}
+
+impl From<TinkerforgeError> for Exception {
+    fn from(error: TinkerforgeError) -> Self {
+        match error {
+            TinkerforgeError::InvalidCall(_) => Exception::IllegalDataValue,
+            TinkerforgeError::NoResponseReceived => Exception::GatewayTargetDeviceFailedToRespond,
+            _ => Exception::ServerDeviceFailure,
+        }
+    }
+}
+
+/// Reports a mapping that does not fit the device, e.g. a misspelled element.
+fn mapping_error(mapping: &RegisterMapping, reason: impl std::fmt::Display) -> Exception {
+    warn!("Invalid mapping of {} at {}: {reason}", mapping.uid, mapping.address);
+    Exception::ServerDeviceFailure
+}
+
+/// A Modbus TCP server serving the mapped devices of a Brick Daemon, stops when dropped.
+pub struct ModbusGateway {
+    local_addr: SocketAddr,
+    tasks: Vec<AbortHandle>,
+}
+
+impl ModbusGateway {
+    /// Starts accepting clients and connects to the Brick Daemon, use port 0 to let the system choose a free port.
+    pub async fn bind(address: impl ToSocketAddrs, config: ModbusConfig) -> Result<ModbusGateway, TinkerforgeError> {
+        let registers = config.register_map()?;
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            registers,
+            connection: watch::channel(None).0,
+            devices: Mutex::new(HashMap::new()),
+            definitions: Mutex::new(BTreeMap::new()),
+        });
+        let connection_task = tokio::spawn(maintain_connection(shared.clone())).abort_handle();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        info!("Client {peer} connected");
+                        tokio::spawn(serve_client(shared.clone(), stream, peer));
+                    }
+                    Err(error) => warn!("Cannot accept connection: {error}"),
+                }
+            }
+        })
+        .abort_handle();
+        Ok(ModbusGateway { local_addr, tasks: vec![connection_task, accept_task] })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for ModbusGateway {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct Shared {
+    config: ModbusConfig,
+    registers: RegisterMap,
+    connection: watch::Sender<Option<AsyncIpConnection>>,
+    /// Devices of the uids seen in enumerations and identities.
+    devices: Mutex<HashMap<Uid, DeviceIdentifier>>,
+    definitions: Mutex<BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Shared {
+    /// Returns the mapping covering an address.
+    fn mapping(&self, table: Table, address: u16) -> Option<&RegisterMapping> {
+        let (_, mapping) = self.registers[table as usize].range(..=address).next_back()?;
+        (u32::from(mapping.address) + u32::from(table.width(mapping)) > address.into()).then_some(mapping)
+    }
+
+    async fn device(&self, uid: Uid, connection: &AsyncIpConnection) -> Result<&'static DeviceMetadata, Exception> {
+        if let Some(device) = lock(&self.devices).get(&uid) {
+            return Ok(device.metadata());
+        }
+        let response = connection.clone().get(uid, FUNCTION_GET_IDENTITY, &[], DEFAULT_TIMEOUT).await?;
+        let device = response
+            .body()
+            .get(23..25)
+            .and_then(|bytes| u16::from_le_byte_slice(bytes).try_into().ok())
+            .ok_or(Exception::ServerDeviceFailure)?;
+        lock(&self.devices).insert(uid, device);
+        Ok(device.metadata())
+    }
+
+    /// Definition of a device with its constants exchanged by value.
+    fn definition(&self, device: &DeviceMetadata) -> Arc<DeviceDefinition> {
+        let mut definitions = lock(&self.definitions);
+        definitions
+            .entry(device.identifier)
+            .or_insert_with(|| {
+                let mut definition = DeviceDefinition::from(device);
+                for element in definition.packets.iter_mut().flat_map(|packet| packet.elements.iter_mut()) {
+                    element.extra.clear();
+                }
+                Arc::new(definition)
+            })
+            .clone()
+    }
+
+    async fn call(&self, uid: Uid, function: &str, arguments: Map<String, Value>) -> Result<Value, Exception> {
+        let connection = self.connection.borrow().clone().ok_or(Exception::GatewayTargetDeviceFailedToRespond)?;
+        let device = self.device(uid, &connection).await?;
+        let mut dynamic = DynamicDevice::new(uid, connection, self.definition(device));
+        dynamic.call(function, Value::Object(arguments)).await.map_err(|error| {
+            debug!("Call of {function} of {uid} failed: {error}");
+            error.into()
+        })
+    }
+
+    /// Calls the getter of a mapping and returns the mapped element, not yet indexed.
+    async fn element(&self, mapping: &RegisterMapping) -> Result<Value, Exception> {
+        let response = self.call(mapping.uid, &mapping.getter, Map::new()).await?;
+        let Value::Object(mut elements) = response else {
+            return Err(mapping_error(mapping, "getter has no response"));
+        };
+        match &mapping.element {
+            Some(element) => elements.remove(element).ok_or_else(|| mapping_error(mapping, format!("getter has no element {element}"))),
+            None if elements.len() == 1 => Ok(elements.into_iter().next().map(|(_, value)| value).unwrap_or_default()),
+            None => Err(mapping_error(mapping, "getter has several elements, choose one")),
+        }
+    }
+
+    /// Reads the values of `count` addresses, words for registers and 0 or 1 for bits.
+    async fn read(&self, table: Table, start: u16, count: u16) -> Result<Vec<u16>, Exception> {
+        let end = u32::from(start) + u32::from(count);
+        let mut values = Vec::with_capacity(count.into());
+        let mut address = u32::from(start);
+        while address < end {
+            let mapping = self.mapping(table, address as u16).ok_or(Exception::IllegalDataAddress)?;
+            let element = self.element(mapping).await?;
+            let value = match (&element, mapping.index) {
+                (Value::Array(values), Some(index)) => values.get(index).ok_or_else(|| mapping_error(mapping, "index out of range"))?,
+                (Value::Array(_), None) => return Err(mapping_error(mapping, "element is an array, choose an index")),
+                (value, _) => value,
+            };
+            let value = match value {
+                Value::Bool(value) => f64::from(u8::from(*value)),
+                value => value.as_f64().ok_or_else(|| mapping_error(mapping, format!("{value} is no number")))?,
+            };
+            let words = if table.holds_bits() {
+                vec![u16::from(value != 0.0)]
+            } else {
+                mapping.register_type.encode(value).ok_or_else(|| mapping_error(mapping, format!("{value} exceeds the register type")))?
+            };
+            let first = u32::from(mapping.address);
+            values.extend(words.iter().skip((address - first) as usize).take((end - address) as usize));
+            address = first + u32::from(table.width(mapping));
+        }
+        Ok(values)
+    }
+
+    /// Writes consecutive addresses, which have to cover whole mappings.
+    async fn write(&self, table: Table, start: u16, values: &[u16]) -> Result<(), Exception> {
+        let end = u32::from(start) + values.len() as u32;
+        let mut address = u32::from(start);
+        while address < end {
+            let mapping = self.mapping(table, address as u16).ok_or(Exception::IllegalDataAddress)?;
+            let width = u32::from(table.width(mapping));
+            if u32::from(mapping.address) != address || address + width > end {
+                return Err(Exception::IllegalDataAddress);
+            }
+            let words = &values[(address - u32::from(start)) as usize..(address - u32::from(start) + width) as usize];
+            let value = if table.holds_bits() { f64::from(words[0]) } else { mapping.register_type.decode(words) };
+            self.set(mapping, value).await?;
+            address += width;
+        }
+        Ok(())
+    }
+
+    async fn set(&self, mapping: &RegisterMapping, value: f64) -> Result<(), Exception> {
+        let setter = mapping.setter.as_deref().unwrap_or_default();
+        let connection = self.connection.borrow().clone().ok_or(Exception::GatewayTargetDeviceFailedToRespond)?;
+        let device = self.device(mapping.uid, &connection).await?;
+        let packet = device.function(setter).ok_or_else(|| mapping_error(mapping, format!("unknown setter {setter}")))?;
+        let mut candidates = packet.requests().filter(|element| match &mapping.argument {
+            Some(argument) => element.name == argument,
+            None => !mapping.arguments.contains_key(element.name),
+        });
+        let (Some(argument), None) = (candidates.next(), candidates.next()) else {
+            return Err(mapping_error(mapping, "cannot tell the argument of the setter, choose one"));
+        };
+        let mut value = match argument.element_type {
+            ElementType::Bool => Value::Bool(value != 0.0),
+            ElementType::Float => Value::from(value),
+            _ => Value::from(value.round() as i64),
+        };
+        if argument.count > 1 {
+            let index = mapping.index.ok_or_else(|| mapping_error(mapping, "argument is an array, choose an index"))?;
+            let Value::Array(mut values) = self.element(mapping).await? else {
+                return Err(mapping_error(mapping, "element of the getter is no array"));
+            };
+            *values.get_mut(index).ok_or_else(|| mapping_error(mapping, "index out of range"))? = value;
+            value = Value::Array(values);
+        }
+        let mut arguments = mapping.arguments.clone();
+        arguments.insert(argument.name.to_string(), value);
+        self.call(mapping.uid, setter, arguments).await.map(|_| ())
+    }
+
+    /// Answers a request PDU with a response PDU.
+    async fn handle(&self, request: &[u8]) -> Vec<u8> {
+        let function = request[0];
+        let word = |offset: usize| request.get(offset..offset + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        let result = match (function, word(1), word(3)) {
+            (1..=4, Some(start), Some(count)) => {
+                let (table, max) = match function {
+                    1 => (Table::Coils, MAX_READ_BITS),
+                    2 => (Table::DiscreteInputs, MAX_READ_BITS),
+                    3 => (Table::HoldingRegisters, MAX_READ_REGISTERS),
+                    _ => (Table::InputRegisters, MAX_READ_REGISTERS),
+                };
+                if !(1..=max).contains(&count) {
+                    Err(Exception::IllegalDataValue)
+                } else if u32::from(start) + u32::from(count) > 0x10000 {
+                    Err(Exception::IllegalDataAddress)
+                } else {
+                    self.read(table, start, count).await.map(|values| encode_values(table, &values))
+                }
+            }
+            (5, Some(address), Some(value)) => match value {
+                0xFF00 | 0x0000 => self.write(Table::Coils, address, &[u16::from(value != 0)]).await.map(|_| request[1..5].to_vec()),
+                _ => Err(Exception::IllegalDataValue),
+            },
+            (6, Some(address), Some(value)) => self.write(Table::HoldingRegisters, address, &[value]).await.map(|_| request[1..5].to_vec()),
+            (15 | 16, Some(start), Some(count)) => {
+                let (table, max) =
+                    if function == 15 { (Table::Coils, MAX_WRITE_BITS) } else { (Table::HoldingRegisters, MAX_WRITE_REGISTERS) };
+                match decode_values(table, count, &request[5..]) {
+                    Some(_) if !(1..=max).contains(&count) => Err(Exception::IllegalDataValue),
+                    Some(_) if u32::from(start) + u32::from(count) > 0x10000 => Err(Exception::IllegalDataAddress),
+                    Some(values) => self.write(table, start, &values).await.map(|_| request[1..5].to_vec()),
+                    None => Err(Exception::IllegalDataValue),
+                }
+            }
+            (1..=6 | 15 | 16, _, _) => Err(Exception::IllegalDataValue),
+            _ => Err(Exception::IllegalFunction),
+        };
+        match result {
+            Ok(data) => [&[function], data.as_slice()].concat(),
+            Err(exception) => vec![function | 0x80, exception as u8],
+        }
+    }
+}
+
+/// Encodes read values with their byte count, bits are packed starting with the least significant bit.
+fn encode_values(table: Table, values: &[u16]) -> Vec<u8> {
+    let bytes = if table.holds_bits() {
+        values.chunks(8).map(|bits| bits.iter().enumerate().fold(0, |byte, (index, bit)| byte | ((*bit as u8) << index))).collect()
+    } else {
+        values.iter().flat_map(|value| value.to_be_bytes()).collect::<Vec<_>>()
+    };
+    [&[bytes.len() as u8], bytes.as_slice()].concat()
+}
+
+/// Decodes the byte count and values of a write request.
+fn decode_values(table: Table, count: u16, data: &[u8]) -> Option<Vec<u16>> {
+    let (&byte_count, bytes) = data.split_first()?;
+    let expected = if table.holds_bits() { usize::from(count).div_ceil(8) } else { usize::from(count) * 2 };
+    if usize::from(byte_count) != expected || bytes.len() != expected {
+        return None;
+    }
+    Some(if table.holds_bits() {
+        (0..usize::from(count)).map(|index| u16::from(bytes[index / 8] & (1 << (index % 8)) != 0)).collect()
+    } else {
+        bytes.chunks_exact(2).map(|word| u16::from_be_bytes([word[0], word[1]])).collect()
+    })
+}
+
+/// Connects to the Brick Daemon and keeps track of its devices, reconnecting when the connection fails.
+async fn maintain_connection(shared: Arc<Shared>) {
+    let address = &shared.config.brick_daemon;
+    loop {
+        let mut connection = match AsyncIpConnection::new(address.clone()).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Cannot connect to {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if let Some(secret) = &shared.config.secret {
+            if let Err(error) = connection.authenticate(secret).await {
+                warn!("Cannot authenticate at {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        let enumerations = match connection.enumerate().await {
+            Ok(enumerations) => enumerations,
+            Err(error) => {
+                warn!("Cannot enumerate devices of {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Connected to {address}");
+        shared.connection.send_replace(Some(connection));
+        tokio::pin!(enumerations);
+        while let Some(enumeration) = enumerations.next().await {
+            let mut devices = lock(&shared.devices);
+            match enumeration.device_identifier.parsed() {
+                Some(device) if enumeration.enumeration_type != EnumerationType::Disconnected => devices.insert(enumeration.uid, device),
+                _ => devices.remove(&enumeration.uid),
+            };
+        }
+        warn!("Lost connection to {address}");
+        shared.connection.send_replace(None);
+        lock(&shared.devices).clear();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn serve_client(shared: Arc<Shared>, mut stream: TcpStream, peer: SocketAddr) {
+    let mut header = [0; HEADER_SIZE];
+    loop {
+        if stream.read_exact(&mut header).await.is_err() {
+            break;
+        }
+        let protocol = u16::from_be_bytes([header[2], header[3]]);
+        let length = usize::from(u16::from_be_bytes([header[4], header[5]]));
+        // the length includes the unit id, a PDU holds at most 253 bytes
+        if protocol != 0 || !(2..=254).contains(&length) {
+            warn!("Invalid request of {peer}, closing connection");
+            break;
+        }
+        let mut request = vec![0; length - 1];
+        if stream.read_exact(&mut request).await.is_err() {
+            break;
+        }
+        let response = shared.handle(&request).await;
+        let mut packet = Vec::with_capacity(HEADER_SIZE + response.len());
+        packet.extend_from_slice(&header[0..4]);
+        packet.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        packet.push(header[6]);
+        packet.extend_from_slice(&response);
+        if stream.write_all(&packet).await.is_err() {
+            break;
+        }
+    }
+    info!("Client {peer} disconnected");
+}
+
+#[cfg(test)]
+mod test {
+    use crate::modbus::{decode_values, encode_values, ModbusConfig, RegisterType, Table};
+
+    #[test]
+    fn test_register_types() {
+        assert_eq!(RegisterType::I16.encode(-2.0), Some(vec![0xFFFE]));
+        assert_eq!(RegisterType::U16.encode(-2.0), None);
+        assert_eq!(RegisterType::I32.encode(70000.0), Some(vec![1, 4464]));
+        assert_eq!(RegisterType::I32.decode(&[0xFFFF, 0xFFFF]), -1.0);
+        assert_eq!(RegisterType::F32.decode(&RegisterType::F32.encode(1.5).unwrap()), 1.5);
+        let bits = [1, 0, 1, 1, 0, 0, 0, 0, 1];
+        let encoded = encode_values(Table::Coils, &bits);
+        assert_eq!(encoded, vec![2, 0b1101, 1]);
+        assert_eq!(decode_values(Table::Coils, 9, &encoded), Some(bits.to_vec()));
+        assert_eq!(decode_values(Table::HoldingRegisters, 2, &[4, 0, 1]), None);
+    }
+
+    #[test]
+    fn test_register_map() {
+        let config = |registers: &str| {
+            ModbusConfig::from_toml(&format!(
+                r#"
+                [[input_registers]]
+                address = 10
+                uid = "ZQH"
+                getter = "get_temperature"
+                type = "i32"
+                {registers}
+                "#
+            ))
+            .unwrap()
+            .register_map()
+        };
+        assert!(config("").is_ok());
+        assert!(config("[[input_registers]]\naddress = 11\nuid = \"ZQH\"\ngetter = \"get_temperature\"").is_err());
+        assert!(config("[[input_registers]]\naddress = 9\nuid = \"ZQH\"\ngetter = \"get_temperature\"").is_ok());
+        assert!(config("[[holding_registers]]\naddress = 10\nuid = \"ZQH\"\ngetter = \"get_temperature\"").is_err());
+    }
+}
@@ -0,0 +1,776 @@
+//! Bridges Brick Daemons to an MQTT broker, with the topic scheme and JSON payloads of the MQTT bindings.
+//!
+//! A JSON object published to `<prefix>request/<device>/<uid>/<function>` calls the function with the object's entries
+//! as arguments, the result is published to `<prefix>response/<device>/<uid>/<function>`. Publishing `true` to
+//! `<prefix>register/<device>/<uid>/<callback>` forwards the callback to `<prefix>callback/<device>/<uid>/<callback>`
+//! until `false` is published. Devices are named like `temperature_v2_bricklet`, constants by their snake case name
+//! and failures are reported as `{"_ERROR": "..."}`. Anything after the function name is appended to the response
+//! topic, so clients can tell their responses apart. `ip_connection/enumerate`, `ip_connection/get_connection_state`
+//! and `bindings/reset_callbacks` work as in the MQTT bindings.
+//!
+//! In addition, the enumeration of every device is published retained to `<prefix>devices/<uid>` and cleared when the
+//! device is disconnected. With Home Assistant discovery, the measured values of every device (see
+//! [`DeviceMetadata::primary_values`]) are announced as sensors and polled periodically.
+//!
+//! The configuration is read from TOML or JSON:
+//! ```toml
+//! global_topic_prefix = "tinkerforge/"
+//!
+//! [broker]
+//! host = "localhost"
+//! port = 1883
+//!
+//! [[brick_daemons]]
+//! host = "localhost"
+//!
+//! # topics of further daemons are prefixed with their name, e.g. tinkerforge/cellar/request/...
+//! [[brick_daemons]]
+//! name = "cellar"
+//! host = "cellar.local"
+//! secret = "secret"
+//!
+//! [home_assistant]
+//! discovery_prefix = "homeassistant"
+//! poll_interval_ms = 10000
+//! ```
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{sync::watch, task::AbortHandle};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    dynamic::{DeviceDefinition, DynamicDevice},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerateResponse, EnumerationType},
+    metadata::{self, DeviceMetadata},
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const QOS: QoS = QoS::AtLeastOnce;
+/// Messages queued for the broker before publishing waits.
+const REQUEST_CAPACITY: usize = 64;
+
+/// Broker, Brick Daemons and Home Assistant discovery of an [`MqttBridge`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    #[serde(default = "default_topic_prefix")]
+    pub global_topic_prefix: String,
+    #[serde(default)]
+    pub broker: BrokerConfig,
+    pub brick_daemons: Vec<BrickDaemonConfig>,
+    /// Announces the devices to Home Assistant, disabled if missing.
+    #[serde(default)]
+    pub home_assistant: Option<HomeAssistantConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_broker_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Default for BrokerConfig {
+    fn default() -> Self {
+        BrokerConfig { host: default_host(), port: default_broker_port(), client_id: default_client_id(), username: None, password: None }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrickDaemonConfig {
+    /// Added to the topic prefix, only one daemon may be unnamed.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_brick_daemon_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HomeAssistantConfig {
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+    /// Interval the announced values are read in.
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_topic_prefix() -> String {
+    "tinkerforge/".to_string()
+}
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_broker_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "tinkerforge_mqtt".to_string()
+}
+
+fn default_brick_daemon_port() -> u16 {
+    4223
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_poll_interval_ms() -> u64 {
+    10000
+}
+
+impl MqttConfig {
+    pub fn from_toml(toml: &str) -> Result<MqttConfig, TinkerforgeError> {
+        toml::from_str(toml).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    pub fn from_json(json: &str) -> Result<MqttConfig, TinkerforgeError> {
+        serde_json::from_str(json).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    /// Reads a configuration, files ending in `.json` are parsed as JSON, all others as TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<MqttConfig, TinkerforgeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            MqttConfig::from_json(&content)
+        } else {
+            MqttConfig::from_toml(&content)
+        }
+    }
+
+    /// Topic prefixes of the Brick Daemons, in the order they are configured.
+    fn daemon_prefixes(&self) -> Result<Vec<String>, TinkerforgeError> {
+        let invalid = |reason: String| Err(TinkerforgeError::InvalidConfig(reason));
+        let mut global_prefix = self.global_topic_prefix.clone();
+        if !global_prefix.is_empty() && !global_prefix.ends_with('/') {
+            global_prefix.push('/');
+        }
+        if global_prefix.contains(['#', '+']) || global_prefix.starts_with('$') {
+            return invalid(format!("Topic prefix {global_prefix} must not contain wildcards or start with $"));
+        }
+        if self.brick_daemons.is_empty() {
+            return invalid("No Brick Daemon configured".to_string());
+        }
+        let mut prefixes = Vec::<String>::with_capacity(self.brick_daemons.len());
+        for daemon in &self.brick_daemons {
+            let prefix = match &daemon.name {
+                Some(name) if name.is_empty() || name.contains(['#', '+', '/']) => {
+                    return invalid(format!("Invalid Brick Daemon name {name}"))
+                }
+                Some(name) => format!("{global_prefix}{name}/"),
+                None => global_prefix.clone(),
+            };
+            if prefixes.contains(&prefix) {
+                return invalid(format!("Brick Daemons share the topic prefix {prefix}, give them distinct names"));
+            }
+            prefixes.push(prefix);
+        }
+        Ok(prefixes)
+    }
+}
+
+/// A bridge between Brick Daemons and an MQTT broker, stops when dropped.
+pub struct MqttBridge {
+    tasks: Vec<AbortHandle>,
+}
+
+impl MqttBridge {
+    /// Connects to the broker and the Brick Daemons, connections are retried until the bridge is dropped.
+    pub fn start(config: MqttConfig) -> Result<MqttBridge, TinkerforgeError> {
+        let prefixes = config.daemon_prefixes()?;
+        let global_prefix = prefixes.iter().min_by_key(|prefix| prefix.len()).cloned().unwrap_or_default();
+        let broker = &config.broker;
+        let mut options = MqttOptions::new(&broker.client_id, &broker.host, broker.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(format!("{global_prefix}callback/bindings/last_will"), "null", QOS, false));
+        if let Some(username) = &broker.username {
+            options.set_credentials(username, broker.password.clone().unwrap_or_default());
+        }
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let daemons = config
+            .brick_daemons
+            .into_iter()
+            .zip(prefixes)
+            .map(|(config, prefix)| {
+                Arc::new(Daemon {
+                    config,
+                    prefix,
+                    connection: watch::channel(None).0,
+                    registrations: Mutex::new(HashMap::new()),
+                    enumerate_topics: Mutex::new(Vec::new()),
+                    devices: Mutex::new(HashSet::new()),
+                    announced: Mutex::new(HashMap::new()),
+                })
+            })
+            .collect::<Vec<_>>();
+        let shared = Arc::new(Shared { client, home_assistant: config.home_assistant, daemons, definitions: Mutex::new(BTreeMap::new()) });
+        let mut tasks = vec![tokio::spawn(run_event_loop(shared.clone(), event_loop)).abort_handle()];
+        for daemon in &shared.daemons {
+            tasks.push(tokio::spawn(maintain_connection(shared.clone(), daemon.clone())).abort_handle());
+        }
+        Ok(MqttBridge { tasks })
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct Shared {
+    client: AsyncClient,
+    home_assistant: Option<HomeAssistantConfig>,
+    daemons: Vec<Arc<Daemon>>,
+    definitions: Mutex<BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>>,
+}
+
+impl Shared {
+    async fn publish(&self, topic: &str, retain: bool, payload: impl Into<Vec<u8>>) {
+        if let Err(error) = self.client.publish(topic, QOS, retain, payload).await {
+            warn!("Cannot publish to {topic}: {error}");
+        }
+    }
+
+    fn definition(&self, device: DeviceIdentifier) -> Arc<DeviceDefinition> {
+        let mut definitions = self.definitions.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        definitions.entry(device).or_insert_with(|| Arc::new(DeviceDefinition::from(metadata::device(device)))).clone()
+    }
+
+    /// Returns the daemon a topic belongs to and the topic without its prefix.
+    fn route<'a>(&self, topic: &'a str) -> Option<(&Arc<Daemon>, &'a str)> {
+        self.daemons
+            .iter()
+            .filter_map(|daemon| Some((daemon, topic.strip_prefix(daemon.prefix.as_str())?)))
+            .filter(|(_, path)| path.starts_with("request/") || path.starts_with("register/"))
+            .max_by_key(|(daemon, _)| daemon.prefix.len())
+    }
+}
+
+struct Daemon {
+    config: BrickDaemonConfig,
+    prefix: String,
+    connection: watch::Sender<Option<AsyncIpConnection>>,
+    /// Tasks forwarding callbacks, by the topic they publish to.
+    registrations: Mutex<HashMap<String, AbortHandle>>,
+    /// Topics enumerations are published to.
+    enumerate_topics: Mutex<Vec<String>>,
+    /// Devices with a retained enumeration.
+    devices: Mutex<HashSet<Uid>>,
+    /// Devices announced to Home Assistant with the task polling their values.
+    announced: Mutex<HashMap<Uid, (DeviceIdentifier, AbortHandle)>>,
+}
+
+impl Daemon {
+    fn address(&self) -> String {
+        format!("{}:{}", self.config.host, self.config.port)
+    }
+
+    fn reset_callbacks(&self) {
+        for (_, task) in lock(&self.registrations).drain() {
+            task.abort();
+        }
+        lock(&self.enumerate_topics).clear();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Topic of a request, without the prefix of its daemon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TopicPath<'a> {
+    request_type: &'a str,
+    device: &'a str,
+    /// Missing for `ip_connection` and `bindings`.
+    uid: Option<&'a str>,
+    function: &'a str,
+    suffix: Option<&'a str>,
+}
+
+impl<'a> TopicPath<'a> {
+    fn parse(path: &'a str) -> Option<TopicPath<'a>> {
+        let mut parts = path.splitn(3, '/');
+        let request_type = parts.next()?;
+        let device = parts.next()?;
+        let rest = parts.next()?;
+        let (uid, rest) = match device {
+            "ip_connection" | "bindings" => (None, rest),
+            _ => rest.split_once('/').map(|(uid, rest)| (Some(uid), rest))?,
+        };
+        let (function, suffix) = rest.split_once('/').map_or((rest, None), |(function, suffix)| (function, Some(suffix)));
+        (!function.is_empty()).then_some(TopicPath { request_type, device, uid, function, suffix })
+    }
+
+    /// Topic of the responses, without the prefix of the daemon.
+    fn response_path(&self) -> String {
+        let response_type = if self.request_type == "request" { "response" } else { "callback" };
+        let mut path = format!("{response_type}/{}/", self.device);
+        if let Some(uid) = self.uid {
+            path.push_str(uid);
+            path.push('/');
+        }
+        path.push_str(self.function);
+        if let Some(suffix) = self.suffix {
+            path.push('/');
+            path.push_str(suffix);
+        }
+        path
+    }
+}
+
+/// Name of a device in topics, e.g. `temperature_v2_bricklet`.
+pub fn mqtt_device_name(device: &DeviceMetadata) -> String {
+    format!("{}_{}", device.name, device.category).to_lowercase().replace([' ', '-'], "_")
+}
+
+fn device_by_mqtt_name(name: &str) -> Option<&'static DeviceMetadata> {
+    metadata::devices().iter().copied().find(|device| mqtt_device_name(device) == name)
+}
+
+fn error(message: impl Into<String>) -> Value {
+    json!({ "_ERROR": message.into() })
+}
+
+async fn run_event_loop(shared: Arc<Shared>, mut event_loop: EventLoop) {
+    let mut first_connection = true;
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("Connected to MQTT broker");
+                // the client queues these, awaiting them here would block the loop processing the queue
+                for daemon in &shared.daemons {
+                    for filter in ["request/#", "register/#"] {
+                        if let Err(error) = shared.client.try_subscribe(format!("{}{filter}", daemon.prefix), QOS) {
+                            warn!("Cannot subscribe to {}{filter}: {error}", daemon.prefix);
+                        }
+                    }
+                    if first_connection {
+                        let _ = shared.client.try_publish(format!("{}callback/bindings/restart", daemon.prefix), QOS, false, "null");
+                    }
+                }
+                first_connection = false;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                tokio::spawn(handle_message(shared.clone(), publish.topic, publish.payload.to_vec()));
+            }
+            Ok(_) => {}
+            Err(error) => {
+                warn!("MQTT connection failed: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn handle_message(shared: Arc<Shared>, topic: String, payload: Vec<u8>) {
+    let Some((daemon, path)) = shared.route(&topic) else {
+        return;
+    };
+    let Some(request) = TopicPath::parse(path) else {
+        warn!("Malformed topic {topic}, expected [request|register]/<device>/<uid>/<function>");
+        return;
+    };
+    let response_topic = format!("{}{}", daemon.prefix, request.response_path());
+    let response = match std::str::from_utf8(&payload) {
+        Err(reason) => Some(error(format!("Could not decode payload as UTF-8: {reason}"))),
+        Ok(payload) => match (request.request_type, request.device) {
+            ("request", "ip_connection") => Some(ip_connection_request(daemon, request.function).await),
+            ("register", "ip_connection") if request.function == "enumerate" => match parse_registration(payload) {
+                Ok(true) => {
+                    let mut topics = lock(&daemon.enumerate_topics);
+                    if !topics.contains(&response_topic) {
+                        topics.push(response_topic.clone());
+                    }
+                    None
+                }
+                Ok(false) => {
+                    lock(&daemon.enumerate_topics).retain(|topic| *topic != response_topic);
+                    None
+                }
+                Err(message) => Some(error(message)),
+            },
+            ("request", "bindings") if request.function == "reset_callbacks" => {
+                daemon.reset_callbacks();
+                None
+            }
+            ("request", _) => Some(device_request(&shared, daemon, request, payload).await),
+            ("register", _) => register_callback(&shared, daemon, request, payload, response_topic.clone()).err().map(error),
+            _ => Some(error(format!("Unknown {} request {}", request.device, request.function))),
+        },
+    };
+    if let Some(response) = response {
+        debug!("Publishing response to {response_topic}");
+        shared.publish(&response_topic, false, response.to_string()).await;
+    }
+}
+
+async fn ip_connection_request(daemon: &Daemon, function: &str) -> Value {
+    let connection = daemon.connection.borrow().clone();
+    match (function, connection) {
+        ("enumerate", Some(mut connection)) => match connection.enumerate().await {
+            // the enumerations arrive at the task maintaining the connection
+            Ok(_) => Value::Null,
+            Err(reason) => error(reason.to_string()),
+        },
+        ("enumerate", None) => error("Not connected to the Brick Daemon"),
+        ("get_connection_state", connection) => {
+            json!({ "connection_state": if connection.is_some() { "connected" } else { "disconnected" } })
+        }
+        _ => error(format!("Unknown ip connection function {function}")),
+    }
+}
+
+/// Accepts `true`, `false` and `{"register": true}` like the MQTT bindings.
+fn parse_registration(payload: &str) -> Result<bool, String> {
+    match serde_json::from_str::<Value>(payload) {
+        Ok(Value::Bool(register)) => Ok(register),
+        Ok(Value::Object(object)) if object.get("register").is_some_and(Value::is_boolean) => Ok(object["register"] == Value::Bool(true)),
+        Ok(_) => Err(format!("Expected bool as parameter of callback registration, but got {payload}")),
+        Err(reason) => Err(format!("Could not parse payload for callback registration as JSON encoding a boolean: {reason}")),
+    }
+}
+
+async fn device_request(shared: &Shared, daemon: &Daemon, request: TopicPath<'_>, payload: &str) -> Value {
+    let Some(device) = device_by_mqtt_name(request.device) else {
+        return error(format!("Unknown device type {}", request.device));
+    };
+    let Ok(uid) = request.uid.unwrap_or_default().parse::<Uid>() else {
+        return error(format!("Could not parse UID {:?}", request.uid.unwrap_or_default()));
+    };
+    let arguments = match payload.trim() {
+        "" => Value::Object(Map::new()),
+        payload => match serde_json::from_str::<Value>(payload) {
+            Ok(arguments @ Value::Object(_)) => arguments,
+            Ok(_) => return error(format!("Expected a JSON object as arguments of {}", request.function)),
+            Err(reason) => {
+                return error(format!(
+                    "Could not parse payload for {} call of {} {uid} as JSON: {reason}",
+                    request.function, request.device
+                ))
+            }
+        },
+    };
+    call(shared, daemon, device, uid, request.function, arguments).await
+}
+
+async fn call(shared: &Shared, daemon: &Daemon, device: &DeviceMetadata, uid: Uid, function: &str, arguments: Value) -> Value {
+    let Some(connection) = daemon.connection.borrow().clone() else {
+        return error("Not connected to the Brick Daemon");
+    };
+    let mut dynamic = DynamicDevice::new(uid, connection, shared.definition(device.identifier));
+    match dynamic.call(function, arguments).await {
+        Ok(mut response) if function == "get_identity" => {
+            let device_identifier = response["device_identifier"].as_u64().and_then(|identifier| u16::try_from(identifier).ok());
+            if let Some(identified) = device_identifier.and_then(|identifier| TryInto::<DeviceIdentifier>::try_into(identifier).ok()) {
+                response["device_identifier"] = Value::from(mqtt_device_name(identified.metadata()));
+                response["_display_name"] = Value::from(identified.metadata().display_name);
+            }
+            response
+        }
+        Ok(response) => response,
+        Err(reason) => error(format!("{reason} (call of {function} of {} {uid})", mqtt_device_name(device))),
+    }
+}
+
+fn register_callback(
+    shared: &Arc<Shared>,
+    daemon: &Arc<Daemon>,
+    request: TopicPath<'_>,
+    payload: &str,
+    topic: String,
+) -> Result<(), String> {
+    let register = parse_registration(payload)?;
+    let device = device_by_mqtt_name(request.device).ok_or_else(|| format!("Unknown device type {}", request.device))?;
+    let uid =
+        request.uid.unwrap_or_default().parse::<Uid>().map_err(|_| format!("Could not parse UID {:?}", request.uid.unwrap_or_default()))?;
+    if device.callback(request.function).is_none() {
+        return Err(format!("Unknown callback {} for device {uid} of type {}", request.function, request.device));
+    }
+    let mut registrations = lock(&daemon.registrations);
+    if !register {
+        if let Some(task) = registrations.remove(&topic) {
+            task.abort();
+        }
+    } else if let Entry::Vacant(entry) = registrations.entry(topic) {
+        let task = tokio::spawn(forward_callback(
+            shared.clone(),
+            daemon.clone(),
+            shared.definition(device.identifier),
+            uid,
+            request.function.to_string(),
+            entry.key().clone(),
+        ));
+        entry.insert(task.abort_handle());
+    }
+    Ok(())
+}
+
+/// Publishes a callback of a device, across reconnects of the Brick Daemon and the device.
+async fn forward_callback(
+    shared: Arc<Shared>,
+    daemon: Arc<Daemon>,
+    definition: Arc<DeviceDefinition>,
+    uid: Uid,
+    callback: String,
+    topic: String,
+) {
+    let mut connections = daemon.connection.subscribe();
+    loop {
+        let connection = connections.borrow_and_update().clone();
+        if let Some(connection) = connection {
+            let mut device = DynamicDevice::new(uid, connection, definition.clone());
+            if let Ok(values) = device.callback_stream(&callback).await {
+                tokio::pin!(values);
+                loop {
+                    tokio::select! {
+                        value = values.next() => match value {
+                            Some(value) => shared.publish(&topic, false, value.to_string()).await,
+                            None => break,
+                        },
+                        _ = connections.changed() => break,
+                    }
+                }
+            }
+        }
+        // the stream also ends when the device is disconnected, it is subscribed again after a while
+        tokio::select! {
+            result = connections.changed() => if result.is_err() { return },
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+        }
+    }
+}
+
+/// Connects to a Brick Daemon and publishes its enumerations, reconnecting when the connection fails.
+async fn maintain_connection(shared: Arc<Shared>, daemon: Arc<Daemon>) {
+    loop {
+        let address = daemon.address();
+        let mut connection = match AsyncIpConnection::new(address.clone()).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Cannot connect to {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if let Some(secret) = &daemon.config.secret {
+            if let Err(error) = connection.authenticate(secret).await {
+                warn!("Cannot authenticate at {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        let enumerations = match connection.enumerate().await {
+            Ok(enumerations) => enumerations,
+            Err(error) => {
+                warn!("Cannot enumerate devices of {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Connected to {address}");
+        daemon.connection.send_replace(Some(connection));
+        tokio::pin!(enumerations);
+        while let Some(enumeration) = enumerations.next().await {
+            publish_enumeration(&shared, &daemon, &enumeration).await;
+        }
+        warn!("Lost connection to {address}");
+        daemon.connection.send_replace(None);
+        let devices = lock(&daemon.devices).drain().collect::<Vec<_>>();
+        for uid in devices {
+            withdraw_device(&shared, &daemon, uid).await;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+fn enumeration_payload(enumeration: &EnumerateResponse) -> Value {
+    let uid_text = |uid: Uid| if u32::from(uid) == 0 { "0".to_string() } else { uid.to_string() };
+    let enumeration_type = match enumeration.enumeration_type {
+        EnumerationType::Available => "available",
+        EnumerationType::Connected => "connected",
+        EnumerationType::Disconnected => "disconnected",
+        EnumerationType::Unknown => "unknown",
+    };
+    let version = |version: crate::ip_connection::Version| json!([version.major(), version.minor(), version.patch()]);
+    let mut payload = json!({
+        "uid": uid_text(enumeration.uid),
+        "connected_uid": uid_text(enumeration.connected_uid),
+        "position": if enumeration.position == '\0' { 'x' } else { enumeration.position }.to_string(),
+        "hardware_version": version(enumeration.hardware_version),
+        "firmware_version": version(enumeration.firmware_version),
+        "device_identifier": enumeration.device_identifier.raw(),
+        "enumeration_type": enumeration_type,
+    });
+    if let Some(device) = enumeration.device_identifier.parsed().filter(|_| enumeration.enumeration_type != EnumerationType::Disconnected) {
+        payload["device_identifier"] = Value::from(mqtt_device_name(device.metadata()));
+        payload["_display_name"] = Value::from(device.metadata().display_name);
+    }
+    payload
+}
+
+async fn publish_enumeration(shared: &Arc<Shared>, daemon: &Arc<Daemon>, enumeration: &EnumerateResponse) {
+    let payload = enumeration_payload(enumeration).to_string();
+    let topics = lock(&daemon.enumerate_topics).clone();
+    for topic in topics {
+        shared.publish(&topic, false, payload.clone()).await;
+    }
+    if enumeration.enumeration_type == EnumerationType::Disconnected {
+        lock(&daemon.devices).remove(&enumeration.uid);
+        withdraw_device(shared, daemon, enumeration.uid).await;
+        return;
+    }
+    lock(&daemon.devices).insert(enumeration.uid);
+    shared.publish(&format!("{}devices/{}", daemon.prefix, enumeration.uid), true, payload).await;
+    if let (Some(home_assistant), Some(device)) = (&shared.home_assistant, enumeration.device_identifier.parsed()) {
+        announce_to_home_assistant(shared, daemon, home_assistant, device.metadata(), enumeration).await;
+    }
+}
+
+fn discovery_topic(home_assistant: &HomeAssistantConfig, uid: Uid, value: &str) -> String {
+    format!("{}/sensor/tinkerforge_{uid}/{value}/config", home_assistant.discovery_prefix)
+}
+
+/// Announces the measured values of a device as sensors and starts polling them.
+async fn announce_to_home_assistant(
+    shared: &Arc<Shared>,
+    daemon: &Arc<Daemon>,
+    home_assistant: &HomeAssistantConfig,
+    device: &'static DeviceMetadata,
+    enumeration: &EnumerateResponse,
+) {
+    let uid = enumeration.uid;
+    if device.primary_values().next().is_none() || lock(&daemon.announced).contains_key(&uid) {
+        return;
+    }
+    let device_name = mqtt_device_name(device);
+    for (packet, element) in device.primary_values() {
+        let si_unit = element.si_unit.expect("primary values have a unit");
+        let mut name = element.name.replace('_', " ");
+        name[..1].make_ascii_uppercase();
+        let mut config = json!({
+            "name": name,
+            "unique_id": format!("tinkerforge_{uid}_{}", element.name),
+            "state_topic": format!("{}response/{device_name}/{uid}/{}/home_assistant", daemon.prefix, packet.name),
+            "value_template": format!("{{{{ value_json.{} * {} }}}}", element.name, si_unit.factor),
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [format!("tinkerforge_{uid}")],
+                "name": format!("{} {uid}", device.display_name),
+                "manufacturer": "Tinkerforge",
+                "model": device.display_name,
+                "sw_version": enumeration.firmware_version.to_string(),
+            },
+        });
+        if !si_unit.symbol.is_empty() {
+            config["unit_of_measurement"] = Value::from(si_unit.symbol);
+        }
+        shared.publish(&discovery_topic(home_assistant, uid, element.name), true, config.to_string()).await;
+    }
+    let poll =
+        tokio::spawn(poll_values(shared.clone(), daemon.clone(), device, uid, Duration::from_millis(home_assistant.poll_interval_ms)));
+    lock(&daemon.announced).insert(uid, (device.identifier, poll.abort_handle()));
+}
+
+/// Clears the retained enumeration of a device and removes its sensors from Home Assistant.
+async fn withdraw_device(shared: &Shared, daemon: &Daemon, uid: Uid) {
+    shared.publish(&format!("{}devices/{uid}", daemon.prefix), true, "").await;
+    let (Some(home_assistant), Some((device, poll))) = (&shared.home_assistant, lock(&daemon.announced).remove(&uid)) else {
+        return;
+    };
+    poll.abort();
+    for (_, element) in device.metadata().primary_values() {
+        shared.publish(&discovery_topic(home_assistant, uid, element.name), true, "").await;
+    }
+}
+
+async fn poll_values(shared: Arc<Shared>, daemon: Arc<Daemon>, device: &'static DeviceMetadata, uid: Uid, interval: Duration) {
+    let device_name = mqtt_device_name(device);
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        for (packet, _) in device.primary_values() {
+            let response = call(&shared, &daemon, device, uid, packet.name, Value::Object(Map::new())).await;
+            let topic = format!("{}response/{device_name}/{uid}/{}/home_assistant", daemon.prefix, packet.name);
+            shared.publish(&topic, false, response.to_string()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        metadata,
+        mqtt::{mqtt_device_name, parse_registration, MqttConfig, TopicPath},
+        DeviceIdentifier,
+    };
+
+    #[test]
+    fn test_topics() {
+        let request = TopicPath::parse("request/temperature_v2_bricklet/ZQH/get_temperature/client1").unwrap();
+        assert_eq!(
+            request,
+            TopicPath {
+                request_type: "request",
+                device: "temperature_v2_bricklet",
+                uid: Some("ZQH"),
+                function: "get_temperature",
+                suffix: Some("client1")
+            }
+        );
+        assert_eq!(request.response_path(), "response/temperature_v2_bricklet/ZQH/get_temperature/client1");
+        let register = TopicPath::parse("register/ip_connection/enumerate").unwrap();
+        assert_eq!(register.response_path(), "callback/ip_connection/enumerate");
+        assert_eq!(TopicPath::parse("request/temperature_v2_bricklet/ZQH"), None);
+        assert_eq!(mqtt_device_name(metadata::device(DeviceIdentifier::TemperatureV2Bricklet)), "temperature_v2_bricklet");
+        assert_eq!(parse_registration(r#"{"register": false}"#), Ok(false));
+        assert!(parse_registration("1").is_err());
+    }
+
+    #[test]
+    fn test_daemon_prefixes() {
+        let config = MqttConfig::from_toml(
+            r#"
+            global_topic_prefix = "tf"
+            [[brick_daemons]]
+            [[brick_daemons]]
+            name = "cellar"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.daemon_prefixes().unwrap(), vec!["tf/", "tf/cellar/"]);
+        let config = MqttConfig::from_toml("[[brick_daemons]]\n[[brick_daemons]]").unwrap();
+        assert!(config.daemon_prefixes().is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Writes and reads TFP traffic as pcap files.
+//!
+//! Packets are written as a TCP stream between a client port and the Brick Daemon port 4223 on the loopback address,
+//! so Wireshark shows them with its own TFP dissector. [`read_pcap`] accepts these files as well as captures of real
+//! Brick Daemon traffic taken with tcpdump or Wireshark, and returns their packets as a [`Recording`]. Use
+//! [`AsyncIpConnection::capture_to_pcap_file`](crate::ip_connection::async_io::AsyncIpConnection::capture_to_pcap_file)
+//! to capture a connection, and the `tfp-dump` binary to print a capture with the [`dissector`](crate::dissector).
+//!
+//! TCP segments are reassembled per connection, retransmitted data is skipped and a packet interrupted by a gap in the
+//! capture is dropped.
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+
+use crate::{
+    error::TinkerforgeError,
+    ip_connection::PacketHeader,
+    recording::{PacketDirection, RecordedPacket, Recording},
+};
+
+/// Port of the Brick Daemon, used to tell requests from responses.
+pub const BRICK_DAEMON_PORT: u16 = 4223;
+/// Port of the client in written captures.
+const CLIENT_PORT: u16 = 49152;
+const LOCALHOST: [u8; 4] = [127, 0, 0, 1];
+
+const MAGIC_MICROSECONDS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOSECONDS: u32 = 0xa1b23c4d;
+const SNAPLEN: u32 = 65535;
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const TCP_HEADER_SIZE: usize = 20;
+const TCP_FLAG_SYN: u8 = 0x02;
+const TCP_FLAGS_PSH_ACK: u8 = 0x18;
+
+/// Returns whether `bytes` start like a pcap file.
+pub fn is_pcap(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && {
+        let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        [MAGIC_MICROSECONDS, MAGIC_NANOSECONDS].into_iter().any(|expected| magic == expected || magic == expected.swap_bytes())
+    }
+}
+
+/// Writes packets as a pcap file of a single TCP connection.
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    start: SystemTime,
+    /// Next TCP sequence number of the client and the Brick Daemon.
+    sequence_numbers: [u32; 2],
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header, the elapsed time of packets is added to the current time.
+    pub fn new(mut writer: W) -> io::Result<PcapWriter<W>> {
+        writer.write_all(&MAGIC_MICROSECONDS.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?;
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        Ok(PcapWriter { writer, start: SystemTime::now(), sequence_numbers: [1, 1] })
+    }
+
+    pub fn write_packet(&mut self, packet: &RecordedPacket) -> io::Result<()> {
+        let (source_port, destination_port, own, other) = match packet.direction {
+            PacketDirection::Sent => (CLIENT_PORT, BRICK_DAEMON_PORT, 0, 1),
+            PacketDirection::Received => (BRICK_DAEMON_PORT, CLIENT_PORT, 1, 0),
+        };
+        let sequence_number = self.sequence_numbers[own];
+        self.sequence_numbers[own] = sequence_number.wrapping_add(packet.bytes.len() as u32);
+
+        let tcp_length = TCP_HEADER_SIZE + packet.bytes.len();
+        let mut frame = Vec::with_capacity(IPV4_HEADER_SIZE + tcp_length);
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&((IPV4_HEADER_SIZE + tcp_length) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, IP_PROTOCOL_TCP, 0, 0]);
+        frame.extend_from_slice(&LOCALHOST);
+        frame.extend_from_slice(&LOCALHOST);
+        let checksum = internet_checksum(0, &frame);
+        frame[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        frame.extend_from_slice(&source_port.to_be_bytes());
+        frame.extend_from_slice(&destination_port.to_be_bytes());
+        frame.extend_from_slice(&sequence_number.to_be_bytes());
+        frame.extend_from_slice(&self.sequence_numbers[other].to_be_bytes());
+        frame.extend_from_slice(&[(TCP_HEADER_SIZE as u8 / 4) << 4, TCP_FLAGS_PSH_ACK, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(&packet.bytes);
+        let mut pseudo_header = Vec::with_capacity(12);
+        pseudo_header.extend_from_slice(&LOCALHOST);
+        pseudo_header.extend_from_slice(&LOCALHOST);
+        pseudo_header.extend_from_slice(&[0, IP_PROTOCOL_TCP]);
+        pseudo_header.extend_from_slice(&(tcp_length as u16).to_be_bytes());
+        let checksum = internet_checksum(internet_sum(0, &pseudo_header), &frame[IPV4_HEADER_SIZE..]);
+        frame[IPV4_HEADER_SIZE + 16..IPV4_HEADER_SIZE + 18].copy_from_slice(&checksum.to_be_bytes());
+
+        let timestamp = (self.start + packet.elapsed).duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.writer.write_all(&(timestamp.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&timestamp.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&frame)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn internet_sum(mut sum: u32, bytes: &[u8]) -> u32 {
+    for chunk in bytes.chunks(2) {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]));
+    }
+    sum
+}
+
+fn internet_checksum(sum: u32, bytes: &[u8]) -> u16 {
+    let mut sum = internet_sum(sum, bytes);
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Reads the TFP packets of all connections to [`BRICK_DAEMON_PORT`] in a pcap file.
+pub fn read_pcap(reader: impl Read) -> Result<Recording, TinkerforgeError> {
+    read_pcap_with_port(reader, BRICK_DAEMON_PORT)
+}
+
+/// Reads the TFP packets of all connections to the given Brick Daemon port in a pcap file, the elapsed time of the
+/// packets is counted from the first frame.
+pub fn read_pcap_with_port(mut reader: impl Read, port: u16) -> Result<Recording, TinkerforgeError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let invalid = |reason: &str| TinkerforgeError::InvalidRecording(format!("Invalid pcap file: {reason}"));
+    if !is_pcap(&bytes) || bytes.len() < 24 {
+        return Err(invalid("missing file header"));
+    }
+    let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let little_endian = magic == MAGIC_MICROSECONDS || magic == MAGIC_NANOSECONDS;
+    let nanoseconds = magic == MAGIC_NANOSECONDS || magic == MAGIC_NANOSECONDS.swap_bytes();
+    let read_u32 = |offset: usize| {
+        let value = [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]];
+        if little_endian {
+            u32::from_le_bytes(value)
+        } else {
+            u32::from_be_bytes(value)
+        }
+    };
+    let link_type = read_u32(20) & 0x0fff_ffff;
+
+    let mut streams = HashMap::<TcpFlow, TcpStreamState>::new();
+    let mut packets = Vec::new();
+    let mut first_timestamp = None;
+    let mut offset = 24;
+    while offset + 16 <= bytes.len() {
+        let subsecond = u64::from(read_u32(offset + 4));
+        let timestamp = Duration::from_secs(read_u32(offset).into())
+            + if nanoseconds { Duration::from_nanos(subsecond) } else { Duration::from_micros(subsecond) };
+        let captured_length = read_u32(offset + 8) as usize;
+        let frame = bytes.get(offset + 16..offset + 16 + captured_length).ok_or_else(|| invalid("truncated frame"))?;
+        offset += 16 + captured_length;
+        let Some(segment) = parse_frame(link_type, frame) else {
+            continue;
+        };
+        let direction = if segment.flow.source_port == port {
+            PacketDirection::Received
+        } else if segment.flow.destination_port == port {
+            PacketDirection::Sent
+        } else {
+            continue;
+        };
+        let elapsed = timestamp.saturating_sub(*first_timestamp.get_or_insert(timestamp));
+        let stream = streams.entry(segment.flow.clone()).or_default();
+        for bytes in stream.push(&segment) {
+            packets.push(RecordedPacket { elapsed, direction, bytes });
+        }
+    }
+    Ok(Recording { packets })
+}
+
+/// Addresses and ports of one direction of a TCP connection.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+struct TcpFlow {
+    source: Vec<u8>,
+    destination: Vec<u8>,
+    source_port: u16,
+    destination_port: u16,
+}
+
+struct TcpSegment<'a> {
+    flow: TcpFlow,
+    sequence_number: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+#[derive(Default)]
+struct TcpStreamState {
+    next_sequence_number: Option<u32>,
+    buffer: Vec<u8>,
+}
+
+impl TcpStreamState {
+    /// Appends the new data of a segment and returns the complete packets.
+    fn push(&mut self, segment: &TcpSegment) -> Vec<Vec<u8>> {
+        let start = if segment.syn { segment.sequence_number.wrapping_add(1) } else { segment.sequence_number };
+        let end = start.wrapping_add(segment.payload.len() as u32);
+        let expected = *self.next_sequence_number.get_or_insert(start);
+        // positive if the segment starts with data seen before, negative after a gap
+        let seen = expected.wrapping_sub(start) as i32;
+        let payload = if seen < 0 {
+            warn!("Missing {} bytes in captured TCP stream, dropping incomplete packet", seen.unsigned_abs());
+            self.buffer.clear();
+            self.next_sequence_number = Some(end);
+            segment.payload
+        } else if (seen as usize) < segment.payload.len() {
+            self.next_sequence_number = Some(end);
+            &segment.payload[seen as usize..]
+        } else {
+            &[]
+        };
+        self.buffer.extend_from_slice(payload);
+
+        let mut packets = Vec::new();
+        while self.buffer.len() >= PacketHeader::SIZE {
+            let length = self.buffer[4] as usize;
+            if length < PacketHeader::SIZE {
+                warn!("Invalid packet length {length} in captured TCP stream, dropping buffered data");
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < length {
+                break;
+            }
+            packets.push(self.buffer.drain(..length).collect());
+        }
+        packets
+    }
+}
+
+fn parse_frame(link_type: u32, frame: &[u8]) -> Option<TcpSegment<'_>> {
+    match link_type {
+        LINKTYPE_NULL => parse_ip(frame.get(4..)?),
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut offset = 14;
+            if ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes([*frame.get(16)?, *frame.get(17)?]);
+                offset += 4;
+            }
+            matches!(ethertype, ETHERTYPE_IPV4 | ETHERTYPE_IPV6).then_some(())?;
+            parse_ip(frame.get(offset..)?)
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => parse_ip(frame),
+        LINKTYPE_LINUX_SLL => parse_ip(frame.get(16..)?),
+        LINKTYPE_LINUX_SLL2 => parse_ip(frame.get(20..)?),
+        _ => None,
+    }
+}
+
+fn parse_ip(packet: &[u8]) -> Option<TcpSegment<'_>> {
+    let (source, destination, segment) = match packet.first()? >> 4 {
+        4 => {
+            let header_length = usize::from(packet[0] & 0x0f) * 4;
+            let total_length = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            (*packet.get(9)? == IP_PROTOCOL_TCP).then_some(())?;
+            (packet.get(12..16)?, packet.get(16..20)?, packet.get(header_length..total_length.min(packet.len()))?)
+        }
+        6 => {
+            let payload_length = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            (*packet.get(6)? == IP_PROTOCOL_TCP).then_some(())?;
+            let end = (IPV6_HEADER_SIZE + payload_length).min(packet.len());
+            (packet.get(8..24)?, packet.get(24..40)?, packet.get(IPV6_HEADER_SIZE..end)?)
+        }
+        _ => return None,
+    };
+    let header_length = usize::from(segment.get(12)? >> 4) * 4;
+    Some(TcpSegment {
+        flow: TcpFlow {
+            source: source.to_vec(),
+            destination: destination.to_vec(),
+            source_port: u16::from_be_bytes([segment[0], segment[1]]),
+            destination_port: u16::from_be_bytes([segment[2], segment[3]]),
+        },
+        sequence_number: u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]),
+        syn: segment.get(13)? & TCP_FLAG_SYN != 0,
+        payload: segment.get(header_length..)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        pcap::{read_pcap, PcapWriter},
+        recording::Recording,
+    };
+
+    #[test]
+    fn test_write_and_read() {
+        let recording =
+            Recording::parse("0 sent 2a00000008012800\n1500 received 2a0000000a0128000809\n2000 received 2a0000000a0928000102").unwrap();
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for packet in &recording.packets {
+            writer.write_packet(packet).unwrap();
+        }
+        let read = read_pcap(writer.writer.as_slice()).unwrap();
+        assert_eq!(
+            read.packets.iter().map(|packet| &packet.bytes).collect::<Vec<_>>(),
+            recording.packets.iter().map(|packet| &packet.bytes).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            read.packets.iter().map(|packet| packet.direction).collect::<Vec<_>>(),
+            recording.packets.iter().map(|packet| packet.direction).collect::<Vec<_>>()
+        );
+        assert_eq!(read.packets[2].elapsed, Duration::from_micros(2000));
+    }
+}
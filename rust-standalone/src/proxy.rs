@@ -0,0 +1,487 @@
+//! Shares one Brick Daemon or Master Extension between many clients.
+//!
+//! The proxy accepts TFP clients and forwards their requests over a single upstream [`AsyncIpConnection`], with
+//! sequence numbers of its own, and answers each client with the sequence number it sent. Callbacks and enumerations
+//! are sent to all clients. The configuration is read from TOML or JSON:
+//! ```toml
+//! upstream = "master-extension.local:4223"
+//! # secret of the upstream daemon, if it requires authentication
+//! upstream_secret = "upstream"
+//!
+//! # without clients, everybody may connect and call every function
+//! [[clients]]
+//! name = "dashboard"
+//! secret = "dashboard"
+//! # functions given by name or as Device.function, a trailing * matches any suffix
+//! allow = ["get_*", "is_*"]
+//!
+//! [[clients]]
+//! name = "controller"
+//! secret = "controller"
+//! ```
+//! If clients are configured, a client has to authenticate with the secret of one of them and may only call the
+//! functions allowed for it, other calls are answered with [`FunctionNotSupported`](BrickletError::FunctionNotSupported).
+//! Enumerate and `get_identity` are always allowed. The upstream connection is reopened when it fails, which
+//! disconnects all clients.
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use sha1::Sha1;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{broadcast, mpsc, watch, Mutex, Semaphore},
+    task::AbortHandle,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::{Base58Error, Uid},
+    bindings::DeviceIdentifier,
+    byte_converter::{FromByteSlice, ToBytes},
+    converting_receiver::BrickletError,
+    device::DEFAULT_TIMEOUT,
+    error::TinkerforgeError,
+    ip_connection::{
+        async_io::{AsyncIpConnection, PacketData},
+        EnumerateResponse, PacketHeader,
+    },
+    metadata::PacketKind,
+    trace::RequestSpan,
+};
+
+/// Uid of the Brick Daemon itself, which answers the authentication functions.
+const BRICK_DAEMON_UID: u32 = 1;
+const FUNCTION_GET_AUTHENTICATION_NONCE: u8 = 1;
+const FUNCTION_AUTHENTICATE: u8 = 2;
+const FUNCTION_DISCONNECT_PROBE: u8 = 128;
+const CALLBACK_ENUMERATE: u8 = 253;
+const FUNCTION_ENUMERATE: u8 = 254;
+const FUNCTION_GET_IDENTITY: u8 = 255;
+const MAX_PACKET_SIZE: u8 = 80;
+const CALLBACK_CAPACITY: usize = 512;
+/// Requests waiting for their response upstream, one less than there are sequence numbers.
+const MAX_PENDING_REQUESTS: usize = 14;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Upstream daemon and clients of a [`Proxy`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Address of the Brick Daemon or Master Extension, e.g. `localhost:4223`.
+    pub upstream: String,
+    #[serde(default)]
+    pub upstream_secret: Option<String>,
+    /// Clients allowed to connect, everybody may connect if empty.
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+}
+
+/// A client identified by its secret.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub name: String,
+    pub secret: String,
+    /// Functions the client may call, all if empty.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub fn from_toml(toml: &str) -> Result<ProxyConfig, TinkerforgeError> {
+        toml::from_str(toml).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    pub fn from_json(json: &str) -> Result<ProxyConfig, TinkerforgeError> {
+        serde_json::from_str(json).map_err(|error| TinkerforgeError::InvalidConfig(error.to_string()))
+    }
+    /// Reads a configuration, files ending in `.json` are parsed as JSON, all others as TOML.
+    pub fn load(path: impl AsRef<Path>) -> Result<ProxyConfig, TinkerforgeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "json") {
+            ProxyConfig::from_json(&content)
+        } else {
+            ProxyConfig::from_toml(&content)
+        }
+    }
+}
+
+impl ClientConfig {
+    fn allows(&self, device: Option<DeviceIdentifier>, function_id: u8) -> bool {
+        if self.allow.is_empty() || matches!(function_id, FUNCTION_ENUMERATE | FUNCTION_GET_IDENTITY) {
+            return true;
+        }
+        let Some(device) = device else {
+            return false;
+        };
+        let Some(function) = device.metadata().packet(function_id).filter(|packet| packet.kind == PacketKind::Function) else {
+            return false;
+        };
+        let qualified = format!("{device:?}.{}", function.name);
+        self.allow.iter().any(|pattern| matches_pattern(pattern, function.name) || matches_pattern(pattern, &qualified))
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+struct Shared {
+    config: ProxyConfig,
+    upstream: watch::Sender<Option<AsyncIpConnection>>,
+    callbacks: broadcast::Sender<Vec<u8>>,
+    /// Devices of the uids seen in enumerations and identities, to check the allowed functions.
+    devices: Mutex<HashMap<Uid, DeviceIdentifier>>,
+    pending_requests: Semaphore,
+}
+
+impl Shared {
+    fn upstream(&self) -> Option<AsyncIpConnection> {
+        self.upstream.borrow().clone()
+    }
+
+    async fn device(&self, uid: Uid) -> Option<DeviceIdentifier> {
+        if let Some(device) = self.devices.lock().await.get(&uid) {
+            return Some(*device);
+        }
+        let response = self.forward(uid, FUNCTION_GET_IDENTITY, &[], true).await.ok().flatten()?;
+        let device = identity_device(response.body())?;
+        self.devices.lock().await.insert(uid, device);
+        Some(device)
+    }
+
+    async fn forward(
+        &self,
+        uid: Uid,
+        function_id: u8,
+        payload: &[u8],
+        response_expected: bool,
+    ) -> Result<Option<PacketData>, TinkerforgeError> {
+        let mut upstream = self.upstream().ok_or(TinkerforgeError::NoResponseReceived)?;
+        let _permit = self.pending_requests.acquire().await.map_err(|_| TinkerforgeError::NoResponseReceived)?;
+        let device = self.devices.lock().await.get(&uid).map(|device| device.metadata());
+        RequestSpan::for_device(uid, function_id, device)
+            .run(upstream.forward(uid, function_id, payload, response_expected, DEFAULT_TIMEOUT))
+            .await
+    }
+}
+
+/// Returns the device of an enumerate callback or `get_identity` response.
+fn identity_device(payload: &[u8]) -> Option<DeviceIdentifier> {
+    let bytes = payload.get(23..25)?;
+    u16::from_le_byte_slice(bytes).try_into().ok()
+}
+
+/// A proxy serving clients on a local address, stops when dropped.
+pub struct Proxy {
+    local_addr: SocketAddr,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Proxy {
+    /// Starts accepting clients and connects to the upstream daemon, use port 0 to let the system choose a free port.
+    pub async fn bind(address: impl ToSocketAddrs, config: ProxyConfig) -> Result<Proxy, TinkerforgeError> {
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            upstream: watch::channel(None).0,
+            callbacks: broadcast::channel(CALLBACK_CAPACITY).0,
+            devices: Mutex::new(HashMap::new()),
+            pending_requests: Semaphore::new(MAX_PENDING_REQUESTS),
+        });
+        let upstream_task = tokio::spawn(maintain_upstream(shared.clone())).abort_handle();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        info!("Client {peer} connected");
+                        tokio::spawn(serve_client(shared.clone(), stream, peer));
+                    }
+                    Err(error) => warn!("Cannot accept connection: {error}"),
+                }
+            }
+        })
+        .abort_handle();
+        Ok(Proxy { local_addr, tasks: vec![upstream_task, accept_task] })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Connects to the upstream daemon and passes its callbacks to the clients, reconnecting when the connection fails.
+async fn maintain_upstream(shared: Arc<Shared>) {
+    loop {
+        let mut connection = match AsyncIpConnection::new(shared.config.upstream.clone()).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Cannot connect to {}: {error}", shared.config.upstream);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        let packets = connection.received_packets().await;
+        if let Some(secret) = &shared.config.upstream_secret {
+            if let Err(error) = connection.authenticate(secret).await {
+                warn!("Cannot authenticate at {}: {error}", shared.config.upstream);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        info!("Connected to {}", shared.config.upstream);
+        shared.upstream.send_replace(Some(connection));
+        tokio::pin!(packets);
+        while let Some(packet) = packets.next().await {
+            let header = packet.header();
+            if header.sequence_number != 0 {
+                continue;
+            }
+            if header.function_id == CALLBACK_ENUMERATE {
+                if let Ok(enumeration) = Result::<EnumerateResponse, Base58Error>::from_le_byte_slice(packet.body()) {
+                    if let Some(device) = enumeration.device_identifier.parsed() {
+                        shared.devices.lock().await.insert(enumeration.uid, device);
+                    }
+                }
+            }
+            let _ = shared.callbacks.send(encode_packet(header, packet.body()));
+        }
+        warn!("Lost connection to {}", shared.config.upstream);
+        shared.upstream.send_replace(None);
+        shared.devices.lock().await.clear();
+    }
+}
+
+fn encode_packet(header: PacketHeader, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0; PacketHeader::SIZE + payload.len()];
+    PacketHeader { length: packet.len() as u8, ..header }.write_to_slice(&mut packet[0..PacketHeader::SIZE]);
+    packet[PacketHeader::SIZE..].copy_from_slice(payload);
+    packet
+}
+
+struct Client {
+    shared: Arc<Shared>,
+    /// Index of the client configuration the client authenticated with.
+    config: Option<usize>,
+    authenticated: Arc<AtomicBool>,
+    server_nonce: Option<[u8; 4]>,
+    responses: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+async fn serve_client(shared: Arc<Shared>, stream: TcpStream, peer: SocketAddr) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (responses, mut outgoing) = mpsc::unbounded_channel::<Vec<u8>>();
+    let authenticated = Arc::new(AtomicBool::new(shared.config.clients.is_empty()));
+    let writer_task = tokio::spawn(async move {
+        while let Some(packet) = outgoing.recv().await {
+            if writer.write_all(&packet).await.is_err() {
+                break;
+            }
+        }
+    });
+    let mut callbacks = shared.callbacks.subscribe();
+    let callback_responses = responses.clone();
+    let callback_authenticated = authenticated.clone();
+    let callback_task = tokio::spawn(async move {
+        loop {
+            match callbacks.recv().await {
+                Ok(callback) if callback_authenticated.load(Ordering::Relaxed) => {
+                    if callback_responses.send(callback).is_err() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(count)) => warn!("Client {peer} skipped {count} callbacks"),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+    let mut upstream = shared.upstream.subscribe();
+    upstream.mark_unchanged();
+    let mut client = Client { shared, config: None, authenticated, server_nonce: None, responses };
+    tokio::select! {
+        _ = client.serve(&mut reader) => {}
+        // clients reconnect and enumerate again once the upstream connection is back
+        _ = upstream.changed() => {}
+    }
+    info!("Client {peer} disconnected");
+    callback_task.abort();
+    writer_task.abort();
+}
+
+impl Client {
+    /// Handles requests until the client disconnects or the connection has to be closed.
+    async fn serve(&mut self, reader: &mut OwnedReadHalf) {
+        loop {
+            let mut header_bytes = [0; PacketHeader::SIZE];
+            if reader.read_exact(&mut header_bytes).await.is_err() {
+                return;
+            }
+            let header = PacketHeader::from_le_byte_slice(&header_bytes);
+            if header.length < PacketHeader::SIZE as u8 || header.length > MAX_PACKET_SIZE || header.sequence_number == 0 {
+                warn!("Invalid request {header:?}, closing connection");
+                return;
+            }
+            let mut payload = vec![0; header.length as usize - PacketHeader::SIZE];
+            if reader.read_exact(&mut payload).await.is_err() {
+                return;
+            }
+            debug!("Received {header:?}: {payload:?}");
+            if !self.handle(header, payload).await {
+                return;
+            }
+        }
+    }
+
+    /// Returns false if the connection has to be closed.
+    async fn handle(&mut self, header: PacketHeader, payload: Vec<u8>) -> bool {
+        let uid = u32::from(header.uid);
+        // the bindings request the nonce with the broadcast uid, so both are accepted
+        if uid == BRICK_DAEMON_UID || (uid == 0 && matches!(header.function_id, FUNCTION_GET_AUTHENTICATION_NONCE | FUNCTION_AUTHENTICATE))
+        {
+            return self.handle_brick_daemon(header, &payload);
+        }
+        if !self.authenticated.load(Ordering::Relaxed) {
+            debug!("Dropping request of unauthenticated client");
+            return true;
+        }
+        if uid == 0 && header.function_id == FUNCTION_DISCONNECT_PROBE {
+            return true;
+        }
+        if let Some(config) = self.config.map(|index| &self.shared.config.clients[index]) {
+            let device = if uid == 0 { None } else { self.shared.device(header.uid).await };
+            if !config.allows(device, header.function_id) {
+                info!("Denied function {} of {} for client {}", header.function_id, header.uid, config.name);
+                if header.response_expected {
+                    self.respond(header, Err(BrickletError::FunctionNotSupported));
+                }
+                return true;
+            }
+        }
+        let shared = self.shared.clone();
+        let responses = self.responses.clone();
+        tokio::spawn(async move {
+            match shared.forward(header.uid, header.function_id, &payload, header.response_expected).await {
+                Ok(Some(response)) => {
+                    let response_header = response.header();
+                    if response_header.function_id == FUNCTION_GET_IDENTITY && response_header.error_code == 0 {
+                        if let Some(device) = identity_device(response.body()) {
+                            shared.devices.lock().await.insert(header.uid, device);
+                        }
+                    }
+                    let header = PacketHeader { sequence_number: header.sequence_number, ..response_header };
+                    let _ = responses.send(encode_packet(header, response.body()));
+                }
+                Ok(None) => {}
+                Err(error) => debug!("No response for {header:?}: {error}"),
+            }
+        });
+        true
+    }
+
+    fn handle_brick_daemon(&mut self, header: PacketHeader, payload: &[u8]) -> bool {
+        let result = match header.function_id {
+            FUNCTION_GET_AUTHENTICATION_NONCE => {
+                let nonce = rand::random::<[u8; 4]>();
+                self.server_nonce = Some(nonce);
+                Ok(nonce.to_vec())
+            }
+            FUNCTION_AUTHENTICATE => {
+                if !self.authenticate(payload) {
+                    warn!("Authentication failed, closing connection");
+                    return false;
+                }
+                Ok(Vec::new())
+            }
+            _ => Err(BrickletError::FunctionNotSupported),
+        };
+        if header.response_expected || header.function_id == FUNCTION_GET_AUTHENTICATION_NONCE {
+            self.respond(header, result);
+        }
+        true
+    }
+
+    /// Looks for the client whose secret matches the HMAC-SHA1 of server and client nonce.
+    fn authenticate(&mut self, payload: &[u8]) -> bool {
+        if self.shared.config.clients.is_empty() {
+            return true;
+        }
+        let (Some(server_nonce), Some(client_nonce), Some(digest)) = (self.server_nonce.take(), payload.get(0..4), payload.get(4..24))
+        else {
+            return false;
+        };
+        self.config = self.shared.config.clients.iter().position(|client| {
+            let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(client.secret.as_bytes()) else {
+                return false;
+            };
+            mac.update(&server_nonce);
+            mac.update(client_nonce);
+            mac.verify_slice(digest).is_ok()
+        });
+        if let Some(index) = self.config {
+            info!("Client {} authenticated", self.shared.config.clients[index].name);
+        }
+        self.authenticated.store(self.config.is_some(), Ordering::Relaxed);
+        self.config.is_some()
+    }
+
+    fn respond(&self, mut header: PacketHeader, result: Result<Vec<u8>, BrickletError>) {
+        let payload = match result {
+            Ok(payload) => payload,
+            Err(error) => {
+                header.error_code = match error {
+                    BrickletError::InvalidParameter => 1,
+                    BrickletError::FunctionNotSupported => 2,
+                    _ => 3,
+                };
+                Vec::new()
+            }
+        };
+        let _ = self.responses.send(encode_packet(header, &payload));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{proxy::ClientConfig, DeviceIdentifier};
+
+    #[test]
+    fn test_allowed_functions() {
+        let client = ClientConfig {
+            name: "dashboard".to_string(),
+            secret: "secret".to_string(),
+            allow: vec!["get_*".to_string(), "TemperatureV2Bricklet.set_status_led_config".to_string()],
+        };
+        let device = Some(DeviceIdentifier::TemperatureV2Bricklet);
+        let function = |name| DeviceIdentifier::TemperatureV2Bricklet.metadata().function(name).unwrap().function_id;
+        assert!(client.allows(device, function("get_temperature")));
+        assert!(client.allows(device, function("set_status_led_config")));
+        assert!(!client.allows(device, function("set_temperature_callback_configuration")));
+        assert!(!client.allows(None, function("get_temperature")));
+        assert!(client.allows(None, 255));
+    }
+}
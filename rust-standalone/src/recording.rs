@@ -0,0 +1,363 @@
+//! Records the traffic of a connection and replays recordings, to reproduce problems offline without the hardware.
+//!
+//! [`AsyncIpConnection::record_to_file`] writes every packet sent and received from then on, one line per packet with
+//! the time since the start of the recording, the direction and the packet as hex:
+//! ```text
+//! 0 sent 2a00000008012800
+//! 1500 received 2a0000000a0128000809
+//! ```
+//! [`AsyncIpConnection::capture_to_pcap_file`] writes the packets as [`pcap`](crate::pcap) file instead, and
+//! [`Recording::load`] reads both formats.
+//!
+//! [`replay`] creates a connection answered by a recording. Requests have to match the recorded requests in order,
+//! except for their sequence number, and received packets are fed back with their original or accelerated timing:
+//! ```
+//! use tinkerforge_async::{
+//!     base58::Uid,
+//!     recording::{replay, Recording, ReplayTiming},
+//!     temperature_v_2::TemperatureV2Bricklet,
+//! };
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let recording = Recording::parse("0 sent 2a00000008012800\n1500 received 2a0000000a0128000809").unwrap();
+//! let (connection, replay) = replay(recording, ReplayTiming::Accelerated(10.0));
+//! let mut bricklet = TemperatureV2Bricklet::new(Uid::from(42), connection);
+//! assert_eq!(bricklet.get_temperature().await.unwrap(), 2312);
+//! replay.completed().await.unwrap();
+//! # });
+//! ```
+use std::{
+    fmt::{Display, Formatter},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    sync::oneshot,
+};
+
+use crate::{
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, PacketHeader},
+    metrics::Metrics,
+    pcap::{is_pcap, read_pcap, PcapWriter},
+};
+
+const REPLAY_BUFFER_SIZE: usize = 4096;
+
+/// Direction of a recorded packet, seen from the recording connection.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+impl Display for PacketDirection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PacketDirection::Sent => "sent",
+            PacketDirection::Received => "received",
+        })
+    }
+}
+
+impl FromStr for PacketDirection {
+    type Err = TinkerforgeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sent" => Ok(PacketDirection::Sent),
+            "received" => Ok(PacketDirection::Received),
+            other => Err(TinkerforgeError::InvalidRecording(format!("Unknown direction {other}"))),
+        }
+    }
+}
+
+/// A packet of a recording.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecordedPacket {
+    /// Time since the start of the recording.
+    pub elapsed: Duration,
+    pub direction: PacketDirection,
+    /// The whole packet, header and payload.
+    pub bytes: Vec<u8>,
+}
+
+impl Display for RecordedPacket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ", self.elapsed.as_micros(), self.direction)?;
+        for byte in &self.bytes {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RecordedPacket {
+    type Err = TinkerforgeError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || TinkerforgeError::InvalidRecording(format!("Invalid line {line:?}"));
+        let mut parts = line.split_whitespace();
+        let (Some(elapsed), Some(direction), Some(hex), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let elapsed = Duration::from_micros(elapsed.parse().map_err(|_| invalid())?);
+        if hex.len() % 2 != 0 || hex.len() < PacketHeader::SIZE * 2 {
+            return Err(invalid());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|index| hex.get(index..index + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+        Ok(RecordedPacket { elapsed, direction: direction.parse()?, bytes })
+    }
+}
+
+/// Packets of a connection in the order they were sent and received.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Recording {
+    pub packets: Vec<RecordedPacket>,
+}
+
+impl Recording {
+    /// Parses a recording, empty lines and lines starting with `#` are skipped.
+    pub fn parse(recording: &str) -> Result<Recording, TinkerforgeError> {
+        let packets = recording
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Recording { packets })
+    }
+    /// Loads a recording, or the packets of a pcap file as read by [`read_pcap`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Recording, TinkerforgeError> {
+        let bytes = fs::read(path)?;
+        if is_pcap(&bytes) {
+            read_pcap(bytes.as_slice())
+        } else {
+            Self::parse(&String::from_utf8_lossy(&bytes))
+        }
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TinkerforgeError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for packet in &self.packets {
+            writeln!(writer, "{packet}")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+    /// Writes the recording as pcap file, see [`pcap`](crate::pcap).
+    pub fn save_pcap(&self, path: impl AsRef<Path>) -> Result<(), TinkerforgeError> {
+        let mut writer = PcapWriter::new(BufWriter::new(File::create(path)?))?;
+        for packet in &self.packets {
+            writer.write_packet(packet)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the packets of a connection while a recording is running.
+pub(crate) struct Recorder {
+    start: Instant,
+    output: RecorderOutput,
+}
+
+enum RecorderOutput {
+    Text(Box<dyn Write + Send>),
+    Pcap(PcapWriter<Box<dyn Write + Send>>),
+}
+
+pub(crate) type SharedRecorder = Arc<Mutex<Option<Recorder>>>;
+
+impl Recorder {
+    pub(crate) fn new(writer: impl Write + Send + 'static) -> Recorder {
+        Recorder { start: Instant::now(), output: RecorderOutput::Text(Box::new(writer)) }
+    }
+
+    pub(crate) fn pcap(writer: impl Write + Send + 'static) -> std::io::Result<Recorder> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Ok(Recorder { start: Instant::now(), output: RecorderOutput::Pcap(PcapWriter::new(writer)?) })
+    }
+
+    fn write(&mut self, packet: &RecordedPacket) -> std::io::Result<()> {
+        match &mut self.output {
+            RecorderOutput::Text(writer) => writeln!(writer, "{packet}"),
+            RecorderOutput::Pcap(writer) => writer.write_packet(packet),
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.output {
+            RecorderOutput::Text(writer) => writer.flush(),
+            RecorderOutput::Pcap(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Records a packet if a recording is running, a failing recording is stopped.
+pub(crate) fn record(recorder: &SharedRecorder, direction: PacketDirection, header: &[u8], payload: &[u8]) {
+    let mut recorder = recorder.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(running) = recorder.as_mut() else {
+        return;
+    };
+    let packet = RecordedPacket { elapsed: running.start.elapsed(), direction, bytes: [header, payload].concat() };
+    if let Err(error) = running.write(&packet) {
+        warn!("Cannot write recording, stopping it: {error}");
+        *recorder = None;
+    }
+}
+
+/// Pace of a replay.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReplayTiming {
+    /// Received packets are delayed like they were recorded.
+    Original,
+    /// Delays are divided by the factor.
+    Accelerated(f64),
+    /// Received packets are sent as soon as the preceding requests arrived.
+    Immediate,
+}
+
+impl ReplayTiming {
+    fn scale(self, delay: Duration) -> Duration {
+        match self {
+            ReplayTiming::Original => delay,
+            ReplayTiming::Accelerated(factor) if factor > 0.0 => delay.div_f64(factor),
+            ReplayTiming::Accelerated(_) | ReplayTiming::Immediate => Duration::ZERO,
+        }
+    }
+}
+
+/// Progress of a running replay.
+#[derive(Debug)]
+pub struct Replay {
+    result: oneshot::Receiver<Result<(), TinkerforgeError>>,
+}
+
+impl Replay {
+    /// Waits until all recorded packets were replayed, fails on the first request which does not match the recording.
+    pub async fn completed(self) -> Result<(), TinkerforgeError> {
+        self.result.await.unwrap_or_else(|_| Err(TinkerforgeError::ReplayMismatch("Replay was aborted".to_string())))
+    }
+}
+
+/// Creates a connection which is answered by a recording.
+///
+/// The connection is closed after the first request which does not match the recording, otherwise it stays open
+/// after the last recorded packet, ignoring further requests.
+pub fn replay(recording: Recording, timing: ReplayTiming) -> (AsyncIpConnection, Replay) {
+    let (client, server) = tokio::io::duplex(REPLAY_BUFFER_SIZE);
+    let (sender, result) = oneshot::channel();
+    tokio::spawn(async move {
+        let mut server = server;
+        let replayed = replay_packets(&mut server, recording, timing).await;
+        let failed = replayed.is_err();
+        let _ = sender.send(replayed);
+        if failed {
+            return;
+        }
+        while let Ok(request) = read_packet(&mut server).await {
+            warn!("Ignoring request after the end of the replay: {request:02x?}");
+        }
+    });
+    (AsyncIpConnection::from_stream(client, "replay".to_string(), Metrics::default()), Replay { result })
+}
+
+async fn replay_packets(stream: &mut DuplexStream, recording: Recording, timing: ReplayTiming) -> Result<(), TinkerforgeError> {
+    // responses carry the sequence number of their request, which differs from the recorded one if the client sent
+    // the requests in a different state
+    let mut sequence_numbers = [None::<u8>; 16];
+    let mut last_elapsed = Duration::ZERO;
+    for (index, packet) in recording.packets.into_iter().enumerate() {
+        let delay = packet.elapsed.saturating_sub(last_elapsed);
+        last_elapsed = packet.elapsed;
+        match packet.direction {
+            PacketDirection::Sent => {
+                let request = read_packet(stream).await.map_err(|_| {
+                    TinkerforgeError::ReplayMismatch(format!("Connection closed before packet {index}: {:02x?}", packet.bytes))
+                })?;
+                if !matches_ignoring_sequence_number(&packet.bytes, &request) {
+                    return Err(TinkerforgeError::ReplayMismatch(format!(
+                        "Request {request:02x?} does not match packet {index}: {:02x?}",
+                        packet.bytes
+                    )));
+                }
+                sequence_numbers[sequence_number(&packet.bytes) as usize] = Some(sequence_number(&request));
+            }
+            PacketDirection::Received => {
+                let delay = timing.scale(delay);
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                let mut bytes = packet.bytes;
+                let recorded_sequence_number = sequence_number(&bytes);
+                if recorded_sequence_number != 0 {
+                    if let Some(actual) = sequence_numbers[recorded_sequence_number as usize] {
+                        bytes[6] = (bytes[6] & 0x0f) | actual << 4;
+                    }
+                }
+                stream.write_all(&bytes).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn read_packet(stream: &mut DuplexStream) -> std::io::Result<Vec<u8>> {
+    let mut packet = vec![0; PacketHeader::SIZE];
+    stream.read_exact(&mut packet).await?;
+    let length = (packet[4] as usize).max(PacketHeader::SIZE);
+    packet.resize(length, 0);
+    stream.read_exact(&mut packet[PacketHeader::SIZE..]).await?;
+    Ok(packet)
+}
+
+fn sequence_number(packet: &[u8]) -> u8 {
+    packet[6] >> 4
+}
+
+fn matches_ignoring_sequence_number(recorded: &[u8], actual: &[u8]) -> bool {
+    recorded.len() == actual.len()
+        && recorded.iter().zip(actual).enumerate().all(
+            |(index, (recorded, actual))| {
+                if index == 6 {
+                    recorded & 0x0f == actual & 0x0f
+                } else {
+                    recorded == actual
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::recording::{PacketDirection, RecordedPacket, Recording};
+
+    #[test]
+    fn test_parse_and_format() {
+        let recording = Recording::parse("# capture\n0 sent 2a00000008012800\n\n1500 received 2a0000000a0128000809\n").unwrap();
+        assert_eq!(
+            recording.packets[1],
+            RecordedPacket {
+                elapsed: Duration::from_micros(1500),
+                direction: PacketDirection::Received,
+                bytes: vec![0x2a, 0, 0, 0, 0x0a, 0x01, 0x28, 0, 0x08, 0x09]
+            }
+        );
+        assert_eq!(recording.packets[0].to_string(), "0 sent 2a00000008012800");
+        assert!(Recording::parse("0 sent 2a000000080128").is_err());
+        assert!(Recording::parse("0 lost 2a00000008012800").is_err());
+    }
+}
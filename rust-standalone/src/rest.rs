@@ -0,0 +1,498 @@
+//! Serves the devices of a Brick Daemon over HTTP with JSON, e.g. for web dashboards.
+//!
+//! * `GET /devices` lists the enumerations of all devices, the topology follows from `connected_uid` and `position`.
+//!   `GET /devices/<uid>` returns a single device.
+//! * `POST /devices/<uid>/<function>` calls the function with the entries of the JSON object in the body as
+//!   arguments and responds with the outputs, e.g. `{"temperature": 2312}` for `get_temperature`. An empty body is an
+//!   empty object. Functions, elements and constants are named in snake case like in [`dynamic`](crate::dynamic).
+//! * `GET /devices/<uid>/callbacks/<callback>` streams the callback as Server-Sent Events, one JSON object per event.
+//!   The stream ends when the device is removed or the connection to the Brick Daemon is lost.
+//!
+//! ```text
+//! $ curl -X POST http://localhost:8080/devices/ZQH/get_temperature
+//! {"temperature":2312}
+//! $ curl http://localhost:8080/devices/ZQH/callbacks/temperature
+//! data: {"temperature":2312}
+//! ```
+//! With a token configured, requests have to send it as `Authorization: Bearer <token>`, or as `?token=<token>` for
+//! browsers' `EventSource`, which cannot set headers. Failures are reported as `{"error": "..."}` with a status telling
+//! the cause apart: 400 for invalid arguments, 401 for a missing token, 404 for unknown devices and functions, 502 for
+//! error codes of the device, 503 while the Brick Daemon is not connected and 504 for timeouts.
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use serde_json::{json, Map, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+    task::AbortHandle,
+};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    converting_receiver::BrickletError,
+    dynamic::{DeviceDefinition, DynamicDevice},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerateResponse, EnumerationType},
+    metadata::{DeviceMetadata, PacketKind},
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const MAX_REQUEST_SIZE: usize = 65536;
+/// Interval of comments sent on idle event streams, to notice closed clients.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Brick Daemon and access control of a [`RestGateway`].
+#[derive(Clone, Debug)]
+pub struct RestConfig {
+    pub brick_daemon: String,
+    pub secret: Option<String>,
+    /// Token clients have to send, no authentication if missing.
+    pub token: Option<String>,
+    /// Value of `Access-Control-Allow-Origin` for dashboards served from another origin, e.g. `*`.
+    pub allow_origin: Option<String>,
+}
+
+impl Default for RestConfig {
+    fn default() -> Self {
+        RestConfig { brick_daemon: "localhost:4223".to_string(), secret: None, token: None, allow_origin: None }
+    }
+}
+
+/// An HTTP server for the devices of a Brick Daemon, stops when dropped.
+pub struct RestGateway {
+    local_addr: SocketAddr,
+    tasks: Vec<AbortHandle>,
+}
+
+impl RestGateway {
+    /// Starts serving requests and connects to the Brick Daemon, use port 0 to let the system choose a free port.
+    pub async fn bind(address: impl ToSocketAddrs, config: RestConfig) -> Result<RestGateway, TinkerforgeError> {
+        let listener = TcpListener::bind(address).await?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            config,
+            connection: watch::channel(None).0,
+            devices: Mutex::new(BTreeMap::new()),
+            definitions: Mutex::new(BTreeMap::new()),
+        });
+        let connection_task = tokio::spawn(maintain_connection(shared.clone())).abort_handle();
+        let accept_task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        tokio::spawn(serve(shared.clone(), stream));
+                    }
+                    Err(error) => warn!("Cannot accept connection: {error}"),
+                }
+            }
+        })
+        .abort_handle();
+        Ok(RestGateway { local_addr, tasks: vec![connection_task, accept_task] })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RestGateway {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+struct Shared {
+    config: RestConfig,
+    connection: watch::Sender<Option<AsyncIpConnection>>,
+    /// Latest enumeration of every connected device.
+    devices: Mutex<BTreeMap<Uid, EnumerateResponse>>,
+    definitions: Mutex<BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Shared {
+    fn definition(&self, device: &DeviceMetadata) -> Arc<DeviceDefinition> {
+        lock(&self.definitions).entry(device.identifier).or_insert_with(|| Arc::new(DeviceDefinition::from(device))).clone()
+    }
+
+    /// The connected device with the given uid, as text from the path.
+    fn device(&self, uid: &str) -> Result<DynamicDevice, Response> {
+        let connection = self.connection.borrow().clone().ok_or_else(|| Response::error("503 Service Unavailable", "Not connected"))?;
+        let unknown = || Response::error("404 Not Found", format!("Unknown device {uid}"));
+        let uid = uid.parse::<Uid>().map_err(|_| unknown())?;
+        let device = lock(&self.devices).get(&uid).and_then(|enumeration| enumeration.device_identifier.parsed()).ok_or_else(unknown)?;
+        Ok(DynamicDevice::new(uid, connection, self.definition(device.metadata())))
+    }
+}
+
+/// Connects to the Brick Daemon and keeps track of its devices, reconnecting when the connection fails.
+async fn maintain_connection(shared: Arc<Shared>) {
+    let address = &shared.config.brick_daemon;
+    loop {
+        let mut connection = match AsyncIpConnection::new(address.clone()).await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Cannot connect to {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if let Some(secret) = &shared.config.secret {
+            if let Err(error) = connection.authenticate(secret).await {
+                warn!("Cannot authenticate at {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        }
+        let enumerations = match connection.enumerate().await {
+            Ok(enumerations) => enumerations,
+            Err(error) => {
+                warn!("Cannot enumerate devices of {address}: {error}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        info!("Connected to {address}");
+        shared.connection.send_replace(Some(connection));
+        tokio::pin!(enumerations);
+        while let Some(enumeration) = enumerations.next().await {
+            let mut devices = lock(&shared.devices);
+            if enumeration.enumeration_type == EnumerationType::Disconnected {
+                devices.remove(&enumeration.uid);
+            } else {
+                devices.insert(enumeration.uid, enumeration);
+            }
+        }
+        warn!("Lost connection to {address}");
+        shared.connection.send_replace(None);
+        lock(&shared.devices).clear();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+fn device_json(enumeration: &EnumerateResponse) -> Value {
+    let uid_text = |uid: Uid| if u32::from(uid) == 0 { "0".to_string() } else { uid.to_string() };
+    let version = |version: crate::ip_connection::Version| json!([version.major(), version.minor(), version.patch()]);
+    let mut device = json!({
+        "uid": uid_text(enumeration.uid),
+        "connected_uid": uid_text(enumeration.connected_uid),
+        "position": enumeration.position.to_string(),
+        "hardware_version": version(enumeration.hardware_version),
+        "firmware_version": version(enumeration.firmware_version),
+        "device_identifier": enumeration.device_identifier.raw(),
+    });
+    if let Some(metadata) = enumeration.device_identifier.parsed().map(DeviceIdentifier::metadata) {
+        device["device_type"] = Value::from(format!("{}_{}", metadata.name, metadata.category).to_lowercase().replace([' ', '-'], "_"));
+        device["display_name"] = Value::from(metadata.display_name);
+    }
+    device
+}
+
+/// A complete response with a JSON body.
+struct Response {
+    status: &'static str,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Response { status: "200 OK", body }
+    }
+    fn error(status: &'static str, message: impl Into<String>) -> Self {
+        Response { status, body: json!({ "error": message.into() }) }
+    }
+}
+
+impl From<TinkerforgeError> for Response {
+    fn from(error: TinkerforgeError) -> Self {
+        let status = match &error {
+            TinkerforgeError::InvalidCall(_) | TinkerforgeError::BrickletError(BrickletError::InvalidParameter) => "400 Bad Request",
+            TinkerforgeError::BrickletError(_) => "502 Bad Gateway",
+            TinkerforgeError::NoResponseReceived => "504 Gateway Timeout",
+            _ => "500 Internal Server Error",
+        };
+        Response::error(status, error.to_string())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+    fn is_authorized(&self, token: &str) -> bool {
+        let bearer = self.header("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        let query = self.query.split('&').find_map(|parameter| parameter.strip_prefix("token="));
+        [bearer, query].into_iter().flatten().any(|candidate| constant_time_eq(candidate.trim().as_bytes(), token.as_bytes()))
+    }
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |difference, (l, r)| difference | (l ^ r)) == 0
+}
+
+/// Reads a request with its body, `None` if the client closed the connection or sent garbage.
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    let header_end = loop {
+        if let Some(position) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        if data.len() > MAX_REQUEST_SIZE {
+            return None;
+        }
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return None,
+            Ok(count) => data.extend_from_slice(&buffer[..count]),
+        }
+    };
+    let head = String::from_utf8_lossy(&data[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split(' ');
+    let (method, target) = (request_line.next()?.to_string(), request_line.next()?);
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect::<Vec<_>>();
+    let mut request = Request { method, path: path.to_string(), query: query.to_string(), headers, body: Vec::new() };
+    let length = request.header("Content-Length").map_or(Some(0), |length| length.parse::<usize>().ok())?;
+    if header_end + length > MAX_REQUEST_SIZE {
+        return None;
+    }
+    let mut body = data.split_off(header_end);
+    while body.len() < length {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return None,
+            Ok(count) => body.extend_from_slice(&buffer[..count]),
+        }
+    }
+    body.truncate(length);
+    request.body = body;
+    Some(request)
+}
+
+/// Answers a single HTTP request.
+async fn serve(shared: Arc<Shared>, mut stream: TcpStream) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    debug!("{} {}", request.method, request.path);
+    let segments = request.path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let response = match (request.method.as_str(), segments.as_slice()) {
+        ("OPTIONS", _) => {
+            let header = "HTTP/1.1 204 No Content\r\nAccess-Control-Allow-Methods: GET, POST, OPTIONS\r\n\
+                Access-Control-Allow-Headers: Authorization, Content-Type\r\nConnection: close\r\n";
+            let _ = stream.write_all(format!("{header}{}\r\n", cors_header(&shared)).as_bytes()).await;
+            return;
+        }
+        _ if shared.config.token.as_deref().is_some_and(|token| !request.is_authorized(token)) => {
+            Response::error("401 Unauthorized", "Missing or wrong token")
+        }
+        ("GET", ["devices"]) if shared.connection.borrow().is_none() => Response::error("503 Service Unavailable", "Not connected"),
+        ("GET", ["devices"]) => Response::ok(lock(&shared.devices).values().map(device_json).collect()),
+        ("GET", ["devices", uid]) => match uid.parse::<Uid>().ok().and_then(|uid| lock(&shared.devices).get(&uid).map(device_json)) {
+            Some(device) => Response::ok(device),
+            None => Response::error("404 Not Found", format!("Unknown device {uid}")),
+        },
+        ("POST", ["devices", uid, function]) => call(&shared, uid, function, &request.body).await,
+        ("GET", ["devices", uid, "callbacks", callback]) => match callback_stream(&shared, uid, callback).await {
+            Ok(callbacks) => {
+                stream_events(&shared, stream, callbacks).await;
+                return;
+            }
+            Err(response) => response,
+        },
+        (_, ["devices", ..]) => Response::error("405 Method Not Allowed", format!("{} is not supported here", request.method)),
+        _ => Response::error("404 Not Found", "Devices are served at /devices"),
+    };
+    let body = response.body.to_string();
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+        response.status,
+        body.len(),
+        cors_header(&shared)
+    );
+    if stream.write_all(header.as_bytes()).await.is_ok() {
+        let _ = stream.write_all(body.as_bytes()).await;
+    }
+}
+
+fn cors_header(shared: &Shared) -> String {
+    shared.config.allow_origin.as_ref().map(|origin| format!("Access-Control-Allow-Origin: {origin}\r\n")).unwrap_or_default()
+}
+
+async fn call(shared: &Shared, uid: &str, function: &str, body: &[u8]) -> Response {
+    let mut device = match shared.device(uid) {
+        Ok(device) => device,
+        Err(response) => return response,
+    };
+    if device.definition().packet(PacketKind::Function, function).is_none() {
+        return Response::error("404 Not Found", format!("{} has no function {function}", device.definition().name));
+    }
+    let arguments = if body.iter().all(u8::is_ascii_whitespace) {
+        Value::Object(Map::new())
+    } else {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(arguments @ Value::Object(_)) => arguments,
+            Ok(_) => return Response::error("400 Bad Request", "The arguments have to be a JSON object"),
+            Err(error) => return Response::error("400 Bad Request", format!("Invalid JSON: {error}")),
+        }
+    };
+    match device.call(function, arguments).await {
+        Ok(response) => Response::ok(response),
+        Err(error) => error.into(),
+    }
+}
+
+async fn callback_stream(shared: &Shared, uid: &str, callback: &str) -> Result<impl tokio_stream::Stream<Item = Value>, Response> {
+    let mut device = shared.device(uid)?;
+    if device.definition().packet(PacketKind::Callback, callback).is_none() {
+        return Err(Response::error("404 Not Found", format!("{} has no callback {callback}", device.definition().name)));
+    }
+    device.callback_stream(callback).await.map_err(Response::from)
+}
+
+/// Sends the callbacks as Server-Sent Events until the stream ends or the client goes away.
+async fn stream_events(shared: &Shared, mut stream: TcpStream, callbacks: impl tokio_stream::Stream<Item = Value>) {
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n{}\r\n",
+        cors_header(shared)
+    );
+    if stream.write_all(header.as_bytes()).await.is_err() {
+        return;
+    }
+    tokio::pin!(callbacks);
+    loop {
+        let event = match tokio::time::timeout(KEEP_ALIVE_INTERVAL, callbacks.next()).await {
+            Ok(Some(value)) => format!("data: {value}\n\n"),
+            Ok(None) => return,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        if stream.write_all(event.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rest::constant_time_eq;
+
+    #[test]
+    fn test_token() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
+
+#[cfg(all(test, feature = "emulator"))]
+mod emulator_test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+    };
+
+    use crate::{
+        emulator::{Emulator, Scenario},
+        rest::{RestConfig, RestGateway},
+    };
+
+    async fn request(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[test]
+    fn test_gateway() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let scenario = Scenario::from_toml(
+                r#"
+                [[devices]]
+                uid = "ZQH"
+                device = "TemperatureV2Bricklet"
+                functions = [{ function = "get_temperature", responses = [[2312]] }]
+                callbacks = [{ callback = "temperature", period_ms = 10, values = [[2400]] }]
+                "#,
+            )
+            .unwrap();
+            let emulator = Emulator::bind("127.0.0.1:0", scenario).await.unwrap();
+            let config =
+                RestConfig { brick_daemon: emulator.local_addr().to_string(), token: Some("token".to_string()), ..RestConfig::default() };
+            let gateway = RestGateway::bind("127.0.0.1:0", config).await.unwrap();
+            let address = gateway.local_addr();
+
+            let unauthorized = request(address, "GET /devices HTTP/1.1\r\n\r\n").await;
+            assert!(unauthorized.starts_with("HTTP/1.1 401"), "{unauthorized}");
+            let mut devices = String::new();
+            for _ in 0..50 {
+                devices = request(address, "GET /devices HTTP/1.1\r\nAuthorization: Bearer token\r\n\r\n").await;
+                if devices.contains("ZQH") {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            assert!(devices.contains(r#""device_type":"temperature_v2_bricklet""#), "{devices}");
+
+            let temperature =
+                request(address, "POST /devices/ZQH/get_temperature?token=token HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}").await;
+            assert!(temperature.starts_with("HTTP/1.1 200 OK"), "{temperature}");
+            assert!(temperature.ends_with(r#"{"temperature":2312}"#), "{temperature}");
+            let unknown = request(address, "POST /devices/ZQH/get_humidity?token=token HTTP/1.1\r\n\r\n").await;
+            assert!(unknown.starts_with("HTTP/1.1 404"), "{unknown}");
+            let invalid = request(
+                address,
+                "POST /devices/ZQH/set_temperature_callback_configuration?token=token HTTP/1.1\r\nContent-Length: 2\r\n\r\n[]",
+            )
+            .await;
+            assert!(invalid.starts_with("HTTP/1.1 400"), "{invalid}");
+
+            let mut events = TcpStream::connect(address).await.unwrap();
+            events.write_all(b"GET /devices/ZQH/callbacks/temperature?token=token HTTP/1.1\r\n\r\n").await.unwrap();
+            let mut events = BufReader::new(events).lines();
+            let mut lines = Vec::new();
+            while let Some(line) = events.next_line().await.unwrap() {
+                let done = line.starts_with("data:");
+                lines.push(line);
+                if done {
+                    break;
+                }
+            }
+            assert_eq!("HTTP/1.1 200 OK", lines[0]);
+            assert!(lines.contains(&"Content-Type: text/event-stream".to_string()), "{lines:?}");
+            assert_eq!(r#"data: {"temperature":2400}"#, lines.last().unwrap());
+
+            emulator.remove_device("ZQH".parse().unwrap()).await;
+            let ended = tokio::time::timeout(Duration::from_secs(2), async { while events.next_line().await.unwrap().is_some() {} }).await;
+            assert!(ended.is_ok(), "the event stream does not end with the device");
+        });
+    }
+}
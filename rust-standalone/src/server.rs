@@ -0,0 +1,81 @@
+//! Building blocks for the device side of the protocol, to write emulated devices and test fixtures.
+//!
+//! Every device comes with a handler trait, e.g. `temperature_v_2::TemperatureV2Handler`, with one function per
+//! function of the device. The `dispatch` function of the device module decodes a request, calls the handler and
+//! encodes its response:
+//! ```
+//! use tinkerforge_async::{
+//!     base58::Uid,
+//!     byte_converter::FromByteSlice,
+//!     converting_receiver::BrickletError,
+//!     server::CallbackEmitter,
+//!     temperature_v_2::{dispatch, TemperatureV2CallbackEmitter, TemperatureV2Handler},
+//! };
+//!
+//! struct Thermometer(i16);
+//!
+//! impl TemperatureV2Handler for Thermometer {
+//!     async fn get_temperature(&mut self) -> Result<i16, BrickletError> {
+//!         Ok(self.0)
+//!     }
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut thermometer = Thermometer(2150);
+//! let response = dispatch(&mut thermometer, 1, &[]).await.unwrap();
+//! assert_eq!(i16::from_le_byte_slice(&response), 2150);
+//! assert!(matches!(dispatch(&mut thermometer, 3, &[]).await, Err(BrickletError::FunctionNotSupported)));
+//!
+//! let (emitter, mut callbacks) = CallbackEmitter::new(Uid::from(42));
+//! TemperatureV2CallbackEmitter::new(emitter).temperature(2200);
+//! assert_eq!(callbacks.recv().await.unwrap().function_id, 4);
+//! # });
+//! ```
+use tokio::sync::broadcast;
+
+use crate::base58::Uid;
+
+const CALLBACK_CAPACITY: usize = 512;
+
+/// A callback sent by an emulated device.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CallbackPacket {
+    pub uid: Uid,
+    pub function_id: u8,
+    /// Encoded values of the callback.
+    pub payload: Vec<u8>,
+}
+
+/// Sends the callbacks of one emulated device to everyone subscribed, e.g. the connections of an emulated Brick Daemon.
+#[derive(Clone, Debug)]
+pub struct CallbackEmitter {
+    uid: Uid,
+    sender: broadcast::Sender<CallbackPacket>,
+}
+
+impl CallbackEmitter {
+    /// Creates an emitter for the device with the given uid and a first receiver of its callbacks.
+    pub fn new(uid: Uid) -> (CallbackEmitter, broadcast::Receiver<CallbackPacket>) {
+        let (sender, receiver) = broadcast::channel(CALLBACK_CAPACITY);
+        (CallbackEmitter { uid, sender }, receiver)
+    }
+
+    /// Creates an emitter which sends to an existing channel, to collect the callbacks of several devices.
+    pub fn with_sender(uid: Uid, sender: broadcast::Sender<CallbackPacket>) -> CallbackEmitter {
+        CallbackEmitter { uid, sender }
+    }
+
+    pub fn uid(&self) -> Uid {
+        self.uid
+    }
+
+    /// Adds a receiver for the callbacks sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<CallbackPacket> {
+        self.sender.subscribe()
+    }
+
+    /// Sends an encoded callback, returns the number of receivers it was sent to.
+    pub fn emit(&self, function_id: u8, payload: Vec<u8>) -> usize {
+        self.sender.send(CallbackPacket { uid: self.uid, function_id, payload }).unwrap_or(0)
+    }
+}
@@ -0,0 +1,234 @@
+//! Instrumentation with [`tracing`](https://docs.rs/tracing), enabled by the `tracing` feature.
+//!
+//! Every request runs in a `request` span at debug level with the fields `uid`, `device_type`, `function`,
+//! `function_id`, `sequence_number`, `latency_ms` and `outcome`. The span is started by the caller, which knows the
+//! device and function, the connection records the sequence number, latency and outcome in it. The outcome is `ok`, the
+//! error code of the response like `function_not_supported`, `timeout` or `error`.
+//!
+//! Callbacks are `callback` events at trace level, opening and closing a connection `connected` and `disconnected`
+//! events at info level. Without the feature, all of this compiles to nothing.
+
+use std::time::Instant;
+
+pub(crate) use self::tracing_support::*;
+use crate::{error::TinkerforgeError, ip_connection::async_io::PacketData};
+
+/// Outcome of a request as recorded in its span.
+fn outcome(response: Result<Option<&PacketData>, &TinkerforgeError>) -> &'static str {
+    match response {
+        Ok(None) => "ok",
+        Ok(Some(packet)) => match packet.header().error_code {
+            0 => "ok",
+            1 => "invalid_parameter",
+            2 => "function_not_supported",
+            _ => "unknown_error",
+        },
+        Err(TinkerforgeError::NoResponseReceived) => "timeout",
+        Err(_) => "error",
+    }
+}
+
+/// Records the latency and outcome of a request in its span.
+pub(crate) fn request_finished(response: Result<Option<&PacketData>, &TinkerforgeError>, started: Instant) {
+    record_finished(outcome(response), started);
+}
+
+#[cfg(feature = "tracing")]
+mod tracing_support {
+    use std::{future::Future, time::Instant};
+
+    use tracing::{field::Empty, Instrument, Span};
+
+    tokio::task_local! {
+        /// Span of the request running in the current task.
+        static REQUEST: Span;
+    }
+
+    use crate::{
+        base58::Uid,
+        ip_connection::PacketHeader,
+        metadata::{devices, DeviceMetadata},
+    };
+
+    /// Span of one request, entered while the request runs.
+    pub(crate) struct RequestSpan(Span);
+
+    impl RequestSpan {
+        pub(crate) fn new(uid: Uid, function_id: u8, device_type: Option<&str>, function: Option<&str>) -> Self {
+            Self(tracing::debug_span!(
+                "request",
+                %uid,
+                device_type,
+                function,
+                function_id,
+                sequence_number = Empty,
+                latency_ms = Empty,
+                outcome = Empty,
+            ))
+        }
+        /// Span of a request to a device of a known type, the function is looked up in its metadata.
+        pub(crate) fn for_device(uid: Uid, function_id: u8, device: Option<&'static DeviceMetadata>) -> Self {
+            let function = device.and_then(|device| device.packet(function_id)).map(|packet| packet.name);
+            Self::new(uid, function_id, device.map(|device| device.name), function)
+        }
+        /// Span of a request to a device, looking up its type by the name used in [`DeviceMetadata::name`].
+        pub(crate) fn for_device_name(uid: Uid, function_id: u8, device_type: &str) -> Self {
+            Self::for_device(uid, function_id, devices().iter().copied().find(|device| device.name == device_type))
+        }
+        pub(crate) async fn run<F: Future>(self, future: F) -> F::Output {
+            REQUEST.scope(self.0.clone(), future.instrument(self.0)).await
+        }
+    }
+
+    /// Records the sequence number of the request sent in its span.
+    pub(crate) fn request_sent(sequence_number: u8) {
+        let _ = REQUEST.try_with(|span| {
+            span.record("sequence_number", sequence_number);
+        });
+    }
+
+    pub(super) fn record_finished(outcome: &'static str, started: Instant) {
+        let _ = REQUEST.try_with(|span| {
+            span.record("latency_ms", started.elapsed().as_secs_f64() * 1000.0);
+            span.record("outcome", outcome);
+        });
+    }
+
+    pub(crate) fn callback_received(header: &PacketHeader) {
+        tracing::trace!(uid = %header.uid, function_id = header.function_id, length = header.length, "callback");
+    }
+
+    pub(crate) fn connected(peer: &str) {
+        tracing::info!(peer, "connected");
+    }
+
+    pub(crate) fn disconnected(peer: &str, reason: &str) {
+        tracing::info!(peer, reason, "disconnected");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod tracing_support {
+    use std::{future::Future, time::Instant};
+
+    use crate::{base58::Uid, ip_connection::PacketHeader, metadata::DeviceMetadata};
+
+    pub(crate) struct RequestSpan;
+
+    impl RequestSpan {
+        pub(crate) fn new(_uid: Uid, _function_id: u8, _device_type: Option<&str>, _function: Option<&str>) -> Self {
+            Self
+        }
+        #[cfg_attr(not(feature = "proxy"), allow(dead_code))]
+        pub(crate) fn for_device(_uid: Uid, _function_id: u8, _device: Option<&'static DeviceMetadata>) -> Self {
+            Self
+        }
+        pub(crate) fn for_device_name(_uid: Uid, _function_id: u8, _device_type: &str) -> Self {
+            Self
+        }
+        pub(crate) async fn run<F: Future>(self, future: F) -> F::Output {
+            future.await
+        }
+    }
+
+    pub(crate) fn request_sent(_sequence_number: u8) {}
+
+    pub(super) fn record_finished(_outcome: &'static str, _started: Instant) {}
+
+    pub(crate) fn callback_received(_header: &PacketHeader) {}
+
+    pub(crate) fn connected(_peer: &str) {}
+
+    pub(crate) fn disconnected(_peer: &str, _reason: &str) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use crate::{ip_connection::async_io::AsyncIpConnection, metrics::Metrics, temperature_v_2::TemperatureV2Bricklet};
+
+    type SpanFields = (&'static Metadata<'static>, BTreeMap<String, String>);
+
+    /// Keeps the fields of all spans.
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Vec<SpanFields>>>);
+
+    struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+    impl Visit for Fields<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            self.0.insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = BTreeMap::new();
+            span.record(&mut Fields(&mut fields));
+            let mut spans = self.0.lock().unwrap();
+            spans.push((span.metadata(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            values.record(&mut Fields(&mut self.0.lock().unwrap()[span.into_u64() as usize - 1].1));
+        }
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+        fn event(&self, _event: &Event<'_>) {}
+        fn enter(&self, _span: &Id) {}
+        fn exit(&self, _span: &Id) {}
+    }
+
+    #[test]
+    fn test_request_span() {
+        let spans = Spans::default();
+        let _guard = tracing::subscriber::set_default(spans.clone());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let (client, mut server) = tokio::io::duplex(1024);
+            let connection = AsyncIpConnection::from_stream(client, "test".to_string(), Metrics::default());
+            tokio::spawn(async move {
+                let mut request = [0; 8];
+                server.read_exact(&mut request).await.unwrap();
+                let mut response = request.to_vec();
+                response[4] = 10;
+                response.extend_from_slice(&2312i16.to_le_bytes());
+                server.write_all(&response).await.unwrap();
+                // the second request is never answered
+                server.read_exact(&mut request).await.unwrap();
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            });
+            let mut bricklet = TemperatureV2Bricklet::new("ZQH".parse().unwrap(), connection);
+            assert_eq!(2312, bricklet.get_temperature().await.unwrap());
+        });
+
+        let spans = spans.0.lock().unwrap();
+        let (metadata, fields) = spans.iter().find(|(metadata, _)| metadata.name() == "request").unwrap();
+        assert_eq!(tracing::Level::DEBUG, *metadata.level());
+        assert_eq!("ZQH", fields["uid"]);
+        assert_eq!("Temperature V2", fields["device_type"]);
+        assert_eq!("get_temperature", fields["function"]);
+        assert_eq!("1", fields["function_id"]);
+        assert_eq!("2", fields["sequence_number"]);
+        assert_eq!("ok", fields["outcome"]);
+        assert!(fields.contains_key("latency_ms"));
+    }
+}
@@ -0,0 +1,823 @@
+//! Terminal user interface to browse the devices of a Brick Daemon, for headless gateways reached over SSH.
+//!
+//! The left pane shows the topology of the devices as reported by enumerations. The right pane shows identity and
+//! firmware of the selected device, its measured values polled every second (see
+//! [`DeviceMetadata::primary_values`]), its functions and callbacks, and below them the responses and callbacks
+//! received.
+//!
+//! `Tab` moves between devices, functions and callbacks, `↑` and `↓` select. `Enter` on a function opens a form with
+//! a field per argument, `Enter` in the form calls the function and `Esc` closes it. Arrays are entered separated by
+//! commas, constants by their name. `Enter` on a callback starts or stops tailing it. `q` quits.
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io,
+    sync::Arc,
+    time::Duration,
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction as LayoutDirection, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use serde_json::{Map, Value};
+use tokio::{sync::mpsc, task::AbortHandle};
+use tokio_stream::StreamExt;
+
+use crate::{
+    base58::Uid,
+    bindings::DeviceIdentifier,
+    dynamic::{DeviceDefinition, DynamicDevice, ElementDefinition},
+    error::TinkerforgeError,
+    ip_connection::{async_io::AsyncIpConnection, EnumerateResponse, EnumerationType},
+    metadata::{DeviceMetadata, Direction, ElementType, PacketKind, PacketMetadata},
+};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Lines kept in the output pane.
+const OUTPUT_LINES: usize = 200;
+
+/// Connects to a Brick Daemon and shows the browser in the terminal until `q` is pressed.
+pub async fn run(brick_daemon: String, secret: Option<String>) -> Result<(), TinkerforgeError> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let connection_task = tokio::spawn(maintain_connection(brick_daemon.clone(), secret, sender.clone())).abort_handle();
+    let input = sender.clone();
+    std::thread::spawn(move || read_input(input));
+    let result = async {
+        let _screen = AlternateScreen::enter()?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        let mut browser = Browser::new(brick_daemon, sender);
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        while !browser.quit {
+            terminal.draw(|frame| browser.draw(frame))?;
+            tokio::select! {
+                Some(message) = receiver.recv() => browser.handle(message),
+                _ = poll.tick() => browser.poll_values(),
+            }
+        }
+        Ok(())
+    }
+    .await;
+    connection_task.abort();
+    result
+}
+
+/// Raw mode on the alternate screen, restored when dropped.
+struct AlternateScreen;
+
+impl AlternateScreen {
+    fn enter() -> io::Result<AlternateScreen> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(AlternateScreen)
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Forwards terminal events until the browser is closed.
+fn read_input(messages: mpsc::UnboundedSender<Message>) {
+    while !messages.is_closed() {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => match event::read() {
+                Ok(event) => {
+                    let _ = messages.send(Message::Input(event));
+                }
+                Err(_) => return,
+            },
+            Ok(false) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Connects to the Brick Daemon and forwards its enumerations, reconnecting when the connection fails.
+async fn maintain_connection(address: String, secret: Option<String>, messages: mpsc::UnboundedSender<Message>) {
+    loop {
+        let result = async {
+            let mut connection = AsyncIpConnection::new(address.clone()).await?;
+            if let Some(secret) = &secret {
+                connection.authenticate(secret).await?;
+            }
+            let enumerations = connection.enumerate().await?;
+            Ok::<_, TinkerforgeError>((connection, enumerations))
+        }
+        .await;
+        let (connection, mut enumerations) = match result {
+            Ok(connected) => connected,
+            Err(error) => {
+                if messages.send(Message::Disconnected(format!("Cannot connect to {address}: {error}"))).is_err() {
+                    return;
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        if messages.send(Message::Connected(connection)).is_err() {
+            return;
+        }
+        while let Some(enumeration) = enumerations.next().await {
+            if messages.send(Message::Enumeration(enumeration)).is_err() {
+                return;
+            }
+        }
+        if messages.send(Message::Disconnected(format!("Lost connection to {address}"))).is_err() {
+            return;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+enum Message {
+    Input(Event),
+    Connected(AsyncIpConnection),
+    Disconnected(String),
+    Enumeration(EnumerateResponse),
+    /// Formatted measured values of a device.
+    Values(Uid, Vec<String>),
+    Output(String),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Focus {
+    Devices,
+    Functions,
+    Callbacks,
+}
+
+/// Arguments of a function being entered.
+struct Form {
+    function: &'static str,
+    /// Request elements of the function with the text entered.
+    fields: Vec<(ElementDefinition, String)>,
+    selected: usize,
+}
+
+struct Browser {
+    brick_daemon: String,
+    status: String,
+    connection: Option<AsyncIpConnection>,
+    devices: BTreeMap<Uid, EnumerateResponse>,
+    /// Uid of the selected device.
+    selected: Option<Uid>,
+    focus: Focus,
+    function: usize,
+    callback: usize,
+    form: Option<Form>,
+    values: Vec<String>,
+    polling: bool,
+    output: VecDeque<String>,
+    tails: HashMap<(Uid, &'static str), AbortHandle>,
+    definitions: BTreeMap<DeviceIdentifier, Arc<DeviceDefinition>>,
+    messages: mpsc::UnboundedSender<Message>,
+    quit: bool,
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        self.stop_tails();
+    }
+}
+
+fn uid_text(uid: Uid) -> String {
+    if u32::from(uid) == 0 {
+        "0".to_string()
+    } else {
+        uid.to_string()
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    name.to_lowercase().replace([' ', '-'], "_")
+}
+
+fn metadata(enumeration: &EnumerateResponse) -> Option<&'static DeviceMetadata> {
+    enumeration.device_identifier.parsed().map(|device| device.metadata())
+}
+
+fn device_name(enumeration: &EnumerateResponse) -> String {
+    match metadata(enumeration) {
+        Some(device) => format!("{} {}", device.display_name, device.category),
+        None => format!("Unknown device {}", enumeration.device_identifier.raw()),
+    }
+}
+
+/// Formats a value of a response or callback, arrays separated by commas.
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(values) => values.iter().map(format_value).collect::<Vec<_>>().join(","),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Formats the elements of a response or callback like `temperature=2312 option=x`.
+fn format_response(response: &Value) -> String {
+    match response {
+        Value::Object(elements) => {
+            elements.iter().map(|(name, value)| format!("{name}={}", format_value(value))).collect::<Vec<_>>().join(" ")
+        }
+        Value::Null => "ok".to_string(),
+        value => format_value(value),
+    }
+}
+
+/// Converts the text of a form field into an argument, arrays are separated by commas and constants given by name.
+fn input_value(element: &ElementDefinition, text: &str) -> Value {
+    let scalar = |item: &str| {
+        let item = item.trim();
+        match element.element_type {
+            ElementType::Char => Value::from(item),
+            _ => serde_json::from_str(item).unwrap_or_else(|_| Value::from(item)),
+        }
+    };
+    match element.element_type {
+        ElementType::String => Value::from(text),
+        _ if element.cardinality > 1 => {
+            Value::Array(text.trim().trim_start_matches('[').trim_end_matches(']').split(',').map(scalar).collect())
+        }
+        _ => scalar(text),
+    }
+}
+
+/// Describes the values accepted for an element, like `u8, one of off, on, show_heartbeat`.
+fn element_hint(element: &ElementDefinition) -> String {
+    let mut hint = format!("{:?}", element.element_type).to_lowercase();
+    if element.cardinality > 1 {
+        hint.push_str(&format!("[{}]", element.cardinality));
+    }
+    let constants = element.extra.iter().filter_map(|extra| extra.constant_group.as_ref()).flat_map(|group| &group.constants);
+    let constants = constants.map(|constant| snake_case(&constant.name)).collect::<Vec<_>>();
+    if !constants.is_empty() {
+        hint.push_str(&format!(", one of {}", constants.join(", ")));
+    }
+    hint
+}
+
+impl Browser {
+    fn new(brick_daemon: String, messages: mpsc::UnboundedSender<Message>) -> Browser {
+        Browser {
+            status: format!("Connecting to {brick_daemon}"),
+            brick_daemon,
+            connection: None,
+            devices: BTreeMap::new(),
+            selected: None,
+            focus: Focus::Devices,
+            function: 0,
+            callback: 0,
+            form: None,
+            values: Vec::new(),
+            polling: false,
+            output: VecDeque::new(),
+            tails: HashMap::new(),
+            definitions: BTreeMap::new(),
+            messages,
+            quit: false,
+        }
+    }
+
+    /// Devices in depth first order of the topology with their depth, devices connected to an unknown device are roots.
+    fn topology(&self) -> Vec<(usize, &EnumerateResponse)> {
+        let mut children = BTreeMap::<Uid, Vec<&EnumerateResponse>>::new();
+        for device in self.devices.values() {
+            let parent = if self.devices.contains_key(&device.connected_uid) && device.connected_uid != device.uid {
+                device.connected_uid
+            } else {
+                Uid::zero()
+            };
+            children.entry(parent).or_default().push(device);
+        }
+        for devices in children.values_mut() {
+            devices.sort_by_key(|device| (device.position, device.uid));
+        }
+        let mut topology = Vec::with_capacity(self.devices.len());
+        let mut pending = children.get(&Uid::zero()).into_iter().flatten().rev().map(|device| (0, *device)).collect::<Vec<_>>();
+        while let Some((depth, device)) = pending.pop() {
+            topology.push((depth, device));
+            // a device listing itself as ancestor would loop forever
+            if topology.len() <= self.devices.len() {
+                pending.extend(children.get(&device.uid).into_iter().flatten().rev().map(|child| (depth + 1, *child)));
+            }
+        }
+        topology
+    }
+
+    fn device(&self) -> Option<(&EnumerateResponse, &'static DeviceMetadata)> {
+        let enumeration = self.devices.get(&self.selected?)?;
+        Some((enumeration, metadata(enumeration)?))
+    }
+
+    fn packets(&self, kind: PacketKind) -> Vec<&'static PacketMetadata> {
+        self.device().map(|(_, device)| device.packets.iter().filter(|packet| packet.kind == kind).collect()).unwrap_or_default()
+    }
+
+    fn definition(&mut self, device: &'static DeviceMetadata) -> Arc<DeviceDefinition> {
+        self.definitions.entry(device.identifier).or_insert_with(|| Arc::new(DeviceDefinition::from(device))).clone()
+    }
+
+    /// The selected device as [`DynamicDevice`], if connected.
+    fn dynamic_device(&mut self) -> Option<DynamicDevice> {
+        let (enumeration, device) = self.device()?;
+        let (uid, connection) = (enumeration.uid, self.connection.clone()?);
+        Some(DynamicDevice::new(uid, connection, self.definition(device)))
+    }
+
+    fn print(&mut self, line: String) {
+        if self.output.len() == OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back(line);
+    }
+
+    fn stop_tails(&mut self) {
+        for (_, tail) in self.tails.drain() {
+            tail.abort();
+        }
+    }
+
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::Input(Event::Key(key)) if key.kind != KeyEventKind::Release => self.handle_key(key),
+            Message::Input(_) => {}
+            Message::Connected(connection) => {
+                self.status = format!("Connected to {}", self.brick_daemon);
+                self.connection = Some(connection);
+            }
+            Message::Disconnected(reason) => {
+                self.status = reason;
+                self.connection = None;
+                self.devices.clear();
+                self.values.clear();
+                self.stop_tails();
+            }
+            Message::Enumeration(enumeration) => {
+                if enumeration.enumeration_type == EnumerationType::Disconnected {
+                    self.devices.remove(&enumeration.uid);
+                    self.tails.retain(|(uid, _), tail| {
+                        if *uid == enumeration.uid {
+                            tail.abort();
+                        }
+                        *uid != enumeration.uid
+                    });
+                } else {
+                    self.devices.insert(enumeration.uid, enumeration);
+                }
+                if !self.selected.is_some_and(|uid| self.devices.contains_key(&uid)) {
+                    let first = self.topology().first().map(|(_, device)| device.uid);
+                    self.select(first);
+                }
+            }
+            Message::Values(uid, values) => {
+                self.polling = false;
+                if self.selected == Some(uid) {
+                    self.values = values;
+                }
+            }
+            Message::Output(line) => self.print(line),
+        }
+    }
+
+    fn select(&mut self, uid: Option<Uid>) {
+        if self.selected != uid {
+            self.selected = uid;
+            self.function = 0;
+            self.callback = 0;
+            self.values.clear();
+            self.poll_values();
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        if let Some(form) = &mut self.form {
+            match key.code {
+                KeyCode::Esc => self.form = None,
+                KeyCode::Enter => self.submit(),
+                KeyCode::Up | KeyCode::BackTab => form.selected = form.selected.saturating_sub(1),
+                KeyCode::Down | KeyCode::Tab => form.selected = (form.selected + 1).min(form.fields.len() - 1),
+                KeyCode::Backspace => {
+                    form.fields[form.selected].1.pop();
+                }
+                KeyCode::Char(c) => form.fields[form.selected].1.push(c),
+                _ => {}
+            }
+            return;
+        }
+        let step = |index: usize, count: usize, down: bool| match down {
+            true => (index + 1).min(count.saturating_sub(1)),
+            false => index.saturating_sub(1),
+        };
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab => {
+                let order = [Focus::Devices, Focus::Functions, Focus::Callbacks];
+                let index = order.iter().position(|focus| *focus == self.focus).unwrap_or_default();
+                let offset = if key.code == KeyCode::Tab { 1 } else { order.len() - 1 };
+                self.focus = order[(index + offset) % order.len()];
+            }
+            KeyCode::Up | KeyCode::Down => {
+                let down = key.code == KeyCode::Down;
+                match self.focus {
+                    Focus::Devices => {
+                        let topology = self.topology().iter().map(|(_, device)| device.uid).collect::<Vec<_>>();
+                        let index = topology.iter().position(|uid| Some(*uid) == self.selected).unwrap_or_default();
+                        self.select(topology.get(step(index, topology.len(), down)).copied());
+                    }
+                    Focus::Functions => self.function = step(self.function, self.packets(PacketKind::Function).len(), down),
+                    Focus::Callbacks => self.callback = step(self.callback, self.packets(PacketKind::Callback).len(), down),
+                }
+            }
+            KeyCode::Enter => match self.focus {
+                Focus::Devices => self.focus = Focus::Functions,
+                Focus::Functions => self.open_form(),
+                Focus::Callbacks => self.toggle_tail(),
+            },
+            _ => {}
+        }
+    }
+
+    /// Opens the form of the selected function, functions without arguments are called right away.
+    fn open_form(&mut self) {
+        let Some(function) = self.packets(PacketKind::Function).get(self.function).copied() else {
+            return;
+        };
+        let Some((_, device)) = self.device() else {
+            return;
+        };
+        let definition = self.definition(device);
+        let Some(packet) = definition.packet(PacketKind::Function, function.name) else {
+            self.print(format!("{} cannot be called", function.name));
+            return;
+        };
+        let fields = packet
+            .elements
+            .iter()
+            .filter(|element| element.direction == Direction::In)
+            .map(|element| (element.clone(), String::new()))
+            .collect::<Vec<_>>();
+        self.form = Some(Form { function: function.name, fields, selected: 0 });
+        if self.form.as_ref().is_some_and(|form| form.fields.is_empty()) {
+            self.submit();
+        }
+    }
+
+    /// Calls the function of the form with the arguments entered.
+    fn submit(&mut self) {
+        let Some(form) = self.form.take() else {
+            return;
+        };
+        let Some(mut device) = self.dynamic_device() else {
+            self.print(format!("{}: not connected", form.function));
+            return;
+        };
+        let arguments =
+            form.fields.iter().map(|(element, text)| (snake_case(&element.name), input_value(element, text))).collect::<Map<_, _>>();
+        let messages = self.messages.clone();
+        tokio::spawn(async move {
+            let line = match device.call(form.function, Value::Object(arguments)).await {
+                Ok(response) => format!("{} {}: {}", uid_text(device.uid()), form.function, format_response(&response)),
+                Err(error) => format!("{} {} failed: {error}", uid_text(device.uid()), form.function),
+            };
+            let _ = messages.send(Message::Output(line));
+        });
+    }
+
+    /// Starts or stops printing the selected callback whenever it is received.
+    fn toggle_tail(&mut self) {
+        let Some(callback) = self.packets(PacketKind::Callback).get(self.callback).copied() else {
+            return;
+        };
+        let Some(mut device) = self.dynamic_device() else {
+            return;
+        };
+        let key = (device.uid(), callback.name);
+        if let Some(tail) = self.tails.remove(&key) {
+            tail.abort();
+            self.print(format!("{} {}: stopped", uid_text(key.0), callback.name));
+            return;
+        }
+        let messages = self.messages.clone();
+        let tail = tokio::spawn(async move {
+            let prefix = format!("{} {}", uid_text(device.uid()), callback.name);
+            match device.callback_stream(callback.name).await {
+                Ok(stream) => {
+                    tokio::pin!(stream);
+                    while let Some(values) = stream.next().await {
+                        if messages.send(Message::Output(format!("{prefix}: {}", format_response(&values)))).is_err() {
+                            break;
+                        }
+                    }
+                }
+                Err(error) => {
+                    let _ = messages.send(Message::Output(format!("{prefix} failed: {error}")));
+                }
+            }
+        });
+        self.tails.insert(key, tail.abort_handle());
+        self.print(format!("{} {}: waiting for callbacks, configure them with the functions", uid_text(key.0), callback.name));
+    }
+
+    /// Reads the measured values of the selected device, unless the last reading is still pending.
+    fn poll_values(&mut self) {
+        if self.polling {
+            return;
+        }
+        let Some((_, device)) = self.device() else {
+            return;
+        };
+        let Some(mut dynamic) = self.dynamic_device() else {
+            return;
+        };
+        let getters = device.primary_values().collect::<Vec<_>>();
+        if getters.is_empty() {
+            return;
+        }
+        self.polling = true;
+        let messages = self.messages.clone();
+        tokio::spawn(async move {
+            let mut values = Vec::with_capacity(getters.len());
+            for (getter, element) in getters {
+                let value = match dynamic.call(getter.name, Value::Object(Map::new())).await {
+                    Ok(response) => match (response[element.name].as_f64(), element.si_unit) {
+                        (Some(value), Some(unit)) => format!("{} {}", value * unit.factor, unit.symbol),
+                        _ => format_value(&response[element.name]),
+                    },
+                    Err(error) => error.to_string(),
+                };
+                values.push(format!("{}: {value}", element.name));
+            }
+            let _ = messages.send(Message::Values(dynamic.uid(), values));
+        });
+    }
+
+    fn block(&self, title: &str, focus: Option<Focus>) -> Block<'static> {
+        let style = if focus == Some(self.focus) && self.form.is_none() { Style::default().fg(Color::Yellow) } else { Style::default() };
+        Block::default().borders(Borders::ALL).border_style(style).title(title.to_string())
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status] = split(frame.size(), LayoutDirection::Vertical, [Constraint::Min(0), Constraint::Length(1)]);
+        let [devices, details] = split(main, LayoutDirection::Horizontal, [Constraint::Percentage(35), Constraint::Percentage(65)]);
+        let value_lines = self.values.len().max(1) as u16 + 2;
+        let [identity, values, packets, output] = split(
+            details,
+            LayoutDirection::Vertical,
+            [Constraint::Length(6), Constraint::Length(value_lines), Constraint::Min(5), Constraint::Length(10)],
+        );
+        let [functions, callbacks] = split(packets, LayoutDirection::Horizontal, [Constraint::Percentage(60), Constraint::Percentage(40)]);
+
+        let topology = self.topology();
+        let items = topology.iter().map(|(depth, device)| {
+            let indent = if *depth == 0 { String::new() } else { format!("{}└ ", "  ".repeat(depth - 1)) };
+            ListItem::new(format!("{indent}{}: {} ({})", device.position, device_name(device), uid_text(device.uid)))
+        });
+        let selected = topology.iter().position(|(_, device)| Some(device.uid) == self.selected);
+        self.render_list(frame, devices, items.collect(), selected, self.block("Devices", Some(Focus::Devices)));
+
+        let identity_lines = match self.device() {
+            Some((enumeration, _)) => vec![
+                Line::from(format!("{} ({})", device_name(enumeration), enumeration.device_identifier.raw())),
+                Line::from(format!(
+                    "UID {}, connected to {} at {}",
+                    uid_text(enumeration.uid),
+                    uid_text(enumeration.connected_uid),
+                    enumeration.position
+                )),
+                Line::from(format!("Hardware {}, firmware {}", enumeration.hardware_version, enumeration.firmware_version)),
+                Line::from(format!("Enumeration {}", format!("{:?}", enumeration.enumeration_type).to_lowercase())),
+            ],
+            None => vec![Line::from("No device selected")],
+        };
+        frame.render_widget(Paragraph::new(identity_lines).block(self.block("Identity", None)), identity);
+        let value_lines = self.values.iter().map(|value| Line::from(value.as_str())).collect::<Vec<_>>();
+        frame.render_widget(Paragraph::new(value_lines).block(self.block("Values", None)), values);
+
+        let functions_list = self.packets(PacketKind::Function).iter().map(|packet| ListItem::new(packet.name)).collect();
+        self.render_list(frame, functions, functions_list, Some(self.function), self.block("Functions", Some(Focus::Functions)));
+        let callbacks_list = self
+            .packets(PacketKind::Callback)
+            .iter()
+            .map(|packet| {
+                let tailed = self.selected.is_some_and(|uid| self.tails.contains_key(&(uid, packet.name)));
+                ListItem::new(if tailed { format!("{} (tailing)", packet.name) } else { packet.name.to_string() })
+            })
+            .collect();
+        self.render_list(frame, callbacks, callbacks_list, Some(self.callback), self.block("Callbacks", Some(Focus::Callbacks)));
+
+        let visible = usize::from(output.height.saturating_sub(2));
+        let output_lines = self.output.iter().skip(self.output.len().saturating_sub(visible)).map(|line| Line::from(line.as_str()));
+        frame.render_widget(Paragraph::new(output_lines.collect::<Vec<_>>()).block(self.block("Output", None)), output);
+        let help = "Tab: switch pane  ↑↓: select  Enter: call function / tail callback  q: quit";
+        frame.render_widget(Paragraph::new(format!("{}  |  {help}", self.status)), status);
+
+        if let Some(form) = &self.form {
+            self.draw_form(frame, form, packets.union(output));
+        }
+    }
+
+    fn render_list(&self, frame: &mut Frame, area: Rect, items: Vec<ListItem>, selected: Option<usize>, block: Block) {
+        let list = List::new(items).block(block).highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut ListState::default().with_selected(selected));
+    }
+
+    fn draw_form(&self, frame: &mut Frame, form: &Form, area: Rect) {
+        let mut lines = Vec::with_capacity(form.fields.len() * 2 + 2);
+        for (index, (element, text)) in form.fields.iter().enumerate() {
+            let marker = if index == form.selected { ">" } else { " " };
+            lines.push(Line::from(format!("{marker} {} ({})", snake_case(&element.name), element_hint(element))));
+            lines.push(Line::from(format!("    {text}{}", if index == form.selected { "_" } else { "" })));
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("Enter: call  Esc: cancel  ↑↓: select argument"));
+        let block = Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::Yellow)).title(form.function);
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+fn split<const N: usize>(area: Rect, direction: LayoutDirection, constraints: [Constraint; N]) -> [Rect; N] {
+    let areas = Layout::default().direction(direction).constraints(constraints).split(area);
+    std::array::from_fn(|index| areas[index])
+}
+
+#[cfg(test)]
+mod test {
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::{backend::TestBackend, Terminal};
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        base58::Uid,
+        bindings::DeviceIdentifier,
+        byte_converter::ParsedOrRaw,
+        dynamic::DeviceDefinition,
+        ip_connection::{EnumerateResponse, EnumerationType, Version},
+        metadata::{self, PacketKind},
+        tui::{input_value, Browser, Focus, Message},
+    };
+
+    fn enumeration(uid: &str, connected_uid: Option<&str>, position: char, device: DeviceIdentifier) -> Message {
+        Message::Enumeration(EnumerateResponse {
+            uid: uid.parse().unwrap(),
+            connected_uid: connected_uid.map_or(Uid::zero(), |uid| uid.parse().unwrap()),
+            position,
+            hardware_version: Version::new(1, 1, 0),
+            firmware_version: Version::new(2, 0, 3),
+            device_identifier: ParsedOrRaw::Parsed(device),
+            enumeration_type: EnumerationType::Available,
+        })
+    }
+
+    fn key(code: KeyCode) -> Message {
+        Message::Input(Event::Key(KeyEvent::new(code, KeyModifiers::NONE)))
+    }
+
+    fn screen(browser: &Browser) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(140, 40)).unwrap();
+        terminal.draw(|frame| browser.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let row = |y| (0..buffer.area.width).map(|x| buffer.get(x, y).symbol()).collect::<String>();
+        (0..buffer.area.height).map(row).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn test_browse() {
+        let mut browser = Browser::new("localhost:4223".to_string(), mpsc::unbounded_channel().0);
+        browser.handle(enumeration("ZQH", Some("6a3"), 'a', DeviceIdentifier::TemperatureV2Bricklet));
+        browser.handle(enumeration("6a3", None, '0', DeviceIdentifier::MasterBrick));
+        let topology = browser.topology().iter().map(|(depth, device)| (*depth, device.uid.to_string())).collect::<Vec<_>>();
+        assert_eq!(topology, vec![(0, "6a3".to_string()), (1, "ZQH".to_string())]);
+
+        browser.handle(key(KeyCode::Down));
+        let screen_text = screen(&browser);
+        assert!(screen_text.contains("└ a: Temperature 2.0 Bricklet (ZQH)"), "{screen_text}");
+        assert!(screen_text.contains("UID ZQH, connected to 6a3 at a"), "{screen_text}");
+        assert!(screen_text.contains("Hardware 1.1.0, firmware 2.0.3"), "{screen_text}");
+
+        browser.handle(key(KeyCode::Tab));
+        assert_eq!(browser.focus, Focus::Functions);
+        browser.function = browser.packets(PacketKind::Function).iter().position(|packet| packet.name == "set_status_led_config").unwrap();
+        browser.handle(key(KeyCode::Enter));
+        for c in "on".chars() {
+            browser.handle(key(KeyCode::Char(c)));
+        }
+        let screen_text = screen(&browser);
+        assert!(screen_text.contains("> config (u8, one of off, on, show_heartbeat, show_status)"), "{screen_text}");
+        assert!(screen_text.contains("    on_"), "{screen_text}");
+        browser.handle(key(KeyCode::Esc));
+        assert!(browser.form.is_none());
+
+        browser.handle(Message::Enumeration(EnumerateResponse {
+            enumeration_type: EnumerationType::Disconnected,
+            ..match enumeration("ZQH", None, 'a', DeviceIdentifier::TemperatureV2Bricklet) {
+                Message::Enumeration(enumeration) => enumeration,
+                _ => unreachable!(),
+            }
+        }));
+        assert_eq!(browser.selected, Some("6a3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_input_value() {
+        let definition = DeviceDefinition::from(metadata::device(DeviceIdentifier::TemperatureV2Bricklet));
+        let elements = &definition.packet(PacketKind::Function, "set_temperature_callback_configuration").unwrap().elements;
+        let element = |name: &str| elements.iter().find(|element| element.name == name).unwrap();
+        assert_eq!(input_value(element("period"), " 1000"), json!(1000));
+        assert_eq!(input_value(element("option"), "x"), json!("x"));
+        assert_eq!(input_value(element("value_has_to_change"), "true"), json!(true));
+        let definition = DeviceDefinition::from(metadata::device(DeviceIdentifier::IndustrialDigitalOut4V2Bricklet));
+        let elements = &definition.packet(PacketKind::Function, "set_value").unwrap().elements;
+        assert_eq!(input_value(&elements[0], "true, false,true,false"), json!([true, false, true, false]));
+    }
+}
+
+#[cfg(all(test, feature = "emulator"))]
+mod emulator_test {
+    use std::time::Duration;
+
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        emulator::{Emulator, Scenario},
+        metadata::PacketKind,
+        tui::{maintain_connection, Browser, Focus, Message},
+    };
+
+    /// Handles messages until the condition holds, returns whether it does within five seconds.
+    async fn wait_for(browser: &mut Browser, receiver: &mut mpsc::UnboundedReceiver<Message>, condition: fn(&Browser) -> bool) -> bool {
+        let wait = async {
+            while !condition(browser) {
+                let message = receiver.recv().await.unwrap();
+                browser.handle(message);
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait).await.is_ok()
+    }
+
+    #[test]
+    fn test_call_and_tail() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let scenario = Scenario::from_toml(
+                r#"
+                [[devices]]
+                uid = "ZQH"
+                device = "TemperatureV2Bricklet"
+                functions = [{ function = "get_temperature", responses = [[2312]] }]
+                callbacks = [{ callback = "temperature", period_ms = 10, values = [[2400]] }]
+                "#,
+            )
+            .unwrap();
+            let emulator = Emulator::bind("127.0.0.1:0", scenario).await.unwrap();
+            let (sender, mut receiver) = mpsc::unbounded_channel();
+            let address = emulator.local_addr().to_string();
+            let connection = tokio::spawn(maintain_connection(address.clone(), None, sender.clone()));
+            let mut browser = Browser::new(address, sender);
+            assert!(wait_for(&mut browser, &mut receiver, |browser| browser.values == ["temperature: 23.12 °C"]).await);
+
+            browser.focus = Focus::Functions;
+            browser.function = browser.packets(PacketKind::Function).iter().position(|packet| packet.name == "get_temperature").unwrap();
+            browser.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+            assert!(
+                wait_for(&mut browser, &mut receiver, |browser| browser
+                    .output
+                    .iter()
+                    .any(|line| line == "ZQH get_temperature: temperature=2312"))
+                .await
+            );
+
+            browser.focus = Focus::Callbacks;
+            browser.callback = browser.packets(PacketKind::Callback).iter().position(|packet| packet.name == "temperature").unwrap();
+            browser.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+            assert!(
+                wait_for(&mut browser, &mut receiver, |browser| browser
+                    .output
+                    .iter()
+                    .any(|line| line == "ZQH temperature: temperature=2400"))
+                .await
+            );
+            browser.handle_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+            assert!(browser.tails.is_empty());
+            connection.abort();
+        });
+    }
+}